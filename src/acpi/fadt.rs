@@ -0,0 +1,128 @@
+//! Fixed ACPI Description Table (FADT)
+//!
+//! Describes the fixed hardware of ACPI: where the power management registers are, which
+//! interrupt the System Control Interrupt (SCI) uses, how to reset the machine and where the
+//! Differentiated System Description Table (DSDT) is. Despite its name the table grew with every
+//! ACPI version, fields of later versions are only read if the table is long enough.
+
+use crate::x86_64::address::PhysicalAddress;
+
+use super::{
+    check_length, read_u16, read_u32, read_u64, AcpiError, GenericAddress, SdtHeader, Signature,
+};
+
+/// Signature of the FADT.
+pub const SIGNATURE: Signature = Signature(*b"FACP");
+
+/// Length of the FADT of ACPI 1.0.
+const ACPI_1_LENGTH: usize = 116;
+const RESET_REGISTER_OFFSET: usize = 116;
+const RESET_VALUE_OFFSET: usize = 128;
+const X_DSDT_OFFSET: usize = 140;
+
+/// Bit in [`Fadt::flags`] that tells that the reset register is supported.
+const RESET_REGISTER_SUPPORTED: u32 = 1 << 10;
+
+/// Bit in [`Fadt::boot_architecture_flags`] telling that the machine has a PS/2 controller.
+pub const BOOT_ARCHITECTURE_8042: u16 = 1 << 1;
+
+/// What kind of machine the firmware says this is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerManagementProfile {
+    Unspecified,
+    Desktop,
+    Mobile,
+    Workstation,
+    EnterpriseServer,
+    SohoServer,
+    AppliancePc,
+    PerformanceServer,
+    Tablet,
+    Reserved(u8),
+}
+
+impl From<u8> for PowerManagementProfile {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => PowerManagementProfile::Unspecified,
+            1 => PowerManagementProfile::Desktop,
+            2 => PowerManagementProfile::Mobile,
+            3 => PowerManagementProfile::Workstation,
+            4 => PowerManagementProfile::EnterpriseServer,
+            5 => PowerManagementProfile::SohoServer,
+            6 => PowerManagementProfile::AppliancePc,
+            7 => PowerManagementProfile::PerformanceServer,
+            8 => PowerManagementProfile::Tablet,
+            other => PowerManagementProfile::Reserved(other),
+        }
+    }
+}
+
+/// The Fixed ACPI Description Table.
+///
+/// The I/O port fields are 0 if the machine does not have the register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fadt {
+    pub header: SdtHeader,
+    /// Physical address of the Firmware ACPI Control Structure (FACS).
+    pub firmware_control: PhysicalAddress,
+    /// Physical address of the DSDT, taken from the 64 bit field if there is one.
+    pub dsdt: PhysicalAddress,
+    pub preferred_profile: PowerManagementProfile,
+    /// The (ISA) interrupt line of the System Control Interrupt.
+    pub sci_interrupt: u16,
+    /// I/O port to write [`Fadt::acpi_enable`] to in order to switch the machine into ACPI mode.
+    pub smi_command_port: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub pm1a_event_block: u32,
+    pub pm1a_control_block: u32,
+    /// I/O port of the power management timer, which counts at 3.579545 MHz.
+    pub pm_timer_block: u32,
+    pub pm_timer_length: u8,
+    /// Index of the century in the CMOS RTC, 0 if it has none.
+    pub century: u8,
+    /// IA-PC boot architecture flags (e.g. [`BOOT_ARCHITECTURE_8042`]).
+    pub boot_architecture_flags: u16,
+    pub flags: u32,
+    /// The register to write [`Fadt::reset_value`] to in order to reset the machine.
+    pub reset_register: Option<GenericAddress>,
+    pub reset_value: u8,
+}
+
+impl Fadt {
+    pub fn parse(bytes: &'static [u8]) -> Result<Fadt, AcpiError> {
+        check_length(bytes, ACPI_1_LENGTH)?;
+
+        let flags = read_u32(bytes, 112);
+        let reset_supported = flags & RESET_REGISTER_SUPPORTED != 0;
+        let reset_register = (reset_supported && bytes.len() > RESET_VALUE_OFFSET)
+            .then(|| GenericAddress::parse(&bytes[RESET_REGISTER_OFFSET..]));
+        let reset_value = bytes.get(RESET_VALUE_OFFSET).copied().unwrap_or(0);
+
+        let mut dsdt = read_u32(bytes, 40) as u64;
+        if bytes.len() >= X_DSDT_OFFSET + 8 && read_u64(bytes, X_DSDT_OFFSET) != 0 {
+            dsdt = read_u64(bytes, X_DSDT_OFFSET);
+        }
+
+        Ok(Fadt {
+            header: SdtHeader::parse(bytes),
+            firmware_control: PhysicalAddress::new(read_u32(bytes, 36) as u64),
+            dsdt: PhysicalAddress::new(dsdt),
+            preferred_profile: bytes[45].into(),
+            sci_interrupt: read_u16(bytes, 46),
+            smi_command_port: read_u32(bytes, 48),
+            acpi_enable: bytes[52],
+            acpi_disable: bytes[53],
+            pm1a_event_block: read_u32(bytes, 56),
+            pm1a_control_block: read_u32(bytes, 64),
+            pm_timer_block: read_u32(bytes, 76),
+            pm_timer_length: bytes[91],
+            century: bytes[108],
+            boot_architecture_flags: read_u16(bytes, 109),
+            flags,
+            reset_register,
+            reset_value,
+        })
+    }
+}
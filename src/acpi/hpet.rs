@@ -0,0 +1,59 @@
+//! High Precision Event Timer Description Table (HPET)
+//!
+//! Tells where the registers of the HPET are and what the timer is capable of. The HPET is a
+//! counter running at a fixed frequency (of at least 10 MHz) along with a few comparators that
+//! raise an interrupt once the counter reaches their value.
+
+use super::{
+    check_length, read_u16, read_u32, AcpiError, GenericAddress, SdtHeader, Signature,
+    GENERIC_ADDRESS_LENGTH, SDT_HEADER_LENGTH,
+};
+
+/// Signature of the HPET table.
+pub const SIGNATURE: Signature = Signature(*b"HPET");
+
+const LENGTH: usize = SDT_HEADER_LENGTH + 20;
+const BASE_ADDRESS_OFFSET: usize = SDT_HEADER_LENGTH + 4;
+const NUMBER_OFFSET: usize = BASE_ADDRESS_OFFSET + GENERIC_ADDRESS_LENGTH;
+
+/// The High Precision Event Timer Description Table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hpet {
+    pub header: SdtHeader,
+    pub hardware_revision: u8,
+    /// Number of comparators of the first timer block.
+    pub comparator_count: u8,
+    /// Whether the main counter is 64 bit wide (otherwise it has 32 bits).
+    pub counter_is_64_bit: bool,
+    /// Whether the HPET can take over the interrupts of the PIT and the RTC.
+    pub legacy_replacement_capable: bool,
+    pub pci_vendor_id: u16,
+    /// Where the registers are, always in system memory.
+    pub base_address: GenericAddress,
+    /// Sequence number of this HPET.
+    pub number: u8,
+    /// The smallest number of counter ticks a periodic comparator can be set to without losing
+    /// interrupts.
+    pub minimum_tick: u16,
+    pub page_protection: u8,
+}
+
+impl Hpet {
+    pub fn parse(bytes: &'static [u8]) -> Result<Hpet, AcpiError> {
+        check_length(bytes, LENGTH)?;
+
+        let event_timer_block_id = read_u32(bytes, SDT_HEADER_LENGTH);
+        Ok(Hpet {
+            header: SdtHeader::parse(bytes),
+            hardware_revision: event_timer_block_id as u8,
+            comparator_count: ((event_timer_block_id >> 8) & 0x1f) as u8 + 1,
+            counter_is_64_bit: event_timer_block_id & (1 << 13) != 0,
+            legacy_replacement_capable: event_timer_block_id & (1 << 15) != 0,
+            pci_vendor_id: (event_timer_block_id >> 16) as u16,
+            base_address: GenericAddress::parse(&bytes[BASE_ADDRESS_OFFSET..]),
+            number: bytes[NUMBER_OFFSET],
+            minimum_tick: read_u16(bytes, NUMBER_OFFSET + 1),
+            page_protection: bytes[NUMBER_OFFSET + 3],
+        })
+    }
+}
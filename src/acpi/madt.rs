@@ -0,0 +1,300 @@
+//! Multiple APIC Description Table (MADT)
+//!
+//! Describes the interrupt controllers of the machine: the local APIC of every processor, the I/O
+//! APICs and how the legacy ISA interrupts are wired to the inputs of the I/O APICs.
+//!
+//! The table consists of a few fixed fields followed by a list of variable length entries, each
+//! starting with its type and length.
+
+use crate::x86_64::address::PhysicalAddress;
+
+use super::{
+    check_length, read_u16, read_u32, read_u64, AcpiError, SdtHeader, Signature, SDT_HEADER_LENGTH,
+};
+
+/// Signature of the MADT.
+pub const SIGNATURE: Signature = Signature(*b"APIC");
+
+/// Bit in the MADT flags that tells that the machine also has the two 8259 PICs.
+const PCAT_COMPAT: u32 = 1;
+/// Length of the header and the fixed fields (local APIC address and flags) in front of the
+/// entries.
+const FIXED_FIELDS_LENGTH: usize = SDT_HEADER_LENGTH + 8;
+
+const ENTRY_LOCAL_APIC: u8 = 0;
+const ENTRY_IO_APIC: u8 = 1;
+const ENTRY_INTERRUPT_SOURCE_OVERRIDE: u8 = 2;
+const ENTRY_LOCAL_APIC_NMI: u8 = 4;
+const ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+
+/// Polarity of an interrupt signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    /// Whatever the bus the interrupt comes from uses (active high for ISA).
+    ConformsToBus,
+    ActiveHigh,
+    ActiveLow,
+}
+
+/// When an interrupt signal counts as raised.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    /// Whatever the bus the interrupt comes from uses (edge for ISA).
+    ConformsToBus,
+    Edge,
+    Level,
+}
+
+/// Decode the `MPS INTI flags` of an entry.
+fn decode_flags(flags: u16) -> (Polarity, TriggerMode) {
+    let polarity = match flags & 0b11 {
+        0b01 => Polarity::ActiveHigh,
+        0b11 => Polarity::ActiveLow,
+        _ => Polarity::ConformsToBus,
+    };
+    let trigger_mode = match (flags >> 2) & 0b11 {
+        0b01 => TriggerMode::Edge,
+        0b11 => TriggerMode::Level,
+        _ => TriggerMode::ConformsToBus,
+    };
+    (polarity, trigger_mode)
+}
+
+/// A processor along with its local APIC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalApicEntry {
+    pub processor_id: u8,
+    pub apic_id: u8,
+    pub flags: u32,
+}
+
+impl LocalApicEntry {
+    /// The processor can be used right away.
+    pub fn is_enabled(&self) -> bool {
+        self.flags & 1 != 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApicEntry {
+    pub id: u8,
+    pub address: PhysicalAddress,
+    /// The global system interrupt of the first input of the I/O APIC.
+    pub global_system_interrupt_base: u32,
+}
+
+/// An ISA interrupt that is not connected to the I/O APIC input with the same number, or does not
+/// use the ISA polarity and trigger mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptSourceOverride {
+    /// Always 0 (ISA).
+    pub bus: u8,
+    /// The ISA interrupt line.
+    pub source: u8,
+    pub global_system_interrupt: u32,
+    pub polarity: Polarity,
+    pub trigger_mode: TriggerMode,
+}
+
+/// The local APIC input(s) the non maskable interrupt is connected to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalApicNmi {
+    /// `0xff` for all processors.
+    pub processor_id: u8,
+    pub polarity: Polarity,
+    pub trigger_mode: TriggerMode,
+    /// The `LINTn` input, 0 or 1.
+    pub local_interrupt: u8,
+}
+
+/// An entry of the MADT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MadtEntry {
+    LocalApic(LocalApicEntry),
+    IoApic(IoApicEntry),
+    InterruptSourceOverride(InterruptSourceOverride),
+    LocalApicNmi(LocalApicNmi),
+    /// The 64 bit address of the local APICs, replacing the one in the fixed part of the table.
+    LocalApicAddressOverride(PhysicalAddress),
+    /// An entry of a type that is not supported (e.g. the x2APIC entries).
+    Unknown {
+        entry_type: u8,
+    },
+}
+
+impl MadtEntry {
+    fn parse(entry_type: u8, bytes: &[u8]) -> MadtEntry {
+        let expected_length = match entry_type {
+            ENTRY_LOCAL_APIC => 8,
+            ENTRY_IO_APIC => 12,
+            ENTRY_INTERRUPT_SOURCE_OVERRIDE => 10,
+            ENTRY_LOCAL_APIC_NMI => 6,
+            ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE => 12,
+            _ => 0,
+        };
+        if bytes.len() < expected_length {
+            return MadtEntry::Unknown { entry_type };
+        }
+
+        match entry_type {
+            ENTRY_LOCAL_APIC => MadtEntry::LocalApic(LocalApicEntry {
+                processor_id: bytes[2],
+                apic_id: bytes[3],
+                flags: read_u32(bytes, 4),
+            }),
+            ENTRY_IO_APIC => MadtEntry::IoApic(IoApicEntry {
+                id: bytes[2],
+                address: PhysicalAddress::new(read_u32(bytes, 4) as u64),
+                global_system_interrupt_base: read_u32(bytes, 8),
+            }),
+            ENTRY_INTERRUPT_SOURCE_OVERRIDE => {
+                let (polarity, trigger_mode) = decode_flags(read_u16(bytes, 8));
+                MadtEntry::InterruptSourceOverride(InterruptSourceOverride {
+                    bus: bytes[2],
+                    source: bytes[3],
+                    global_system_interrupt: read_u32(bytes, 4),
+                    polarity,
+                    trigger_mode,
+                })
+            }
+            ENTRY_LOCAL_APIC_NMI => {
+                let (polarity, trigger_mode) = decode_flags(read_u16(bytes, 3));
+                MadtEntry::LocalApicNmi(LocalApicNmi {
+                    processor_id: bytes[2],
+                    polarity,
+                    trigger_mode,
+                    local_interrupt: bytes[5],
+                })
+            }
+            ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE => {
+                MadtEntry::LocalApicAddressOverride(PhysicalAddress::new(read_u64(bytes, 4)))
+            }
+            entry_type => MadtEntry::Unknown { entry_type },
+        }
+    }
+}
+
+/// The Multiple APIC Description Table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Madt {
+    pub header: SdtHeader,
+    flags: u32,
+    local_apic_address: u32,
+    /// The bytes of all entries.
+    entries: &'static [u8],
+}
+
+impl Madt {
+    /// Parse the fixed fields of the MADT, the entries are parsed on demand by [`Madt::entries`].
+    pub fn parse(bytes: &'static [u8]) -> Result<Madt, AcpiError> {
+        check_length(bytes, FIXED_FIELDS_LENGTH)?;
+        Ok(Madt {
+            header: SdtHeader::parse(bytes),
+            local_apic_address: read_u32(bytes, SDT_HEADER_LENGTH),
+            flags: read_u32(bytes, SDT_HEADER_LENGTH + 4),
+            entries: &bytes[FIXED_FIELDS_LENGTH..],
+        })
+    }
+
+    /// Physical address of the local APIC registers.
+    pub fn local_apic_address(&self) -> PhysicalAddress {
+        self.entries()
+            .find_map(|entry| match entry {
+                MadtEntry::LocalApicAddressOverride(address) => Some(address),
+                _ => None,
+            })
+            .unwrap_or_else(|| PhysicalAddress::new(self.local_apic_address as u64))
+    }
+
+    /// Whether the machine also has the two 8259 PICs, which have to be masked when the APIC is
+    /// used.
+    pub fn has_legacy_pics(&self) -> bool {
+        self.flags & PCAT_COMPAT != 0
+    }
+
+    /// Iterate over the entries of the table.
+    pub fn entries(&self) -> impl Iterator<Item = MadtEntry> {
+        let mut remaining = self.entries;
+        core::iter::from_fn(move || {
+            // Every entry starts with its type and its length (which includes these two bytes).
+            let length = *remaining.get(1)? as usize;
+            if length < 2 || length > remaining.len() {
+                return None;
+            }
+            let (entry, rest) = remaining.split_at(length);
+            remaining = rest;
+            Some(MadtEntry::parse(entry[0], entry))
+        })
+    }
+
+    pub fn local_apics(&self) -> impl Iterator<Item = LocalApicEntry> {
+        self.entries().filter_map(|entry| match entry {
+            MadtEntry::LocalApic(local_apic) => Some(local_apic),
+            _ => None,
+        })
+    }
+
+    pub fn io_apics(&self) -> impl Iterator<Item = IoApicEntry> {
+        self.entries().filter_map(|entry| match entry {
+            MadtEntry::IoApic(io_apic) => Some(io_apic),
+            _ => None,
+        })
+    }
+
+    pub fn interrupt_source_overrides(&self) -> impl Iterator<Item = InterruptSourceOverride> {
+        self.entries().filter_map(|entry| match entry {
+            MadtEntry::InterruptSourceOverride(source_override) => Some(source_override),
+            _ => None,
+        })
+    }
+}
+
+#[test_case]
+fn test_entries_are_parsed_and_overrides_decoded() {
+    use super::test_table;
+
+    #[rustfmt::skip]
+    let content = [
+        // Local APIC address and flags (PCAT_COMPAT)
+        0x00, 0x00, 0xe0, 0xfe, 0x01, 0x00, 0x00, 0x00,
+        // Local APIC: processor 0, APIC ID 0, enabled
+        0, 8, 0, 0, 0x01, 0x00, 0x00, 0x00,
+        // I/O APIC: ID 1 at 0xfec00000, GSI base 0
+        1, 12, 1, 0, 0x00, 0x00, 0xc0, 0xfe, 0x00, 0x00, 0x00, 0x00,
+        // Interrupt source override: ISA IRQ 9 -> GSI 9, active high, level triggered
+        2, 10, 0, 9, 0x09, 0x00, 0x00, 0x00, 0x0d, 0x00,
+        // An entry type that is not supported
+        0x7f, 4, 0, 0,
+    ];
+    let madt = Madt::parse(test_table(b"APIC", &content)).unwrap();
+
+    assert_eq!(madt.local_apic_address(), PhysicalAddress::new(0xfee0_0000));
+    assert!(madt.has_legacy_pics());
+    assert_eq!(madt.entries().count(), 4);
+    assert_eq!(
+        madt.local_apics().next(),
+        Some(LocalApicEntry {
+            processor_id: 0,
+            apic_id: 0,
+            flags: 1
+        })
+    );
+    assert_eq!(
+        madt.io_apics().next().map(|io_apic| io_apic.address),
+        Some(PhysicalAddress::new(0xfec0_0000))
+    );
+    assert_eq!(
+        madt.interrupt_source_overrides().next(),
+        Some(InterruptSourceOverride {
+            bus: 0,
+            source: 9,
+            global_system_interrupt: 9,
+            polarity: Polarity::ActiveHigh,
+            trigger_mode: TriggerMode::Level,
+        })
+    );
+    assert_eq!(
+        madt.entries().last(),
+        Some(MadtEntry::Unknown { entry_type: 0x7f })
+    );
+}
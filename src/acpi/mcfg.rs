@@ -0,0 +1,117 @@
+//! PCI Express Memory Mapped Configuration Space Description Table (MCFG)
+//!
+//! PCI Express makes the configuration space of every device function available as a 4KiB page of
+//! memory mapped I/O. The MCFG lists where these pages are for every range of buses.
+
+use crate::x86_64::address::PhysicalAddress;
+
+use super::{check_length, read_u16, read_u64, AcpiError, SdtHeader, Signature, SDT_HEADER_LENGTH};
+
+/// Signature of the MCFG.
+pub const SIGNATURE: Signature = Signature(*b"MCFG");
+
+/// The entries follow the header and 8 reserved bytes.
+const ENTRIES_OFFSET: usize = SDT_HEADER_LENGTH + 8;
+const ENTRY_LENGTH: usize = 16;
+
+/// Size of the configuration space of a single function.
+const FUNCTION_CONFIGURATION_SIZE: u64 = 4096;
+const FUNCTIONS_PER_DEVICE: u64 = 8;
+const DEVICES_PER_BUS: u64 = 32;
+
+/// The configuration space of the buses `start_bus..=end_bus` of a PCI segment group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct McfgEntry {
+    /// Address of the configuration space of bus 0 (even if the range starts at a later bus).
+    pub base_address: PhysicalAddress,
+    pub segment_group: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+impl McfgEntry {
+    /// Physical address of the configuration space of the function, if the bus is covered by this
+    /// entry.
+    pub fn function_address(&self, bus: u8, device: u8, function: u8) -> Option<PhysicalAddress> {
+        if !(self.start_bus..=self.end_bus).contains(&bus)
+            || device as u64 >= DEVICES_PER_BUS
+            || function as u64 >= FUNCTIONS_PER_DEVICE
+        {
+            return None;
+        }
+
+        let index =
+            (bus as u64 * DEVICES_PER_BUS + device as u64) * FUNCTIONS_PER_DEVICE + function as u64;
+        Some(self.base_address + index * FUNCTION_CONFIGURATION_SIZE)
+    }
+}
+
+/// The PCI Express Memory Mapped Configuration Space Description Table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mcfg {
+    pub header: SdtHeader,
+    /// The bytes of all entries.
+    entries: &'static [u8],
+}
+
+impl Mcfg {
+    pub fn parse(bytes: &'static [u8]) -> Result<Mcfg, AcpiError> {
+        check_length(bytes, ENTRIES_OFFSET)?;
+        Ok(Mcfg {
+            header: SdtHeader::parse(bytes),
+            entries: &bytes[ENTRIES_OFFSET..],
+        })
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = McfgEntry> {
+        self.entries
+            .chunks_exact(ENTRY_LENGTH)
+            .map(|entry| McfgEntry {
+                base_address: PhysicalAddress::new(read_u64(entry, 0)),
+                segment_group: read_u16(entry, 8),
+                start_bus: entry[10],
+                end_bus: entry[11],
+            })
+    }
+
+    /// Physical address of the configuration space of the function in the segment group.
+    pub fn function_address(
+        &self,
+        segment_group: u16,
+        bus: u8,
+        device: u8,
+        function: u8,
+    ) -> Option<PhysicalAddress> {
+        self.entries()
+            .filter(|entry| entry.segment_group == segment_group)
+            .find_map(|entry| entry.function_address(bus, device, function))
+    }
+}
+
+#[test_case]
+fn test_function_addresses_are_computed_per_bus_range() {
+    use super::test_table;
+
+    #[rustfmt::skip]
+    let content = [
+        // reserved
+        0, 0, 0, 0, 0, 0, 0, 0,
+        // base address 0xb0000000, segment group 0, buses 0 to 0x3f
+        0x00, 0x00, 0x00, 0xb0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x3f, 0, 0, 0, 0,
+    ];
+    let mcfg = Mcfg::parse(test_table(b"MCFG", &content)).unwrap();
+
+    assert_eq!(mcfg.entries().count(), 1);
+    assert_eq!(
+        mcfg.function_address(0, 0, 0, 0),
+        Some(PhysicalAddress::new(0xb000_0000))
+    );
+    // Bus 1, device 2, function 3
+    assert_eq!(
+        mcfg.function_address(0, 1, 2, 3),
+        Some(PhysicalAddress::new(0xb000_0000 + (256 + 2 * 8 + 3) * 4096))
+    );
+    assert_eq!(mcfg.function_address(0, 0x40, 0, 0), None);
+    assert_eq!(mcfg.function_address(1, 0, 0, 0), None);
+    assert_eq!(mcfg.function_address(0, 0, 32, 0), None);
+}
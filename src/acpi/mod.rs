@@ -0,0 +1,478 @@
+//! ACPI tables
+//!
+//! The firmware describes the hardware of the machine in the ACPI tables. Their root is the Root
+//! System Description Pointer (RSDP), which a BIOS places in the first MiB of physical memory. It
+//! points to the Root System Description Table (RSDT), or the Extended System Description Table
+//! (XSDT) since ACPI 2.0, which lists the physical addresses of all other tables.
+//!
+//! ```text
+//! RSDP ──> RSDT/XSDT ──┬──> APIC (MADT): interrupt controllers and processors
+//!                      ├──> FACP (FADT): power management and the DSDT
+//!                      ├──> HPET: the high precision event timer
+//!                      ├──> MCFG: PCI Express configuration space
+//!                      └──> ...
+//! ```
+//!
+//! Every table starts with an [`SdtHeader`] and all of its bytes add up to zero. [`AcpiTables`]
+//! only hands out tables whose checksum is valid, parsed into [`Madt`], [`Fadt`], [`Hpet`] or
+//! [`Mcfg`]. The tables are read through the mapping of the complete physical memory set up by the
+//! bootloader.
+//!
+//! # Limitation(s)
+//! * The bootloader does not tell where the RSDP is, so it is searched for the way it is done on
+//! BIOS systems. UEFI systems are not supported.
+//! * The AML in the DSDT and SSDTs is not interpreted.
+
+pub mod fadt;
+pub mod hpet;
+pub mod madt;
+pub mod mcfg;
+
+use conquer_once::spin::OnceCell;
+use core::{fmt, slice, str};
+
+use crate::{memory, x86_64::address::PhysicalAddress};
+
+use self::{fadt::Fadt, hpet::Hpet, madt::Madt, mcfg::Mcfg};
+
+/// Segment of the Extended BIOS Data Area (EBDA) is stored at this physical address.
+const EBDA_SEGMENT_POINTER: u64 = 0x40e;
+/// The RSDP lies in the first KiB of the EBDA ...
+const EBDA_SEARCH_LENGTH: u64 = 1024;
+/// ... or in the BIOS read-only memory between `0xe0000` and `0xfffff`.
+const BIOS_AREA_START: u64 = 0xe0000;
+const BIOS_AREA_END: u64 = 0x100000;
+/// The RSDP always starts at a 16 byte boundary.
+const RSDP_ALIGNMENT: u64 = 16;
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+/// Length of the RSDP of ACPI 1.0. Its checksum covers only these bytes.
+const RSDP_V1_LENGTH: usize = 20;
+/// Length of the RSDP since ACPI 2.0, covered by the extended checksum.
+const RSDP_V2_LENGTH: usize = 36;
+
+/// Length of the [`SdtHeader`] every table starts with.
+pub const SDT_HEADER_LENGTH: usize = 36;
+/// Upper bound on the length of a table. Even the DSDTs of large machines stay well below it, so a
+/// longer table is taken to be corrupted instead of reading that far past it.
+pub const MAX_TABLE_LENGTH: usize = 4 * 1024 * 1024;
+
+static ACPI_TABLES: OnceCell<AcpiTables> = OnceCell::uninit();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    /// There is no RSDP with a valid checksum in any of the places it is searched for.
+    RsdpNotFound,
+    /// [`init`] was called before.
+    AlreadyInitialized,
+    /// The root table does not list a table with this signature.
+    TableNotFound(Signature),
+    /// The bytes of the table do not add up to zero.
+    InvalidChecksum(Signature),
+    /// The table is too short for the fields it must have or longer than [`MAX_TABLE_LENGTH`].
+    InvalidLength(Signature),
+}
+
+/// Find the ACPI tables and make them available through [`tables`].
+///
+/// # Panics
+/// If it is called before [`memory::init`].
+pub fn init() -> Result<(), AcpiError> {
+    let rsdp = find_rsdp().ok_or(AcpiError::RsdpNotFound)?;
+    let tables = AcpiTables::new(rsdp)?;
+    ACPI_TABLES
+        .try_init_once(|| tables)
+        .map_err(|_| AcpiError::AlreadyInitialized)
+}
+
+/// The ACPI tables of the machine, if [`init`] found them.
+pub fn tables() -> Option<&'static AcpiTables> {
+    ACPI_TABLES.get()
+}
+
+/// The four character signature identifying a table, e.g. `APIC` for the MADT.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Signature(pub [u8; 4]);
+
+impl Signature {
+    pub fn as_str(&self) -> &str {
+        str::from_utf8(&self.0).unwrap_or("????")
+    }
+}
+
+impl fmt::Debug for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Signature({:?})", self.as_str())
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The Root System Description Pointer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rsdp {
+    pub address: PhysicalAddress,
+    pub oem_id: [u8; 6],
+    /// 0 for ACPI 1.0, 2 for ACPI 2.0 and later.
+    pub revision: u8,
+    pub rsdt_address: PhysicalAddress,
+    /// Only present since ACPI 2.0.
+    pub xsdt_address: Option<PhysicalAddress>,
+}
+
+impl Rsdp {
+    /// Parse the RSDP at the given address if it has the right signature and checksum.
+    fn parse(address: PhysicalAddress) -> Option<Rsdp> {
+        let bytes = unsafe { physical_bytes(address, RSDP_V1_LENGTH) };
+        if &bytes[0..8] != RSDP_SIGNATURE || !has_valid_checksum(bytes) {
+            return None;
+        }
+
+        let revision = bytes[15];
+        let rsdt_address = PhysicalAddress::new(read_u32(bytes, 16) as u64);
+        let mut xsdt_address = None;
+        if revision >= 2 {
+            let bytes = unsafe { physical_bytes(address, RSDP_V2_LENGTH) };
+            if !has_valid_checksum(bytes) {
+                return None;
+            }
+            xsdt_address = Some(PhysicalAddress::new(read_u64(bytes, 24)));
+        }
+
+        Some(Rsdp {
+            address,
+            oem_id: bytes[9..15].try_into().unwrap(),
+            revision,
+            rsdt_address,
+            xsdt_address,
+        })
+    }
+}
+
+/// Search the EBDA and the BIOS read-only memory for the RSDP.
+fn find_rsdp() -> Option<Rsdp> {
+    let ebda_segment: u16 = read_u16(
+        unsafe { physical_bytes(PhysicalAddress::new(EBDA_SEGMENT_POINTER), 2) },
+        0,
+    );
+    let ebda_start = (ebda_segment as u64) << 4;
+
+    let ebda = ebda_start..ebda_start + EBDA_SEARCH_LENGTH;
+    let bios_area = BIOS_AREA_START..BIOS_AREA_END;
+    ebda.step_by(RSDP_ALIGNMENT as usize)
+        .chain(bios_area.step_by(RSDP_ALIGNMENT as usize))
+        .find_map(|address| Rsdp::parse(PhysicalAddress::new(address)))
+}
+
+/// The header every system description table starts with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SdtHeader {
+    pub signature: Signature,
+    /// Length of the whole table (including the header) in bytes.
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+impl SdtHeader {
+    /// # Panics
+    /// If there are less than [`SDT_HEADER_LENGTH`] bytes.
+    pub fn parse(bytes: &[u8]) -> SdtHeader {
+        SdtHeader {
+            signature: Signature(bytes[0..4].try_into().unwrap()),
+            length: read_u32(bytes, 4),
+            revision: bytes[8],
+            checksum: bytes[9],
+            oem_id: bytes[10..16].try_into().unwrap(),
+            oem_table_id: bytes[16..24].try_into().unwrap(),
+            oem_revision: read_u32(bytes, 24),
+            creator_id: read_u32(bytes, 28),
+            creator_revision: read_u32(bytes, 32),
+        }
+    }
+}
+
+/// A system description table in physical memory.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Table {
+    address: PhysicalAddress,
+    header: SdtHeader,
+}
+
+impl Table {
+    /// Read the header of the table at the given address.
+    ///
+    /// # Safety
+    /// There must be a table at the given address.
+    unsafe fn at(address: PhysicalAddress) -> Table {
+        let header = SdtHeader::parse(physical_bytes(address, SDT_HEADER_LENGTH));
+        Table { address, header }
+    }
+
+    pub fn address(&self) -> PhysicalAddress {
+        self.address
+    }
+
+    pub fn header(&self) -> &SdtHeader {
+        &self.header
+    }
+
+    pub fn signature(&self) -> Signature {
+        self.header.signature
+    }
+
+    /// The complete table, including the header.
+    ///
+    /// The length is clamped to at least the header and at most [`MAX_TABLE_LENGTH`] bytes, so
+    /// only tables that passed validation are guaranteed to be complete.
+    pub fn bytes(&self) -> &'static [u8] {
+        let length = (self.header.length as usize).clamp(SDT_HEADER_LENGTH, MAX_TABLE_LENGTH);
+        unsafe { physical_bytes(self.address, length) }
+    }
+
+    pub fn has_valid_checksum(&self) -> bool {
+        has_valid_checksum(self.bytes())
+    }
+
+    /// Make sure the length and checksum are valid before the table is used.
+    fn validate(self) -> Result<Table, AcpiError> {
+        if !has_valid_length(&self.header) {
+            return Err(AcpiError::InvalidLength(self.signature()));
+        }
+        if !self.has_valid_checksum() {
+            return Err(AcpiError::InvalidChecksum(self.signature()));
+        }
+        Ok(self)
+    }
+}
+
+impl fmt::Debug for Table {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Table")
+            .field("signature", &self.header.signature)
+            .field("address", &self.address)
+            .field("length", &self.header.length)
+            .finish()
+    }
+}
+
+/// The root of the ACPI tables.
+pub struct AcpiTables {
+    rsdp: Rsdp,
+    /// The XSDT if there is one, otherwise the RSDT.
+    root: Table,
+    /// Size of the addresses in the root table (4 in the RSDT, 8 in the XSDT).
+    entry_size: usize,
+}
+
+impl AcpiTables {
+    fn new(rsdp: Rsdp) -> Result<AcpiTables, AcpiError> {
+        let (root_address, entry_size) = match rsdp.xsdt_address {
+            Some(xsdt_address) => (xsdt_address, 8),
+            None => (rsdp.rsdt_address, 4),
+        };
+
+        Ok(AcpiTables {
+            rsdp,
+            root: unsafe { Table::at(root_address) }.validate()?,
+            entry_size,
+        })
+    }
+
+    pub fn rsdp(&self) -> &Rsdp {
+        &self.rsdp
+    }
+
+    /// The RSDT or XSDT.
+    pub fn root(&self) -> &Table {
+        &self.root
+    }
+
+    /// Iterate over all tables listed in the root table, no matter whether their checksum is
+    /// valid.
+    pub fn iter(&self) -> impl Iterator<Item = Table> + '_ {
+        self.root.bytes()[SDT_HEADER_LENGTH..]
+            .chunks_exact(self.entry_size)
+            .map(|entry| match entry.len() {
+                4 => read_u32(entry, 0) as u64,
+                _ => read_u64(entry, 0),
+            })
+            .map(|address| unsafe { Table::at(PhysicalAddress::new(address)) })
+    }
+
+    /// Find the first table with the given signature and check that it is valid.
+    pub fn find(&self, signature: Signature) -> Result<Table, AcpiError> {
+        self.iter()
+            .find(|table| table.signature() == signature)
+            .ok_or(AcpiError::TableNotFound(signature))?
+            .validate()
+    }
+
+    /// The table at the given address (e.g. the DSDT, which is not listed in the root table),
+    /// after checking that it is valid.
+    ///
+    /// # Safety
+    /// There must be a table at the given address.
+    pub unsafe fn table_at(&self, address: PhysicalAddress) -> Result<Table, AcpiError> {
+        Table::at(address).validate()
+    }
+
+    /// The Multiple APIC Description Table.
+    pub fn madt(&self) -> Result<Madt, AcpiError> {
+        Madt::parse(self.find(madt::SIGNATURE)?.bytes())
+    }
+
+    /// The Fixed ACPI Description Table.
+    pub fn fadt(&self) -> Result<Fadt, AcpiError> {
+        Fadt::parse(self.find(fadt::SIGNATURE)?.bytes())
+    }
+
+    /// The High Precision Event Timer Description Table.
+    pub fn hpet(&self) -> Result<Hpet, AcpiError> {
+        Hpet::parse(self.find(hpet::SIGNATURE)?.bytes())
+    }
+
+    /// The PCI Express Memory Mapped Configuration Space Description Table.
+    pub fn mcfg(&self) -> Result<Mcfg, AcpiError> {
+        Mcfg::parse(self.find(mcfg::SIGNATURE)?.bytes())
+    }
+}
+
+/// Where a register lives, as described by a Generic Address Structure (GAS).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GenericAddress {
+    pub address_space: AddressSpace,
+    /// Size of the register in bits.
+    pub bit_width: u8,
+    /// Position of the register within the address in bits.
+    pub bit_offset: u8,
+    /// 0 (undefined), 1 (byte), 2 (word), 3 (double word) or 4 (quad word).
+    pub access_size: u8,
+    pub address: u64,
+}
+
+/// Length of a [`GenericAddress`] in a table.
+pub const GENERIC_ADDRESS_LENGTH: usize = 12;
+
+impl GenericAddress {
+    /// # Panics
+    /// If there are less than [`GENERIC_ADDRESS_LENGTH`] bytes.
+    pub fn parse(bytes: &[u8]) -> GenericAddress {
+        GenericAddress {
+            address_space: match bytes[0] {
+                0 => AddressSpace::SystemMemory,
+                1 => AddressSpace::SystemIo,
+                2 => AddressSpace::PciConfiguration,
+                other => AddressSpace::Other(other),
+            },
+            bit_width: bytes[1],
+            bit_offset: bytes[2],
+            access_size: bytes[3],
+            address: read_u64(bytes, 4),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSpace {
+    SystemMemory,
+    SystemIo,
+    PciConfiguration,
+    /// Any of the address spaces the kernel does not deal with (e.g. the embedded controller).
+    Other(u8),
+}
+
+/// Fail with [`AcpiError::InvalidLength`] if the table is shorter than `length`.
+fn check_length(bytes: &[u8], length: usize) -> Result<(), AcpiError> {
+    if bytes.len() < length {
+        return Err(AcpiError::InvalidLength(SdtHeader::parse(bytes).signature));
+    }
+    Ok(())
+}
+
+/// The length in the header covers at least the header itself and at most
+/// [`MAX_TABLE_LENGTH`] bytes.
+fn has_valid_length(header: &SdtHeader) -> bool {
+    (SDT_HEADER_LENGTH..=MAX_TABLE_LENGTH).contains(&(header.length as usize))
+}
+
+/// All bytes of an ACPI structure add up to zero (modulo 256).
+fn has_valid_checksum(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+/// Access `length` bytes of physical memory through the physical memory mapping.
+///
+/// # Safety
+/// The memory must be mapped by the bootloader and not be changed while the slice is used.
+unsafe fn physical_bytes(address: PhysicalAddress, length: usize) -> &'static [u8] {
+    slice::from_raw_parts(memory::physical_to_virtual(address).as_ptr(), length)
+}
+
+// ACPI structures are little endian and mostly unaligned, so their fields are read byte wise.
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// Build a table with the given signature and content, filling in the length and checksum of its
+/// header.
+#[cfg(test)]
+fn test_table(signature: &[u8; 4], content: &[u8]) -> &'static [u8] {
+    use alloc::vec::Vec;
+
+    let length = SDT_HEADER_LENGTH + content.len();
+    let mut bytes = Vec::with_capacity(length);
+    bytes.extend_from_slice(signature);
+    bytes.extend_from_slice(&(length as u32).to_le_bytes());
+    bytes.resize(SDT_HEADER_LENGTH, 0);
+    bytes.extend_from_slice(content);
+    let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    bytes[9] = 0u8.wrapping_sub(sum);
+    bytes.leak()
+}
+
+#[test_case]
+fn test_checksum_and_length_are_validated() {
+    let bytes = test_table(b"TEST", &[1, 2, 3]);
+    assert!(has_valid_checksum(bytes));
+    let header = SdtHeader::parse(bytes);
+    assert_eq!(header.signature.as_str(), "TEST");
+    assert_eq!(header.length, SDT_HEADER_LENGTH as u32 + 3);
+
+    let mut corrupted = bytes.to_vec();
+    corrupted[SDT_HEADER_LENGTH] ^= 0xff;
+    assert!(!has_valid_checksum(&corrupted));
+
+    assert_eq!(
+        check_length(bytes, SDT_HEADER_LENGTH + 4),
+        Err(AcpiError::InvalidLength(Signature(*b"TEST")))
+    );
+    assert_eq!(check_length(bytes, SDT_HEADER_LENGTH + 3), Ok(()));
+
+    assert!(has_valid_length(&header));
+    for length in [
+        0,
+        SDT_HEADER_LENGTH as u32 - 1,
+        MAX_TABLE_LENGTH as u32 + 1,
+        u32::MAX,
+    ] {
+        assert!(!has_valid_length(&SdtHeader { length, ..header }));
+    }
+}
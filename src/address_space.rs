@@ -0,0 +1,255 @@
+//! Per-process address spaces
+//!
+//! Every [`AddressSpace`] has its own level 4 [`PageTable`]. The part of the virtual address space
+//! between [`USER_SPACE_START`] and [`USER_SPACE_END`] belongs to the address space alone, while
+//! all other level 4 entries are copied from the kernel's page table. This way the lower level
+//! tables of the kernel are shared by all address spaces and the kernel stays mapped no matter
+//! which address space is active.
+//!
+//! The bootloader places the kernel, the physical memory mapping and the recursive entry in the
+//! lowest level 4 entries, so instead of the usual higher half we reserve a range of level 4
+//! entries for user space that the kernel doesn't use.
+
+use alloc::vec::Vec;
+use core::ptr;
+
+use crate::{
+    copy_on_write,
+    frame_allocator::FrameAllocator,
+    memory::{self, FRAME_ALLOCATOR},
+    utils::Locked,
+    x86_64::{
+        address::VirtualAddress,
+        instructions::{read_control_register_3, write_control_register_3},
+        paging::{
+            self, MappedRegion, Mapper, MappingError, Page, PageFrame, PageTable,
+            PageTableEntryFlags, PageTableIndex, PageTableLevel,
+        },
+    },
+};
+
+/// Start of the part of the virtual address space that is private to an [`AddressSpace`] (level 4
+/// entry 16).
+pub const USER_SPACE_START: u64 = 0x0000_0800_0000_0000;
+/// End (exclusive) of the part of the virtual address space that is private to an
+/// [`AddressSpace`] (level 4 entry 128).
+pub const USER_SPACE_END: u64 = 0x0000_4000_0000_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSpaceError {
+    /// There was no frame left for the level 4 table.
+    FrameAllocationFailed,
+    /// The page is not inside of [`USER_SPACE_START`]..[`USER_SPACE_END`].
+    OutsideUserSpace,
+    /// Mapping the page failed.
+    Mapping(MappingError),
+}
+
+/// A page table hierarchy of its own that shares the kernel's mappings.
+///
+/// The frames mapped into the user part of the address space (see
+/// [`AddressSpace::map_user_page`]) are owned by it and freed along with its page tables once the
+/// address space is dropped. Frames shared with a clone (see [`AddressSpace::try_clone`]) are
+/// only freed once neither of them uses them anymore.
+///
+/// # Limitation(s)
+/// * Level 4 entries the kernel starts to use after the address space was created are not
+/// visible in it. Mappings below the already existing entries are, as those tables are shared.
+pub struct AddressSpace {
+    level4_frame: PageFrame,
+    frame_allocator: &'static Locked<FrameAllocator>,
+}
+
+impl AddressSpace {
+    /// Allocate a new level 4 table and copy the kernel's entries into it.
+    ///
+    /// # Panics
+    /// If it is called before [`memory::init`] or the kernel uses any part of the user space.
+    pub fn new() -> Result<Self, AddressSpaceError> {
+        let level4_frame = FRAME_ALLOCATOR
+            .lock()
+            .allocate_normal_frame()
+            .ok_or(AddressSpaceError::FrameAllocationFailed)?;
+        let address_space = AddressSpace {
+            level4_frame,
+            frame_allocator: &FRAME_ALLOCATOR,
+        };
+
+        let (kernel_level4_frame, _) = read_control_register_3();
+        let kernel_table = unsafe { &*memory::memory_mapper().lock().level4_table() };
+        let table = unsafe { &mut *address_space.level4_table() };
+        table.clear_all_entries();
+
+        for index in (0..512).map(PageTableIndex::new_truncate) {
+            let entry = kernel_table[index];
+            if is_user_space_entry(index) {
+                assert!(
+                    entry.is_unused(),
+                    "the kernel uses level 4 entry {:?}",
+                    index
+                );
+                continue;
+            }
+
+            table[index] = entry;
+            // A recursive entry has to point to the table it is in, otherwise the recursive
+            // addresses would still show the kernel's tables while this address space is active.
+            if entry.frame(PageTableLevel::Level4) == Ok(kernel_level4_frame) {
+                table[index].set_address(level4_frame.start_address(), entry.flags());
+            }
+        }
+
+        Ok(address_space)
+    }
+
+    /// Map the page to a newly allocated, zeroed frame that is accessible from user mode.
+    /// `PRESENT` and `USER_ACCESSIBLE` are added to the given flags.
+    pub fn map_user_page(
+        &mut self,
+        page: Page,
+        flags: PageTableEntryFlags,
+    ) -> Result<(), AddressSpaceError> {
+        let start = page.start_address().as_u64();
+        if start < USER_SPACE_START || start + page.size() > USER_SPACE_END {
+            return Err(AddressSpaceError::OutsideUserSpace);
+        }
+
+        let frame = match page {
+            Page::Normal(_) => self.frame_allocator.lock().allocate_normal_frame(),
+            Page::Huge(_) => self.frame_allocator.lock().allocate_huge_frame(),
+            Page::Giant(_) => self.frame_allocator.lock().allocate_giant_frame(),
+        }
+        .ok_or(AddressSpaceError::Mapping(
+            MappingError::FrameAllocationFailed,
+        ))?;
+
+        // The frame can hold data of whoever used it before.
+        let frame_pointer: *mut u8 =
+            memory::physical_to_virtual(frame.start_address()).as_mut_ptr();
+        unsafe { ptr::write_bytes(frame_pointer, 0, frame.size() as usize) };
+
+        let flags = flags | PageTableEntryFlags::PRESENT | PageTableEntryFlags::USER_ACCESSIBLE;
+        unsafe { self.map_to(page, frame, flags) }.map_err(|error| {
+            self.frame_allocator.lock().deallocate_frame(frame);
+            AddressSpaceError::Mapping(error)
+        })
+    }
+
+    /// Create a new address space with the same user pages as this one. The pages are shared
+    /// copy-on-write, so the frames behind them only get copied once either side writes to them.
+    pub fn try_clone(&mut self) -> Result<AddressSpace, AddressSpaceError> {
+        let mut clone = AddressSpace::new()?;
+
+        let user_regions: Vec<MappedRegion> = paging::mapped_regions(&*self)
+            .filter(|region| is_user_space_address(region.start.start_address()))
+            .collect();
+        for page in user_regions.iter().flat_map(MappedRegion::pages) {
+            unsafe { copy_on_write::share_page(self, &mut clone, page) }
+                .map_err(AddressSpaceError::Mapping)?;
+        }
+
+        Ok(clone)
+    }
+
+    /// Whether this address space is the one currently loaded in CR3.
+    pub fn is_active(&self) -> bool {
+        read_control_register_3().0 == self.level4_frame
+    }
+
+    /// Make this the active address space by loading its level 4 table into CR3. This flushes all
+    /// non global entries of the TLB.
+    ///
+    /// # Safety
+    /// The caller must ensure that the address space outlives its use, i.e. that another address
+    /// space is activated before it is dropped. Anything referencing memory in the user part of
+    /// the previous address space becomes invalid.
+    pub unsafe fn activate(&self) {
+        let (_, flags) = read_control_register_3();
+        write_control_register_3(self.level4_frame, flags);
+    }
+}
+
+impl Mapper for AddressSpace {
+    fn level4_table(&self) -> *mut PageTable {
+        memory::physical_to_virtual(self.level4_frame.start_address()).as_mut_ptr()
+    }
+
+    unsafe fn child_table(
+        &self,
+        _table: *mut PageTable,
+        _index: PageTableIndex,
+        frame: PageFrame,
+    ) -> *mut PageTable {
+        memory::physical_to_virtual(frame.start_address()).as_mut_ptr()
+    }
+
+    fn frame_allocator(&self) -> &'static Locked<FrameAllocator> {
+        self.frame_allocator
+    }
+
+    /// Only the tables of the user part belong to the address space, the rest is the kernel's.
+    fn owns_level3_table(&self, index: PageTableIndex) -> bool {
+        is_user_space_entry(index)
+    }
+}
+
+impl Drop for AddressSpace {
+    /// Free every frame mapped in the user part along with the page tables of it and the level 4
+    /// table itself.
+    ///
+    /// # Panics
+    /// If the address space is still active.
+    fn drop(&mut self) {
+        assert!(!self.is_active(), "dropping the active address space");
+
+        let table = self.level4_table();
+        for index in (0..512).map(PageTableIndex::new_truncate) {
+            if !is_user_space_entry(index) {
+                continue;
+            }
+            if let Ok(frame) = unsafe { &*table }[index].frame(PageTableLevel::Level4) {
+                unsafe { free_table(self, frame, PageTableLevel::Level3) };
+            }
+        }
+
+        self.frame_allocator
+            .lock()
+            .deallocate_frame(self.level4_frame);
+    }
+}
+
+/// Whether the level 4 entry at `index` maps a part of the user space.
+fn is_user_space_entry(index: PageTableIndex) -> bool {
+    let zero = PageTableIndex::new_truncate(0);
+    is_user_space_address(VirtualAddress::from_page_table_indices(
+        index, zero, zero, zero,
+    ))
+}
+
+fn is_user_space_address(address: VirtualAddress) -> bool {
+    (USER_SPACE_START..USER_SPACE_END).contains(&address.as_u64())
+}
+
+/// Free the table of the given `level` in `frame`, every table below it and every frame mapped by
+/// them.
+///
+/// # Safety
+/// The caller must ensure that the table is not in use by any active address space.
+unsafe fn free_table(address_space: &AddressSpace, frame: PageFrame, level: PageTableLevel) {
+    let table =
+        &*address_space.child_table(ptr::null_mut(), PageTableIndex::new_truncate(0), frame);
+    for entry in table.iter() {
+        let child = match entry.frame(level) {
+            Ok(child) => child,
+            Err(_) => continue,
+        };
+        match level.next_lower_level() {
+            Some(lower_level) if !entry.has_huge_frame() => {
+                free_table(address_space, child, lower_level)
+            }
+            _ => address_space.frame_allocator.lock().deallocate_frame(child),
+        }
+    }
+
+    address_space.frame_allocator.lock().deallocate_frame(frame);
+}
@@ -1,28 +1,91 @@
 //! Setup heap allocation
 //!
-//! We setup a [`global_allocator`] here. Which uses an implementaion of Allocator (currently
-//! ['LinkedListAllocator']). Also, provides functionality to initialize the heap space.
+//! We setup a [`global_allocator`] here. Which uses an implementaion of Allocator (picked with the
+//! `alloc-*` cargo features, ['FixedSizeBlockAllocator'] by default). Also, provides functionality
+//! to initialize the heap space and to grow it when the allocator runs out of memory.
+//!
+//! The heap reserves [`HEAP_MAX_SIZE`] bytes of the kernel's virtual address space up front, so it
+//! can always grow in place.
 
-use core::alloc::Layout;
+use conquer_once::spin::OnceCell;
+use core::alloc::{GlobalAlloc, Layout};
 
+#[cfg(feature = "alloc-bump")]
+use crate::allocator::bump::BumpAllocator;
+#[cfg(feature = "heap-debug")]
+use crate::allocator::debug::DebugAllocator;
+#[cfg(any(
+    feature = "alloc-slab",
+    not(any(feature = "alloc-bump", feature = "alloc-linked-list"))
+))]
+use crate::allocator::fixed_size_block::FixedSizeBlockAllocator;
+#[cfg(feature = "alloc-linked-list")]
+use crate::allocator::linked_list::LinkedListAllocator;
 use crate::{
-    allocator::linked_list::LinkedListAllocator,
+    allocator::{align_up, HeapStats},
+    memory,
     utils::Locked,
+    virtual_memory::{RangeOwner, KERNEL_VIRTUAL_MEMORY},
     x86_64::{
         address::VirtualAddress,
-        paging::{MappingError, OffsetMemoryMapper, Page, PageInner, PageTableEntryFlags},
+        paging::{Mapper, MappingError, Page, PageInner, PageSize, PageTableEntryFlags, Size4KiB},
     },
 };
 
-/// Easily recognizable heap starting address.
-pub const HEAP_START: usize = 0x_4444_4444_0000;
-/// Heap Size
+/// Heap Size that is mapped on initialization
 pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
+/// Size up to which the heap is allowed to grow
+pub const HEAP_MAX_SIZE: usize = 16 * 1024 * 1024; // 16 MiB
+/// Minimum amount by which the heap grows once it runs out of memory
+pub const HEAP_GROWTH_SIZE: usize = 64 * 1024; // 64 KiB
+
+const PAGE_SIZE: usize = Size4KiB::SIZE as usize;
+
+#[cfg(all(feature = "alloc-bump", feature = "alloc-linked-list"))]
+compile_error!("only one of the `alloc-*` features can be enabled at a time");
+#[cfg(all(
+    feature = "alloc-slab",
+    any(feature = "alloc-bump", feature = "alloc-linked-list")
+))]
+compile_error!("only one of the `alloc-*` features can be enabled at a time");
+
+/// The allocation strategy used for the kernel heap, picked by the `alloc-*` cargo features
+/// (`alloc-slab` is used if none of them is enabled). All the allocators in [`crate::allocator`]
+/// provide a const `new`, an `init` taking the heap start and size and an `extend` to add memory at
+/// the end of the heap.
+#[cfg(any(
+    feature = "alloc-slab",
+    not(any(feature = "alloc-bump", feature = "alloc-linked-list"))
+))]
+type HeapAllocator = FixedSizeBlockAllocator;
+/// Selected with the `alloc-bump` feature.
+#[cfg(feature = "alloc-bump")]
+type HeapAllocator = BumpAllocator;
+/// Selected with the `alloc-linked-list` feature.
+#[cfg(feature = "alloc-linked-list")]
+type HeapAllocator = LinkedListAllocator;
+
+/// Name of the [`HeapAllocator`] in use.
+#[cfg(any(
+    feature = "alloc-slab",
+    not(any(feature = "alloc-bump", feature = "alloc-linked-list"))
+))]
+pub const HEAP_ALLOCATOR_NAME: &str = "slab (fixed size blocks)";
+#[cfg(feature = "alloc-bump")]
+pub const HEAP_ALLOCATOR_NAME: &str = "bump";
+#[cfg(feature = "alloc-linked-list")]
+pub const HEAP_ALLOCATOR_NAME: &str = "linked list";
 
 /// Setup a global heap allocator. This attribute is only appliable to a `static` that implements
 /// the [`GlobalAlloc`] trait.
+#[cfg_attr(not(feature = "heap-debug"), global_allocator)]
+static ALLOCATOR: GrowableHeap = GrowableHeap::new();
+
+/// With the `heap-debug` feature every allocation goes through a [`DebugAllocator`] first, which
+/// detects heap corruption on deallocation.
+#[cfg(feature = "heap-debug")]
 #[global_allocator]
-static ALLOCATOR: Locked<LinkedListAllocator> = Locked::new(LinkedListAllocator::new());
+static DEBUG_ALLOCATOR: DebugAllocator<GrowableHeap> = DebugAllocator::new(&ALLOCATOR);
 
 /// Setup which function should be called when our global allocator fails to allocate space on the
 /// heap.
@@ -31,27 +94,142 @@ fn alloc_error_handler(layout: Layout) -> ! {
     panic!("allocation error: {:?}", layout)
 }
 
+/// The kernel heap.
+///
+/// It hands out memory using the [`HeapAllocator`]. Whenever that runs out of memory it maps more
+/// pages directly after the end of the heap (using the kernel's memory mapper) and retries the
+/// allocation. The heap grows by at least [`HEAP_GROWTH_SIZE`] at a time and never beyond
+/// [`HEAP_MAX_SIZE`].
+///
+/// # Limitation(s)
+/// * The heap never shrinks, even if all the memory is freed again.
+/// * Growing requires the kernel's memory mapper. If it is locked at the time of the allocation
+/// the heap can't grow and the allocation fails.
+pub struct GrowableHeap {
+    allocator: Locked<HeapAllocator>,
+    start: OnceCell<usize>,
+    size: Locked<usize>,
+}
+
+impl GrowableHeap {
+    const fn new() -> Self {
+        GrowableHeap {
+            allocator: Locked::new(HeapAllocator::new()),
+            start: OnceCell::uninit(),
+            size: Locked::new(0),
+        }
+    }
+
+    /// Map enough memory at the end of the heap for the given layout and hand it to the allocator.
+    ///
+    /// Returns None if the heap is not initialized yet, it would grow beyond [`HEAP_MAX_SIZE`], the
+    /// memory mapper or frame allocator are in use or mapping the memory failed. Pages that were
+    /// mapped before a failure stay part of the heap.
+    fn grow(&self, layout: Layout) -> Option<()> {
+        let start = *self.start.get()?;
+        let mut size = self.size.lock();
+        // An allocation needs some room for alignment and the allocator's bookkeeping on top of
+        // its size.
+        let growth = align_up(layout.size() + layout.align(), PAGE_SIZE).max(HEAP_GROWTH_SIZE);
+        if *size + growth > HEAP_MAX_SIZE {
+            return None;
+        }
+
+        let mut mapper = memory::memory_mapper().try_lock()?;
+        for _ in 0..growth / PAGE_SIZE {
+            map_heap_page(&mut *mapper, start + *size).ok()?;
+            unsafe { self.allocator.lock().extend(PAGE_SIZE) };
+            *size += PAGE_SIZE;
+        }
+
+        Some(())
+    }
+}
+
+unsafe impl GlobalAlloc for GrowableHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.allocator.alloc(layout);
+        if !ptr.is_null() {
+            return ptr;
+        }
+
+        match self.grow(layout) {
+            Some(()) => self.allocator.alloc(layout),
+            None => ptr,
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.allocator.dealloc(ptr, layout)
+    }
+}
+
 /// Setup virtual memory range and map it to physical memory.
-pub fn init_heap(mapper: &mut OffsetMemoryMapper) -> Result<(), MappingError> {
-    let page_range = {
-        let heap_start = VirtualAddress::new(HEAP_START as u64);
-        let heap_end = heap_start + (HEAP_SIZE as u64 - 1u64);
-        let heap_start_page = PageInner::containing_address(heap_start);
-        let heap_end_page = PageInner::containing_address(heap_end);
-        PageInner::range_inclusive(heap_start_page, heap_end_page)
-    };
-
-    for page in page_range {
-        let page = Page::Normal(page);
-        let frame = mapper
-            .frame_allocator
-            .allocate_normal_frame()
-            .ok_or(MappingError::FrameAllocationFailed)?;
-        let flags = PageTableEntryFlags::PRESENT | PageTableEntryFlags::WRITABLE;
-        unsafe { mapper.map_to(page, frame, flags)? };
+///
+/// # Panics
+/// If it is called more than once or there is no room for the heap in the kernel's virtual address
+/// space.
+pub fn init_heap(mapper: &mut impl Mapper) -> Result<(), MappingError> {
+    let range = KERNEL_VIRTUAL_MEMORY
+        .lock()
+        .allocate(HEAP_MAX_SIZE as u64, PAGE_SIZE as u64, RangeOwner::Heap)
+        .expect("no virtual memory left for the heap");
+    let heap_start = range.start.as_u64() as usize;
+    for offset in (0..HEAP_SIZE).step_by(PAGE_SIZE) {
+        map_heap_page(mapper, heap_start + offset)?;
     }
 
-    unsafe { ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE) };
+    unsafe { ALLOCATOR.allocator.lock().init(heap_start, HEAP_SIZE) };
+    *ALLOCATOR.size.lock() = HEAP_SIZE;
+    ALLOCATOR
+        .start
+        .try_init_once(|| heap_start)
+        .expect("init_heap should only be called once");
 
     Ok(())
 }
+
+/// Start address of the kernel heap.
+///
+/// # Panics
+/// If it is called before [`init_heap`].
+pub fn heap_start() -> usize {
+    *ALLOCATOR
+        .start
+        .get()
+        .expect("heap_start called before init_heap")
+}
+
+/// Current [`HeapStats`] of the kernel heap.
+pub fn heap_stats() -> HeapStats {
+    ALLOCATOR.allocator.stats()
+}
+
+/// Number of bytes currently mapped for the heap.
+pub fn heap_size() -> usize {
+    *ALLOCATOR.size.lock()
+}
+
+/// Map the heap page at the given address to a newly allocated frame. Heap memory is never
+/// executable.
+///
+/// The heap grows from within [`GlobalAlloc::alloc`], which can be called while the frame
+/// allocator is locked (e.g. by an interrupt handler). So rather than spinning forever on the
+/// lock, this fails with `MappingError::FrameAllocationFailed` if the frame allocator is in use.
+fn map_heap_page(mapper: &mut impl Mapper, address: usize) -> Result<(), MappingError> {
+    let page = Page::Normal(PageInner::containing_address(VirtualAddress::new(
+        address as u64,
+    )));
+    let frame = mapper
+        .frame_allocator()
+        .try_lock()
+        .and_then(|mut frame_allocator| frame_allocator.allocate_normal_frame())
+        .ok_or(MappingError::FrameAllocationFailed)?;
+    let flags = PageTableEntryFlags::PRESENT
+        | PageTableEntryFlags::WRITABLE
+        | PageTableEntryFlags::NO_EXECUTE;
+    unsafe { mapper.map_to(page, frame, flags) }.map_err(|error| {
+        mapper.frame_allocator().lock().deallocate_frame(frame);
+        error
+    })
+}
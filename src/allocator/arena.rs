@@ -0,0 +1,123 @@
+//! Scoped arenas on top of the [`BumpAllocator`]
+use alloc::{
+    alloc::{AllocError, Allocator, Layout},
+    boxed::Box,
+    vec,
+};
+use core::ptr::{self, NonNull};
+
+use crate::utils::Locked;
+
+use super::{bump::BumpAllocator, HeapStatistics, HeapStats};
+
+/// A fixed size chunk of the kernel heap that hands out memory through a [`BumpAllocator`].
+///
+/// It implements the [`Allocator`] trait, so collections can be placed in it with e.g.
+/// `Vec::new_in(&arena)`. Individual deallocations are cheap no-ops (apart from book keeping), all
+/// the memory becomes available again once every allocation is gone, the arena is [`Arena::reset`]
+/// or it is dropped. The borrow of the arena makes sure nothing allocated in it outlives either of
+/// the latter two.
+///
+/// ```ignore
+/// let arena = Arena::new(4096);
+/// let mut words = Vec::new_in(&arena);
+/// words.extend(command.split_whitespace());
+/// ```
+///
+/// # Limitation(s)
+/// * The arena does not grow. Allocations fail once its capacity is used up.
+pub struct Arena {
+    /// The memory the allocations are placed in, taken out of its [`Box`] so that nothing but the
+    /// allocator accesses it. It is given back to the heap when the arena is dropped.
+    memory: *mut [u8],
+    allocator: Locked<BumpAllocator>,
+}
+
+// The memory is only ever accessed through the allocator, which is behind a lock.
+unsafe impl Send for Arena {}
+unsafe impl Sync for Arena {}
+
+impl Arena {
+    /// Create an arena of `capacity` bytes, taken from the kernel heap.
+    pub fn new(capacity: usize) -> Self {
+        let memory = Box::into_raw(vec![0u8; capacity].into_boxed_slice());
+        let mut allocator = BumpAllocator::new();
+        // The memory does not move along with the arena and lives as long as it does.
+        unsafe { allocator.init(memory as *mut u8 as usize, capacity) };
+
+        Arena {
+            memory,
+            allocator: Locked::new(allocator),
+        }
+    }
+
+    /// Free everything that was allocated in the arena at once.
+    ///
+    /// Taking `&mut self` ensures that there is nothing left which was allocated through a shared
+    /// reference to the arena.
+    pub fn reset(&mut self) {
+        self.allocator.lock().reset();
+    }
+}
+
+unsafe impl Allocator for Arena {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let start = self.allocator.lock().allocate(layout).ok_or(AllocError)?;
+        NonNull::new(ptr::slice_from_raw_parts_mut(
+            start as *mut u8,
+            layout.size(),
+        ))
+        .ok_or(AllocError)
+    }
+
+    unsafe fn deallocate(&self, _pointer: NonNull<u8>, _layout: Layout) {
+        self.allocator.lock().deallocate();
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        // Anything allocated in the arena borrows it, so nothing uses the memory anymore.
+        drop(unsafe { Box::from_raw(self.memory) });
+    }
+}
+
+impl HeapStatistics for Arena {
+    fn stats(&self) -> HeapStats {
+        self.allocator.lock().stats()
+    }
+}
+
+#[test_case]
+fn test_arena_allocations_do_not_overlap() {
+    use alloc::vec::Vec;
+
+    let arena = Arena::new(256);
+    let mut first = Vec::with_capacity_in(4, &arena);
+    let mut second = Vec::with_capacity_in(4, &arena);
+    first.extend([1u64, 2, 3, 4]);
+    second.extend([5u64, 6, 7, 8]);
+
+    assert_eq!(first, [1, 2, 3, 4]);
+    assert_eq!(second, [5, 6, 7, 8]);
+    assert_eq!(arena.stats().live_allocations, 2);
+    assert_eq!(arena.stats().bytes_allocated, 64);
+}
+
+#[test_case]
+fn test_arena_fails_when_full_and_can_be_reset() {
+    use alloc::{boxed::Box, vec::Vec};
+
+    let mut arena = Arena::new(64);
+    let mut vec: Vec<u8, &Arena> = Vec::new_in(&arena);
+    assert!(vec.try_reserve_exact(64).is_ok());
+    assert!(Box::try_new_in(0u8, &arena).is_err());
+    // Leaking the vector keeps its allocation alive, only a reset gets the memory back.
+    vec.leak();
+    assert_eq!(arena.stats().bytes_free, 0);
+
+    arena.reset();
+    assert_eq!(arena.stats().bytes_free, 64);
+    assert_eq!(arena.stats().live_allocations, 0);
+    assert!(Box::try_new_in(0u8, &arena).is_ok());
+}
@@ -4,7 +4,7 @@ use core::ptr;
 use crate::utils::Locked;
 use alloc::alloc::{GlobalAlloc, Layout};
 
-use super::align_up;
+use super::{align_up, HeapStatistics, HeapStats};
 
 /// It allocates memory linearly and only keeps track of the number of allocated bytes and the
 /// number of allocations.
@@ -46,6 +46,7 @@ pub struct BumpAllocator {
     heap_end: usize,
     next: usize,
     allocations: usize,
+    peak_next: usize,
 }
 
 impl BumpAllocator {
@@ -56,6 +57,7 @@ impl BumpAllocator {
             heap_end: 0,
             next: 0,
             allocations: 0,
+            peak_next: 0,
         }
     }
 
@@ -70,6 +72,61 @@ impl BumpAllocator {
         self.heap_start = heap_start;
         self.heap_end = heap_start + heap_size;
         self.next = heap_start;
+        self.peak_next = heap_start;
+    }
+
+    /// Add `size` bytes directly after the current end of the heap.
+    ///
+    /// # Safety
+    /// The caller must ensure that the memory right after the heap is valid and unused.
+    pub unsafe fn extend(&mut self, size: usize) {
+        self.heap_end += size;
+    }
+
+    /// Bump `next` past a region fitting `layout` and return the start of the region, or `None`
+    /// if the heap has no room left for it.
+    pub(super) fn allocate(&mut self, layout: Layout) -> Option<usize> {
+        let allocation_start = align_up(self.next, layout.align());
+        // This can overflow when adding layout.size() to allocation_start.
+        let allocation_end = allocation_start.checked_add(layout.size())?;
+        if allocation_end > self.heap_end {
+            return None;
+        }
+
+        self.allocations += 1;
+        self.next = allocation_end;
+        self.peak_next = self.peak_next.max(allocation_end);
+        Some(allocation_start)
+    }
+
+    /// Forget about one allocation. Once none are left the whole heap is free again.
+    pub(super) fn deallocate(&mut self) {
+        self.allocations -= 1;
+        if self.allocations == 0 {
+            self.next = self.heap_start;
+        }
+    }
+
+    /// Free all allocations at once.
+    pub(super) fn reset(&mut self) {
+        self.allocations = 0;
+        self.next = self.heap_start;
+    }
+}
+
+impl HeapStatistics for BumpAllocator {
+    /// Everything between the start of the heap and `next` counts as allocated, even the parts
+    /// of it that were already freed.
+    fn stats(&self) -> HeapStats {
+        let bytes_free = self.heap_end - self.next;
+        HeapStats {
+            bytes_allocated: self.next - self.heap_start,
+            bytes_free,
+            live_allocations: self.allocations,
+            free_list_length: if bytes_free > 0 { 1 } else { 0 },
+            largest_free_block: bytes_free,
+            peak_bytes_allocated: self.peak_next - self.heap_start,
+        }
     }
 }
 
@@ -79,30 +136,13 @@ impl BumpAllocator {
 /// that we need to perform mutations we make use of syncornised interior mutability.
 unsafe impl GlobalAlloc for Locked<BumpAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let mut bump_allocator = self.lock();
-
-        let allocation_start = align_up(bump_allocator.next, layout.align());
-        let allocation_end = match allocation_start.checked_add(layout.size()) {
-            Some(next) => next,
-            // Returning null pointer here signals that we ran out of memory. Here it can happen
-            // when adding layout.size() to alloc_start results in overflow.
-            None => return ptr::null_mut(),
-        };
-        if bump_allocator.next > bump_allocator.heap_end {
+        match self.lock().allocate(layout) {
+            Some(allocation_start) => allocation_start as *mut u8,
             // Signaling that we ran out of memory
-            ptr::null_mut()
-        } else {
-            bump_allocator.allocations += 1;
-            bump_allocator.next = allocation_end;
-            allocation_start as *mut u8
+            None => ptr::null_mut(),
         }
     }
     unsafe fn dealloc(&self, _layout: *mut u8, _: Layout) {
-        let mut bump_allocator = self.lock();
-        bump_allocator.allocations -= 1;
-
-        if bump_allocator.allocations == 0 {
-            bump_allocator.next = bump_allocator.heap_start;
-        }
+        self.lock().deallocate();
     }
 }
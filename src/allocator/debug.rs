@@ -0,0 +1,271 @@
+//! Heap debugging allocator (enabled with the `heap-debug` feature)
+
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    fmt, mem, ptr,
+};
+
+use super::align_up;
+
+/// Byte pattern the red zones around every allocation are filled with.
+const CANARY: u8 = 0xab;
+/// Byte pattern freed memory is filled with. Reading a pointer or length made out of these bytes
+/// is a sure sign of a use after free.
+const POISON: u8 = 0xdf;
+/// Size of each of the red zones before and after the user data.
+const RED_ZONE_SIZE: usize = 16;
+
+/// Value of [`AllocationHeader::state`] while the allocation is alive.
+const ALLOCATED: u64 = 0xa110_ca7e_d000_0000;
+/// Value of [`AllocationHeader::state`] once the allocation was freed.
+const FREED: u64 = 0xf4ee_d000_0000_0000;
+
+/// Bookkeeping stored in front of every allocation.
+///
+/// The state is the last field, as the underlying allocators write their free list nodes at the
+/// start of a freed block and we want it to survive that.
+#[repr(C)]
+struct AllocationHeader {
+    size: usize,
+    align: usize,
+    _reserved: usize,
+    state: u64,
+}
+
+/// The kinds of heap corruption [`DebugAllocator`] detects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapCorruption {
+    /// The allocation was already freed.
+    DoubleFree,
+    /// The header in front of the allocation is not one of ours. Either the pointer was never
+    /// allocated by us or the header got overwritten.
+    UnknownAllocation,
+    /// The [`Layout`] passed to `dealloc` is not the one the memory was allocated with.
+    LayoutMismatch {
+        allocated: Layout,
+        deallocated: Layout,
+    },
+    /// Something wrote into the red zone in front of the allocation.
+    RedZoneBeforeOverwritten,
+    /// Something wrote into the red zone after the allocation (i.e. a buffer overflow).
+    RedZoneAfterOverwritten,
+}
+
+impl fmt::Display for HeapCorruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeapCorruption::DoubleFree => write!(f, "double free"),
+            HeapCorruption::UnknownAllocation => {
+                write!(f, "freeing memory the heap never allocated")
+            }
+            HeapCorruption::LayoutMismatch {
+                allocated,
+                deallocated,
+            } => write!(
+                f,
+                "freeing with {:?} but it was allocated with {:?}",
+                deallocated, allocated
+            ),
+            HeapCorruption::RedZoneBeforeOverwritten => {
+                write!(f, "buffer underflow into the red zone")
+            }
+            HeapCorruption::RedZoneAfterOverwritten => {
+                write!(f, "buffer overflow into the red zone")
+            }
+        }
+    }
+}
+
+/// Wraps another allocator and checks every deallocation for signs of heap corruption.
+///
+/// Every allocation is surrounded by red zones filled with a canary value and preceded by a header
+/// recording its [`Layout`] and whether it is still alive.
+///
+/// ```text
+///  ┌────────┬─────────┬───────────────────────────┬──────────┐
+///  │ header │ red zone│ user data                 │ red zone │
+///  └────────┴─────────┴───────────────────────────┴──────────┘
+///                     ▲
+///                     └─── pointer handed out
+/// ```
+///
+/// On `dealloc` we panic with a description of the problem if the header says the memory was
+/// already freed, the layout does not match or any of the red zones were overwritten. Freed memory
+/// is then filled with a poison pattern before it is handed back to the wrapped allocator.
+///
+/// # Limitation(s)
+/// * Every allocation takes up at least `2 * RED_ZONE_SIZE` plus the header more memory.
+/// * A double free is only detected as long as the memory was not handed out again in between.
+pub struct DebugAllocator<A: 'static> {
+    inner: &'static A,
+}
+
+impl<A> DebugAllocator<A> {
+    /// Create a new [`DebugAllocator`] that gets its memory from `inner`.
+    pub const fn new(inner: &'static A) -> Self {
+        DebugAllocator { inner }
+    }
+}
+
+/// Layout of the whole debug allocation along with the offset of the user data in it.
+fn padded_layout(layout: Layout) -> (Layout, usize) {
+    let align = layout.align().max(mem::align_of::<AllocationHeader>());
+    let data_offset = align_up(mem::size_of::<AllocationHeader>() + RED_ZONE_SIZE, align);
+    let size = data_offset + layout.size() + RED_ZONE_SIZE;
+    let padded = Layout::from_size_align(size, align).expect("padded layout is too big");
+    (padded, data_offset)
+}
+
+/// Header of the allocation whose user data starts at `ptr`.
+unsafe fn header<'a>(ptr: *mut u8) -> &'a mut AllocationHeader {
+    let header_ptr = ptr.sub(RED_ZONE_SIZE + mem::size_of::<AllocationHeader>());
+    &mut *(header_ptr as *mut AllocationHeader)
+}
+
+/// Write the header and red zones for an allocation with the given layout. `ptr` points to the
+/// user data.
+unsafe fn prepare(ptr: *mut u8, layout: Layout) {
+    *header(ptr) = AllocationHeader {
+        size: layout.size(),
+        align: layout.align(),
+        _reserved: 0,
+        state: ALLOCATED,
+    };
+    ptr::write_bytes(ptr.sub(RED_ZONE_SIZE), CANARY, RED_ZONE_SIZE);
+    ptr::write_bytes(ptr.add(layout.size()), CANARY, RED_ZONE_SIZE);
+}
+
+/// Verify the header and red zones of the allocation with user data at `ptr` that is being freed
+/// with the given layout.
+unsafe fn check(ptr: *mut u8, layout: Layout) -> Result<(), HeapCorruption> {
+    let header = header(ptr);
+    match header.state {
+        ALLOCATED => {}
+        FREED => return Err(HeapCorruption::DoubleFree),
+        _ => return Err(HeapCorruption::UnknownAllocation),
+    }
+
+    if header.size != layout.size() || header.align != layout.align() {
+        return Err(HeapCorruption::LayoutMismatch {
+            allocated: Layout::from_size_align_unchecked(header.size, header.align),
+            deallocated: layout,
+        });
+    }
+
+    let is_intact = |start: *mut u8| (0..RED_ZONE_SIZE).all(|offset| *start.add(offset) == CANARY);
+    if !is_intact(ptr.sub(RED_ZONE_SIZE)) {
+        return Err(HeapCorruption::RedZoneBeforeOverwritten);
+    }
+    if !is_intact(ptr.add(layout.size())) {
+        return Err(HeapCorruption::RedZoneAfterOverwritten);
+    }
+
+    Ok(())
+}
+
+/// Mark the allocation with user data at `ptr` as freed and fill it (including the red zones)
+/// with [`POISON`].
+unsafe fn poison(ptr: *mut u8, layout: Layout) {
+    header(ptr).state = FREED;
+    ptr::write_bytes(
+        ptr.sub(RED_ZONE_SIZE),
+        POISON,
+        layout.size() + 2 * RED_ZONE_SIZE,
+    );
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for DebugAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let (padded, data_offset) = padded_layout(layout);
+        let start = self.inner.alloc(padded);
+        if start.is_null() {
+            return start;
+        }
+
+        let ptr = start.add(data_offset);
+        prepare(ptr, layout);
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Err(corruption) = check(ptr, layout) {
+            panic!("heap corruption at {:p}: {}", ptr, corruption);
+        }
+
+        poison(ptr, layout);
+        let (padded, data_offset) = padded_layout(layout);
+        self.inner.dealloc(ptr.sub(data_offset), padded);
+    }
+}
+
+/// Backing memory for the tests below.
+#[cfg(test)]
+#[repr(align(64))]
+struct TestAllocation([u8; 256]);
+
+/// Set up an allocation of `layout` inside of `memory` and return the pointer to its user data.
+#[cfg(test)]
+fn test_allocation(memory: &mut TestAllocation, layout: Layout) -> *mut u8 {
+    let (_, data_offset) = padded_layout(layout);
+    unsafe {
+        let ptr = memory.0.as_mut_ptr().add(data_offset);
+        prepare(ptr, layout);
+        ptr
+    }
+}
+
+#[test_case]
+fn test_untouched_allocation_passes_checks() {
+    let mut memory = TestAllocation([0; 256]);
+    let layout = Layout::from_size_align(24, 8).unwrap();
+    let ptr = test_allocation(&mut memory, layout);
+
+    unsafe {
+        ptr::write_bytes(ptr, 0x42, layout.size());
+        assert_eq!(check(ptr, layout), Ok(()));
+    }
+}
+
+#[test_case]
+fn test_overflow_and_underflow_are_detected() {
+    let mut memory = TestAllocation([0; 256]);
+    let layout = Layout::from_size_align(24, 8).unwrap();
+    let ptr = test_allocation(&mut memory, layout);
+
+    unsafe {
+        ptr.add(layout.size()).write(0);
+        assert_eq!(
+            check(ptr, layout),
+            Err(HeapCorruption::RedZoneAfterOverwritten)
+        );
+
+        prepare(ptr, layout);
+        ptr.sub(1).write(0);
+        assert_eq!(
+            check(ptr, layout),
+            Err(HeapCorruption::RedZoneBeforeOverwritten)
+        );
+    }
+}
+
+#[test_case]
+fn test_double_free_and_layout_mismatch_are_detected() {
+    let mut memory = TestAllocation([0; 256]);
+    let layout = Layout::from_size_align(24, 8).unwrap();
+    let ptr = test_allocation(&mut memory, layout);
+
+    unsafe {
+        let wrong_layout = Layout::from_size_align(32, 8).unwrap();
+        assert_eq!(
+            check(ptr, wrong_layout),
+            Err(HeapCorruption::LayoutMismatch {
+                allocated: layout,
+                deallocated: wrong_layout,
+            })
+        );
+
+        poison(ptr, layout);
+        assert!((0..layout.size()).all(|offset| *ptr.add(offset) == POISON));
+        assert_eq!(check(ptr, layout), Err(HeapCorruption::DoubleFree));
+    }
+}
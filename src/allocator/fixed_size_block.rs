@@ -0,0 +1,218 @@
+//! Fixed Size Block Allocator design
+
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem,
+};
+
+use crate::{
+    allocator::{linked_list::LinkedListAllocator, HeapStatistics, HeapStats},
+    utils::Locked,
+};
+
+/// The block sizes to use.
+///
+/// The sizes must each be power of 2 because they are also used as the block alignment
+/// (alignments must be always powers of 2). Allocations bigger than the biggest block size are
+/// handed to the fallback allocator.
+const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+struct ListNode {
+    next: Option<&'static mut ListNode>,
+}
+
+/// Keeps a separate free list for every size class in [`BLOCK_SIZES`].
+///
+/// Instead of handing out exactly the requested amount of memory, an allocation is rounded up to
+/// the next block size. The blocks of one size are all interchangeable, so freed blocks are pushed
+/// onto the list of their size and popped off again by the next allocation of the same size class.
+/// Both of these are constant time operations.
+///
+/// ```text
+/// Legend:
+/// n -> List Node with n being pointer to the next node (of the same size)
+///
+/// list_heads[0] (8)  ──► ┌─┐ ──► ┌─┐ ──► ┌─┐
+///                        │n│     │n│     │n│
+///                        └─┘     └─┘     └─┘
+/// list_heads[1] (16) ──► ┌──┐ ──► ┌──┐
+///                        │n │     │n │
+///                        └──┘     └──┘
+/// ...
+/// list_heads[8] (2048) ──► None
+/// ```
+///
+/// # Operations
+///
+/// * *Allocating a block*: We pick the smallest block size that fits the layout and pop the head
+/// of its list. When the list is empty we allocate a new block of that size from the fallback
+/// allocator.
+/// * *Deallocating a block*: We push the block onto the head of the list of its size. Blocks are
+/// never given back to the fallback allocator.
+/// * *Large allocations*: Layouts that do not fit into the biggest block size are allocated and
+/// deallocated directly by the fallback allocator (a [`LinkedListAllocator`]).
+///
+/// # Limitation(s)
+/// * Rounding up to the block size wastes up to half of every block.
+/// * Memory that was once used for a block of some size can only be used for blocks of the same
+/// size from then on.
+pub struct FixedSizeBlockAllocator {
+    list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
+    fallback_allocator: LinkedListAllocator,
+    bytes_allocated: usize,
+    peak_bytes_allocated: usize,
+    live_allocations: usize,
+}
+
+impl FixedSizeBlockAllocator {
+    /// Create a new [`FixedSizeBlockAllocator`] with empty block lists.
+    pub const fn new() -> Self {
+        const EMPTY: Option<&'static mut ListNode> = None;
+        FixedSizeBlockAllocator {
+            list_heads: [EMPTY; BLOCK_SIZES.len()],
+            fallback_allocator: LinkedListAllocator::new(),
+            bytes_allocated: 0,
+            peak_bytes_allocated: 0,
+            live_allocations: 0,
+        }
+    }
+
+    /// Initialize the allocator with the heap information. The whole heap is initially handed to
+    /// the fallback allocator.
+    ///
+    /// # Safety
+    /// This function is unsafe because the caller must guarantee that the given heap bounds are
+    /// valid and that the heap is unused. This method must be called only once.
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.fallback_allocator.init(heap_start, heap_size);
+    }
+
+    /// Add `size` bytes directly after the current end of the heap. The memory is handed to the
+    /// fallback allocator.
+    ///
+    /// # Safety
+    /// The caller must ensure that the memory right after the heap is valid and unused.
+    pub unsafe fn extend(&mut self, size: usize) {
+        self.fallback_allocator.extend(size);
+    }
+
+    /// Allocates using the fallback allocator.
+    fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
+        self.fallback_allocator.allocate(layout)
+    }
+
+    /// Iterate over the free blocks in the list at the given index of `BLOCK_SIZES`.
+    fn free_blocks(&self, index: usize) -> impl Iterator<Item = &ListNode> {
+        let mut current = self.list_heads[index].as_deref();
+        core::iter::from_fn(move || {
+            let block = current?;
+            current = block.next.as_deref();
+            Some(block)
+        })
+    }
+}
+
+/// Number of bytes an allocation with the given layout takes up (the size of its block).
+fn allocated_size(layout: &Layout) -> usize {
+    list_index(layout).map_or(layout.size(), |index| BLOCK_SIZES[index])
+}
+
+/// Choose an appropriate block size for the given layout.
+///
+/// Returns an index into the `BLOCK_SIZES` array.
+fn list_index(layout: &Layout) -> Option<usize> {
+    let required_block_size = layout.size().max(layout.align());
+    BLOCK_SIZES
+        .iter()
+        .position(|&size| size >= required_block_size)
+}
+
+impl HeapStatistics for FixedSizeBlockAllocator {
+    /// Blocks sitting in the block lists count as free, but only blocks of their own size can be
+    /// allocated from them.
+    fn stats(&self) -> HeapStats {
+        let fallback_stats = self.fallback_allocator.stats();
+        let mut stats = HeapStats {
+            bytes_allocated: self.bytes_allocated,
+            live_allocations: self.live_allocations,
+            peak_bytes_allocated: self.peak_bytes_allocated,
+            ..fallback_stats
+        };
+
+        for (index, &block_size) in BLOCK_SIZES.iter().enumerate() {
+            let free_blocks = self.free_blocks(index).count();
+            stats.bytes_free += free_blocks * block_size;
+            stats.free_list_length += free_blocks;
+            if free_blocks > 0 {
+                stats.largest_free_block = stats.largest_free_block.max(block_size);
+            }
+        }
+
+        stats
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut allocator = self.lock();
+        let ptr = match list_index(&layout) {
+            Some(index) => match allocator.list_heads[index].take() {
+                Some(node) => {
+                    allocator.list_heads[index] = node.next.take();
+                    node as *mut ListNode as *mut u8
+                }
+                None => {
+                    // no block exists in list => allocate new block
+                    let block_size = BLOCK_SIZES[index];
+                    // only works if all block sizes are a power of 2
+                    let block_align = block_size;
+                    let layout = Layout::from_size_align(block_size, block_align).unwrap();
+                    allocator.fallback_alloc(layout)
+                }
+            },
+            None => allocator.fallback_alloc(layout),
+        };
+
+        if !ptr.is_null() {
+            allocator.bytes_allocated += allocated_size(&layout);
+            allocator.peak_bytes_allocated = allocator
+                .peak_bytes_allocated
+                .max(allocator.bytes_allocated);
+            allocator.live_allocations += 1;
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut allocator = self.lock();
+        allocator.bytes_allocated -= allocated_size(&layout);
+        allocator.live_allocations -= 1;
+        match list_index(&layout) {
+            Some(index) => {
+                let new_node = ListNode {
+                    next: allocator.list_heads[index].take(),
+                };
+                // verify that block has size and alignment required for storing node
+                assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
+                assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
+                let new_node_ptr = ptr as *mut ListNode;
+                new_node_ptr.write(new_node);
+                allocator.list_heads[index] = Some(&mut *new_node_ptr);
+            }
+            None => allocator.fallback_allocator.deallocate(ptr, layout),
+        }
+    }
+}
+
+#[test_case]
+fn test_list_index_picks_smallest_fitting_block() {
+    let layout = |size, align| Layout::from_size_align(size, align).unwrap();
+
+    assert_eq!(list_index(&layout(1, 1)), Some(0));
+    assert_eq!(list_index(&layout(8, 8)), Some(0));
+    assert_eq!(list_index(&layout(9, 1)), Some(1));
+    // alignment bigger than the size decides the block
+    assert_eq!(list_index(&layout(8, 64)), Some(3));
+    assert_eq!(list_index(&layout(2048, 8)), Some(BLOCK_SIZES.len() - 1));
+    assert_eq!(list_index(&layout(2049, 8)), None);
+}
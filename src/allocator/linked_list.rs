@@ -5,7 +5,10 @@ use core::{
     mem, ptr,
 };
 
-use crate::{allocator::align_up, utils::Locked};
+use crate::{
+    allocator::{align_up, HeapStatistics, HeapStats},
+    utils::Locked,
+};
 
 struct ListNode {
     next: Option<&'static mut ListNode>,
@@ -26,12 +29,23 @@ impl ListNode {
     }
 }
 
+/// How [`LinkedListAllocator`] picks a free region for an allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FitStrategy {
+    /// Use the first region (lowest address) that is big enough. Fast as the search stops early.
+    FirstFit,
+    /// Use the smallest region that is big enough. Keeps big regions intact for big allocations
+    /// at the cost of always traversing the whole list.
+    BestFit,
+}
+
 /// Keeps track of free space in a single Linked List.
 ///
 /// Each node in this linked list represents free space in the memory. Each node contains size of
 /// the memory and the pointer to the next available free space. We only need the pointer to the
 /// first unused region to keep track of all the unused regions. This is often referred to as free
-/// list.
+/// list. The list is kept sorted by address, which means that neighbouring free regions are also
+/// neighbours in the list.
 ///
 /// ```text
 /// Legend:
@@ -50,27 +64,40 @@ impl ListNode {
 ///
 /// # Operations
 ///
-/// * *Adding a free region*: When an allocated region is freed we add it to the top of the head.
-/// (This operation is essentially like adding a head to a linked list).
-/// * *Allocationg a region*: We traverse through the list to find an appropriate region and then
-/// assign a portion of that region. We update the pointers accordingly. (This operation is like
-/// deleting a node and then optionally inserting a new one in it's place.)
+/// * *Adding a free region*: When an allocated region is freed we insert it at its place in the
+/// list (by address). If the region directly before or after it is free as well they are merged
+/// into a single region, so freeing everything gives us back a single region spanning the heap.
+/// * *Allocationg a region*: We traverse through the list to find an appropriate region (according
+/// to the [`FitStrategy`]) and then assign a portion of that region. Whatever is left on either
+/// side of the allocation is added back as a free region.
 ///
 /// # Limitation(s)
-/// * Currently we don't merge free blocks together this leads to fragmentation of memory.
-/// Eventually even if we free up everything we can't allocate space even if we have that much free
-/// space available with us. A more robust implementation will need to merge these adjacent free
-/// blocks together.
-/// * We need to traverse the whole list in order to find a suitable region to allocate. This can
-/// be a performance nightmare.
+/// * We need to traverse the list in order to find a suitable region to allocate as well as to
+/// find the place to insert a freed region. This can be a performance nightmare.
 pub struct LinkedListAllocator {
     head: ListNode,
+    heap_end: usize,
+    strategy: FitStrategy,
+    bytes_allocated: usize,
+    peak_bytes_allocated: usize,
+    live_allocations: usize,
 }
 
 impl LinkedListAllocator {
+    /// Create a new [`LinkedListAllocator`] that uses [`FitStrategy::FirstFit`].
     pub const fn new() -> Self {
+        Self::with_strategy(FitStrategy::FirstFit)
+    }
+
+    /// Create a new [`LinkedListAllocator`] that uses the given [`FitStrategy`].
+    pub const fn with_strategy(strategy: FitStrategy) -> Self {
         LinkedListAllocator {
             head: ListNode::new(0),
+            heap_end: 0,
+            strategy,
+            bytes_allocated: 0,
+            peak_bytes_allocated: 0,
+            live_allocations: 0,
         }
     }
 
@@ -79,8 +106,63 @@ impl LinkedListAllocator {
     /// # Safety
     /// This function is unsafe because the caller must guarantee that the given heap bounds are
     /// valid and that the heap is unused. This method must be called only once.
-    pub unsafe fn init(&mut self, heap_start: usize, heap_end: usize) {
-        self.add_free_region(heap_start, heap_end);
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.add_free_region(heap_start, heap_size);
+        self.heap_end = heap_start + heap_size;
+    }
+
+    /// Add `size` bytes directly after the current end of the heap. They are merged with the last
+    /// free region if it reaches up to the end of the heap.
+    ///
+    /// # Safety
+    /// The caller must ensure that the memory right after the heap is valid and unused.
+    pub unsafe fn extend(&mut self, size: usize) {
+        self.add_free_region(self.heap_end, size);
+        self.heap_end += size;
+    }
+
+    /// Allocate a region that fits the given layout from the free list. Returns a null pointer if
+    /// there is no suitable region left.
+    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        // perform layout adjustments
+        let (size, align) = LinkedListAllocator::size_align(layout);
+
+        if let Some((region, alloc_start)) = self.find_region(size, align) {
+            let alloc_end = alloc_start.checked_add(size).expect("overflow");
+            // The node lives inside the region, so we must not touch it once we start adding the
+            // leftovers back to the list.
+            let (region_start, region_end) = (region.start_addr(), region.end_addr());
+            let front_padding = alloc_start - region_start;
+            let excess_size = region_end - alloc_end;
+            if front_padding > 0 {
+                unsafe { self.add_free_region(region_start, front_padding) };
+            }
+            if excess_size > 0 {
+                unsafe { self.add_free_region(alloc_end, excess_size) };
+            }
+
+            self.bytes_allocated += size;
+            self.peak_bytes_allocated = self.peak_bytes_allocated.max(self.bytes_allocated);
+            self.live_allocations += 1;
+            alloc_start as *mut u8
+        } else {
+            ptr::null_mut()
+        }
+    }
+
+    /// Return a region previously handed out by [`LinkedListAllocator::allocate`] to the free
+    /// list.
+    ///
+    /// # Safety
+    /// The caller must guarantee that `ptr` was allocated by this allocator with the same layout
+    /// and that it is not used anymore.
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        // perform layout adjustments
+        let (size, _) = LinkedListAllocator::size_align(layout);
+
+        self.bytes_allocated -= size;
+        self.live_allocations -= 1;
+        self.add_free_region(ptr as usize, size)
     }
 
     unsafe fn add_free_region(&mut self, address: usize, size: usize) {
@@ -88,29 +170,76 @@ impl LinkedListAllocator {
         assert_eq!(align_up(address, mem::align_of::<ListNode>()), address);
         assert!(size >= mem::size_of::<ListNode>());
 
+        // find the last region that starts before the freed one
+        let mut previous = &mut self.head;
+        while previous
+            .next
+            .as_ref()
+            .map_or(false, |next| next.start_addr() < address)
+        {
+            previous = previous.next.as_mut().unwrap();
+        }
+
         let mut node = ListNode::new(size);
-        node.next = self.head.next.take();
+        node.next = previous.next.take();
         let node_ptr = address as *mut ListNode;
         node_ptr.write(node);
-        self.head.next = Some(&mut *node_ptr);
+        let node = &mut *node_ptr;
+
+        // merge with the region after the freed one
+        match node.next.take() {
+            Some(next) if node.end_addr() == next.start_addr() => {
+                node.size += next.size;
+                node.next = next.next.take();
+            }
+            next => node.next = next,
+        }
+
+        // merge with the region before the freed one (the head is not part of the heap)
+        if previous.size > 0 && previous.end_addr() == node.start_addr() {
+            previous.size += node.size;
+            previous.next = node.next.take();
+        } else {
+            previous.next = Some(node);
+        }
+    }
+
+    /// Iterate over the free regions in order of their address.
+    fn free_regions(&self) -> impl Iterator<Item = &ListNode> {
+        let mut current = self.head.next.as_deref();
+        core::iter::from_fn(move || {
+            let region = current?;
+            current = region.next.as_deref();
+            Some(region)
+        })
     }
 
+    /// Find a region for an allocation with given size and alignment according to the
+    /// [`FitStrategy`] and remove it from the list.
+    ///
+    /// Returns the region along with the allocation start address.
     fn find_region(&mut self, size: usize, align: usize) -> Option<(&'static mut ListNode, usize)> {
-        let mut current = &mut self.head;
+        let region_start = {
+            let mut suitable_regions = self
+                .free_regions()
+                .filter(|region| Self::allocate_from_region(region, size, align).is_ok());
+            match self.strategy {
+                FitStrategy::FirstFit => suitable_regions.next(),
+                FitStrategy::BestFit => suitable_regions.min_by_key(|region| region.size),
+            }?
+            .start_addr()
+        };
 
-        while let Some(ref mut region) = current.next {
-            if let Ok(allocation_start) = Self::allocate_from_region(&region, size, align) {
-                // remove node from the list
-                let next = region.next.take();
-                let found_region = Some((current.next.take().unwrap(), allocation_start));
-                current.next = next;
-                return found_region;
-            } else {
-                current = current.next.as_mut().unwrap();
-            }
+        // remove node from the list
+        let mut current = &mut self.head;
+        while current.next.as_ref()?.start_addr() != region_start {
+            current = current.next.as_mut().unwrap();
         }
+        let region = current.next.take().unwrap();
+        current.next = region.next.take();
 
-        None
+        let allocation_start = Self::allocate_from_region(region, size, align).ok()?;
+        Some((region, allocation_start))
     }
 
     /// Try to use the given region for an allocation with given size and
@@ -118,7 +247,13 @@ impl LinkedListAllocator {
     ///
     /// Returns the allocation start address on success.
     fn allocate_from_region(region: &ListNode, size: usize, align: usize) -> Result<usize, ()> {
-        let alloc_start = align_up(region.start_addr(), align);
+        let mut alloc_start = align_up(region.start_addr(), align);
+        let front_padding = alloc_start - region.start_addr();
+        if front_padding > 0 && front_padding < mem::size_of::<ListNode>() {
+            // padding in front is too small to hold a ListNode (required because it is added back
+            // as a free region) so we move on to the next aligned address that leaves enough room
+            alloc_start = align_up(region.start_addr() + mem::size_of::<ListNode>(), align);
+        }
         let alloc_end = alloc_start.checked_add(size).ok_or(())?;
 
         if alloc_end > region.end_addr() {
@@ -151,28 +286,122 @@ impl LinkedListAllocator {
     }
 }
 
+impl HeapStatistics for LinkedListAllocator {
+    fn stats(&self) -> HeapStats {
+        HeapStats {
+            bytes_allocated: self.bytes_allocated,
+            bytes_free: self.free_regions().map(|region| region.size).sum(),
+            live_allocations: self.live_allocations,
+            free_list_length: self.free_regions().count(),
+            largest_free_block: self
+                .free_regions()
+                .map(|region| region.size)
+                .max()
+                .unwrap_or(0),
+            peak_bytes_allocated: self.peak_bytes_allocated,
+        }
+    }
+}
+
 unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        // perform layout adjustments
-        let (size, align) = LinkedListAllocator::size_align(layout);
-        let mut allocator = self.lock();
+        self.lock().allocate(layout)
+    }
 
-        if let Some((region, alloc_start)) = allocator.find_region(size, align) {
-            let alloc_end = alloc_start.checked_add(size).expect("overflow");
-            let excess_size = region.end_addr() - alloc_end;
-            if excess_size > 0 {
-                allocator.add_free_region(alloc_end, excess_size);
-            }
-            alloc_start as *mut u8
-        } else {
-            ptr::null_mut()
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.lock().deallocate(ptr, layout)
+    }
+}
+
+/// Backing memory for the tests below, aligned so that every address in it can hold a
+/// [`ListNode`].
+#[cfg(test)]
+#[repr(align(16))]
+struct TestHeap([u8; 1024]);
+
+#[cfg(test)]
+fn test_layout(size: usize) -> Layout {
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+#[test_case]
+fn test_freed_neighbouring_regions_are_merged() {
+    let mut heap = TestHeap([0; 1024]);
+    let mut allocator = LinkedListAllocator::new();
+    unsafe { allocator.init(heap.0.as_mut_ptr() as usize, heap.0.len()) };
+
+    let a = allocator.allocate(test_layout(256));
+    let b = allocator.allocate(test_layout(256));
+    let c = allocator.allocate(test_layout(256));
+    assert!(!a.is_null() && !b.is_null() && !c.is_null());
+
+    unsafe {
+        allocator.deallocate(b, test_layout(256));
+        allocator.deallocate(a, test_layout(256));
+        allocator.deallocate(c, test_layout(256));
+    }
+
+    assert_eq!(allocator.free_regions().count(), 1);
+    assert!(!allocator.allocate(test_layout(1024)).is_null());
+}
+
+#[test_case]
+fn test_free_regions_stay_sorted_by_address() {
+    let mut heap = TestHeap([0; 1024]);
+    let mut allocator = LinkedListAllocator::new();
+    unsafe { allocator.init(heap.0.as_mut_ptr() as usize, heap.0.len()) };
+
+    let allocations = [(); 6].map(|_| allocator.allocate(test_layout(64)));
+    unsafe {
+        for &allocation in allocations.iter().step_by(2).rev() {
+            allocator.deallocate(allocation, test_layout(64));
         }
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        // perform layout adjustments
-        let (size, _) = LinkedListAllocator::size_align(layout);
+    let starts = allocator.free_regions().map(ListNode::start_addr);
+    let expected = allocations
+        .iter()
+        .step_by(2)
+        .map(|&allocation| allocation as usize);
+    assert!(starts.take(3).eq(expected));
+}
+
+#[test_case]
+fn test_fit_strategies_pick_different_regions() {
+    for (strategy, expected) in [(FitStrategy::FirstFit, 0), (FitStrategy::BestFit, 2)] {
+        let mut heap = TestHeap([0; 1024]);
+        let mut allocator = LinkedListAllocator::with_strategy(strategy);
+        unsafe { allocator.init(heap.0.as_mut_ptr() as usize, heap.0.len()) };
+
+        // free regions of 128 and 64 bytes separated by allocated ones
+        let allocations = [128, 16, 64, 16].map(|size| allocator.allocate(test_layout(size)));
+        unsafe {
+            allocator.deallocate(allocations[0], test_layout(128));
+            allocator.deallocate(allocations[2], test_layout(64));
+        }
 
-        self.lock().add_free_region(ptr as usize, size)
+        assert_eq!(allocator.allocate(test_layout(64)), allocations[expected]);
     }
 }
+
+#[test_case]
+fn test_stats_track_allocations_and_free_regions() {
+    let mut heap = TestHeap([0; 1024]);
+    let mut allocator = LinkedListAllocator::new();
+    unsafe { allocator.init(heap.0.as_mut_ptr() as usize, heap.0.len()) };
+
+    let a = allocator.allocate(test_layout(256));
+    let b = allocator.allocate(test_layout(128));
+    unsafe { allocator.deallocate(a, test_layout(256)) };
+
+    let stats = allocator.stats();
+    assert_eq!(stats.bytes_allocated, 128);
+    assert_eq!(stats.bytes_free, 1024 - 128);
+    assert_eq!(stats.live_allocations, 1);
+    assert_eq!(stats.free_list_length, 2);
+    assert_eq!(stats.largest_free_block, 1024 - 256 - 128);
+    assert_eq!(stats.peak_bytes_allocated, 256 + 128);
+
+    unsafe { allocator.deallocate(b, test_layout(128)) };
+    assert_eq!(allocator.stats().live_allocations, 0);
+}
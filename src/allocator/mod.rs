@@ -1,10 +1,47 @@
 //! Various heap allocation strategies
 
+pub mod arena;
 pub mod bump;
+#[cfg(feature = "heap-debug")]
+pub mod debug;
+pub mod fixed_size_block;
 pub mod linked_list;
 
+use crate::utils::Locked;
+
+/// A snapshot of how much of a heap is in use.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Bytes handed out to allocations that are still alive, including any padding the allocator
+    /// added to them.
+    pub bytes_allocated: usize,
+    /// Bytes that can still be handed out.
+    pub bytes_free: usize,
+    /// Number of allocations that have not been freed yet.
+    pub live_allocations: usize,
+    /// Number of free regions the allocator keeps track of.
+    pub free_list_length: usize,
+    /// Size of the biggest free region. Allocations bigger than this fail even if `bytes_free`
+    /// would be enough.
+    pub largest_free_block: usize,
+    /// The highest `bytes_allocated` has been since the heap was initialized.
+    pub peak_bytes_allocated: usize,
+}
+
+/// Allocators that can report [`HeapStats`] about themselves.
+pub trait HeapStatistics {
+    fn stats(&self) -> HeapStats;
+}
+
+impl<A: HeapStatistics> Locked<A> {
+    /// Lock the allocator and return its current [`HeapStats`].
+    pub fn stats(&self) -> HeapStats {
+        self.lock().stats()
+    }
+}
+
 /// Align the given address `address` to the next multiple of `align`.
-fn align_up(address: usize, align: usize) -> usize {
+pub(crate) fn align_up(address: usize, align: usize) -> usize {
     let remainder = address % align;
     if remainder == 0 {
         address // addr already aligned
@@ -0,0 +1,196 @@
+//! I/O APIC
+//!
+//! The I/O APIC receives the interrupts of the devices on its inputs (pins) and forwards them to
+//! the local APICs. Its redirection table decides for every pin which vector is raised on which
+//! processor and how the signal is interpreted (polarity and trigger mode).
+//!
+//! Only two registers are memory mapped: one selects an internal register, the other is a window
+//! to the selected register.
+
+use crate::{
+    mmio::{map_mmio, Mmio, MmioError},
+    x86_64::address::PhysicalAddress,
+};
+
+const REGISTERS_LENGTH: usize = 0x20;
+const REGISTER_SELECT: usize = 0x00;
+const REGISTER_WINDOW: usize = 0x10;
+
+const REGISTER_ID: u32 = 0x00;
+const REGISTER_VERSION: u32 = 0x01;
+/// The redirection table starts at this register, every entry takes up two registers.
+const REGISTER_REDIRECTION_TABLE: u32 = 0x10;
+
+const ENTRY_ACTIVE_LOW: u64 = 1 << 13;
+const ENTRY_LEVEL_TRIGGERED: u64 = 1 << 15;
+const ENTRY_MASKED: u64 = 1 << 16;
+const ENTRY_DESTINATION_SHIFT: u64 = 56;
+
+/// Polarity of the signal on a pin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinPolarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+/// When the signal on a pin counts as an interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinTriggerMode {
+    Edge,
+    Level,
+}
+
+/// An entry of the redirection table.
+///
+/// The interrupt is always delivered with fixed delivery mode to the local APIC with the ID
+/// `destination` (physical destination mode).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RedirectionEntry {
+    pub vector: u8,
+    pub polarity: PinPolarity,
+    pub trigger_mode: PinTriggerMode,
+    pub masked: bool,
+    /// ID of the local APIC to deliver the interrupt to.
+    pub destination: u8,
+}
+
+impl RedirectionEntry {
+    fn to_bits(self) -> u64 {
+        let mut bits = self.vector as u64 | (self.destination as u64) << ENTRY_DESTINATION_SHIFT;
+        if self.polarity == PinPolarity::ActiveLow {
+            bits |= ENTRY_ACTIVE_LOW;
+        }
+        if self.trigger_mode == PinTriggerMode::Level {
+            bits |= ENTRY_LEVEL_TRIGGERED;
+        }
+        if self.masked {
+            bits |= ENTRY_MASKED;
+        }
+        bits
+    }
+
+    fn from_bits(bits: u64) -> RedirectionEntry {
+        RedirectionEntry {
+            vector: bits as u8,
+            polarity: match bits & ENTRY_ACTIVE_LOW {
+                0 => PinPolarity::ActiveHigh,
+                _ => PinPolarity::ActiveLow,
+            },
+            trigger_mode: match bits & ENTRY_LEVEL_TRIGGERED {
+                0 => PinTriggerMode::Edge,
+                _ => PinTriggerMode::Level,
+            },
+            masked: bits & ENTRY_MASKED != 0,
+            destination: (bits >> ENTRY_DESTINATION_SHIFT) as u8,
+        }
+    }
+}
+
+/// An I/O APIC along with the global system interrupts (GSIs) of its pins.
+pub struct IoApic {
+    registers: Mmio<u32>,
+    global_system_interrupt_base: u32,
+    pin_count: u32,
+}
+
+// The I/O APIC is not tied to a processor, only the `Mmio` holding a pointer keeps this from being
+// sent across threads automatically.
+unsafe impl Send for IoApic {}
+
+impl IoApic {
+    /// Map the registers of the I/O APIC at the given physical address. Its first pin raises the
+    /// global system interrupt `global_system_interrupt_base`.
+    ///
+    /// # Safety
+    /// The address must be the one of an I/O APIC (e.g. taken from the MADT).
+    pub unsafe fn new(
+        address: PhysicalAddress,
+        global_system_interrupt_base: u32,
+    ) -> Result<IoApic, MmioError> {
+        let mut io_apic = IoApic {
+            registers: map_mmio(address, REGISTERS_LENGTH)?,
+            global_system_interrupt_base,
+            pin_count: 0,
+        };
+        // Bits 16 to 23 hold the index of the last redirection entry.
+        io_apic.pin_count = ((io_apic.read(REGISTER_VERSION) >> 16) & 0xff) + 1;
+        Ok(io_apic)
+    }
+
+    pub fn id(&mut self) -> u8 {
+        ((self.read(REGISTER_ID) >> 24) & 0xf) as u8
+    }
+
+    /// Number of pins (and redirection entries).
+    pub fn pin_count(&self) -> u32 {
+        self.pin_count
+    }
+
+    /// The pin the global system interrupt comes in on, if it is handled by this I/O APIC.
+    pub fn pin_of(&self, global_system_interrupt: u32) -> Option<u32> {
+        global_system_interrupt
+            .checked_sub(self.global_system_interrupt_base)
+            .filter(|&pin| pin < self.pin_count)
+    }
+
+    /// # Panics
+    /// If the pin does not exist.
+    pub fn redirection(&mut self, pin: u32) -> RedirectionEntry {
+        let register = self.redirection_register(pin);
+        let low = self.read(register) as u64;
+        let high = self.read(register + 1) as u64;
+        RedirectionEntry::from_bits(high << 32 | low)
+    }
+
+    /// # Panics
+    /// If the pin does not exist.
+    pub fn set_redirection(&mut self, pin: u32, entry: RedirectionEntry) {
+        let register = self.redirection_register(pin);
+        let bits = entry.to_bits();
+        // Mask the pin while the entry is only half written.
+        self.write(register, ENTRY_MASKED as u32);
+        self.write(register + 1, (bits >> 32) as u32);
+        self.write(register, bits as u32);
+    }
+
+    /// # Panics
+    /// If the pin does not exist.
+    pub fn set_masked(&mut self, pin: u32, masked: bool) {
+        let entry = self.redirection(pin);
+        self.set_redirection(pin, RedirectionEntry { masked, ..entry });
+    }
+
+    fn redirection_register(&self, pin: u32) -> u32 {
+        assert!(
+            pin < self.pin_count,
+            "the I/O APIC has no pin {} (only {})",
+            pin,
+            self.pin_count
+        );
+        REGISTER_REDIRECTION_TABLE + 2 * pin
+    }
+
+    fn read(&mut self, register: u32) -> u32 {
+        self.registers.write_at(REGISTER_SELECT, register);
+        self.registers.read_at(REGISTER_WINDOW)
+    }
+
+    fn write(&mut self, register: u32, value: u32) {
+        self.registers.write_at(REGISTER_SELECT, register);
+        self.registers.write_at(REGISTER_WINDOW, value);
+    }
+}
+
+#[test_case]
+fn test_redirection_entry_bits_round_trip() {
+    let entry = RedirectionEntry {
+        vector: 0x30,
+        polarity: PinPolarity::ActiveLow,
+        trigger_mode: PinTriggerMode::Level,
+        masked: true,
+        destination: 3,
+    };
+    let bits = entry.to_bits();
+    assert_eq!(bits, 0x0300_0000_0001_a030);
+    assert_eq!(RedirectionEntry::from_bits(bits), entry);
+}
@@ -0,0 +1,169 @@
+//! Local APIC
+//!
+//! Every processor has a local APIC. It receives the interrupts from the I/O APICs (and other
+//! processors), hands them to the processor and has a timer of its own. Its registers are 32 bit
+//! wide and placed 16 bytes apart in a 4KiB page of memory mapped I/O.
+
+use crate::{
+    mmio::{map_mmio, Mmio, MmioError},
+    x86_64::{
+        address::PhysicalAddress,
+        instructions::{read_model_specific_register, write_model_specific_register},
+    },
+};
+
+/// Model specific register holding the physical address of the local APIC and whether it is
+/// enabled.
+const IA32_APIC_BASE: u32 = 0x1b;
+const APIC_BASE_GLOBAL_ENABLE: u64 = 1 << 11;
+
+/// Size of the register page.
+const REGISTERS_LENGTH: usize = 0x400;
+
+const REGISTER_ID: usize = 0x20;
+const REGISTER_VERSION: usize = 0x30;
+const REGISTER_TASK_PRIORITY: usize = 0x80;
+const REGISTER_END_OF_INTERRUPT: usize = 0xb0;
+const REGISTER_SPURIOUS_INTERRUPT_VECTOR: usize = 0xf0;
+const REGISTER_ERROR_STATUS: usize = 0x280;
+const REGISTER_LVT_TIMER: usize = 0x320;
+const REGISTER_LVT_ERROR: usize = 0x370;
+const REGISTER_TIMER_INITIAL_COUNT: usize = 0x380;
+const REGISTER_TIMER_CURRENT_COUNT: usize = 0x390;
+const REGISTER_TIMER_DIVIDE_CONFIGURATION: usize = 0x3e0;
+
+/// Bit in the spurious interrupt vector register that enables the local APIC.
+const SOFTWARE_ENABLE: u32 = 1 << 8;
+/// Bit in the local vector table (LVT) registers that masks the interrupt.
+const LVT_MASKED: u32 = 1 << 16;
+/// Bit in the LVT timer register that makes the timer restart once it reaches zero.
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+
+/// Whether the APIC timer fires once or periodically.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerMode {
+    OneShot,
+    Periodic,
+}
+
+/// What the bus frequency is divided by to get the frequency the APIC timer counts down with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum TimerDivide {
+    By1 = 0b1011,
+    By2 = 0b0000,
+    By4 = 0b0001,
+    By8 = 0b0010,
+    By16 = 0b0011,
+    By32 = 0b1000,
+    By64 = 0b1001,
+    By128 = 0b1010,
+}
+
+/// The local APIC of the processor we are running on.
+pub struct LocalApic {
+    registers: Mmio<u32>,
+}
+
+// The registers are only ever accessed with single volatile reads and writes of a whole register,
+// which the local APIC handles atomically. With a single processor there is no one else to share
+// it with anyway.
+unsafe impl Sync for LocalApic {}
+unsafe impl Send for LocalApic {}
+
+impl LocalApic {
+    /// Map the registers of the local APIC at the given physical address.
+    ///
+    /// # Safety
+    /// The address must be the one of the local APIC (e.g. taken from the MADT).
+    pub unsafe fn new(address: PhysicalAddress) -> Result<LocalApic, MmioError> {
+        Ok(LocalApic {
+            registers: map_mmio(address, REGISTERS_LENGTH)?,
+        })
+    }
+
+    pub fn id(&self) -> u8 {
+        (self.read(REGISTER_ID) >> 24) as u8
+    }
+
+    pub fn version(&self) -> u8 {
+        self.read(REGISTER_VERSION) as u8
+    }
+
+    /// Enable the local APIC and let it deliver interrupts of all priorities. Interrupts that
+    /// vanish before the processor accepts them raise the `spurious_vector`.
+    ///
+    /// # Safety
+    /// There must be a handler for the spurious vector, which must not acknowledge the interrupt.
+    pub unsafe fn enable(&self, spurious_vector: u8) {
+        let base = read_model_specific_register(IA32_APIC_BASE);
+        write_model_specific_register(IA32_APIC_BASE, base | APIC_BASE_GLOBAL_ENABLE);
+
+        self.write(REGISTER_TASK_PRIORITY, 0);
+        self.write(
+            REGISTER_SPURIOUS_INTERRUPT_VECTOR,
+            SOFTWARE_ENABLE | spurious_vector as u32,
+        );
+    }
+
+    /// Raise `vector` whenever the local APIC detects an error, see [`LocalApic::error_status`].
+    pub fn set_error_vector(&self, vector: u8) {
+        self.write(REGISTER_LVT_ERROR, vector as u32);
+    }
+
+    /// The errors the local APIC detected since this was called the last time.
+    pub fn error_status(&self) -> u32 {
+        // The register only gets updated by a write.
+        self.write(REGISTER_ERROR_STATUS, 0);
+        self.read(REGISTER_ERROR_STATUS)
+    }
+
+    /// Acknowledge the interrupt that is currently handled.
+    pub fn end_of_interrupt(&self) {
+        self.write(REGISTER_END_OF_INTERRUPT, 0);
+    }
+
+    /// Start the timer, raising `vector` after `initial_count` ticks of the divided bus frequency.
+    ///
+    /// The bus frequency differs from machine to machine, so the timer has to be calibrated
+    /// against another clock to get to a known period.
+    ///
+    /// With the vector `PIC_1_OFFSET + IRQ_APIC_TIMER` the handlers registered for
+    /// [`crate::interrupt::IRQ_APIC_TIMER`] run.
+    pub fn start_timer(
+        &self,
+        vector: u8,
+        initial_count: u32,
+        divide: TimerDivide,
+        mode: TimerMode,
+    ) {
+        let mode = match mode {
+            TimerMode::OneShot => 0,
+            TimerMode::Periodic => LVT_TIMER_PERIODIC,
+        };
+        self.write(REGISTER_TIMER_DIVIDE_CONFIGURATION, divide as u32);
+        self.write(REGISTER_LVT_TIMER, mode | vector as u32);
+        // Writing the initial count starts the timer.
+        self.write(REGISTER_TIMER_INITIAL_COUNT, initial_count);
+    }
+
+    pub fn stop_timer(&self) {
+        self.write(REGISTER_LVT_TIMER, LVT_MASKED);
+        self.write(REGISTER_TIMER_INITIAL_COUNT, 0);
+    }
+
+    /// Ticks left until the timer fires.
+    pub fn timer_current_count(&self) -> u32 {
+        self.read(REGISTER_TIMER_CURRENT_COUNT)
+    }
+
+    fn read(&self, register: usize) -> u32 {
+        self.registers.read_at(register)
+    }
+
+    fn write(&self, register: usize, value: u32) {
+        // `Mmio::write_at` wants exclusive access, which a register write does not need.
+        let address = self.registers.virtual_address() + register as u64;
+        unsafe { address.as_mut_ptr::<u32>().write_volatile(value) }
+    }
+}
@@ -0,0 +1,253 @@
+//! Advanced Programmable Interrupt Controller (APIC)
+//!
+//! The successor of the 8259 PICs. Devices raise their interrupts on the pins of an I/O APIC
+//! ([`io::IoApic`]), which forwards them as vectors to the local APIC ([`local::LocalApic`]) of a
+//! processor. Unlike the two 8259s this is not limited to 15 interrupt lines and works with more
+//! than one processor.
+//!
+//! ```text
+//!  ISA devices ──┐    ___________                       ____________        _____
+//!  PCI devices ──┼──>|  I/O APIC |── vector + dest. ──>| Local APIC |────> | CPU |
+//!                └──>|___________|                     |____________|      |_____|
+//!                                                          ▲
+//!                                             APIC timer ──┘
+//! ```
+//!
+//! [`init`] takes the interrupt controllers from the MADT (see [`crate::acpi::madt`]), routes
+//! every interrupt line of [`crate::interrupt`] to the pin of an I/O APIC, masks the 8259s and
+//! enables the local APIC. From then on [`crate::interrupt::register_irq`] unmasks the pin of a
+//! line once it has a handler and the end of interrupt goes to the local APIC.
+//!
+//! The ISA interrupts 0 to 15 keep the lines (and vectors) they had with the PICs, even when the
+//! MADT connects them to another pin (e.g. the timer usually comes in on pin 2). The remaining pins
+//! become lines 16 and up.
+
+pub mod io;
+pub mod local;
+
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+
+use crate::{
+    acpi::{
+        self,
+        madt::{Madt, Polarity, TriggerMode},
+        AcpiError,
+    },
+    interrupt::{self, IRQ_APIC_TIMER, IRQ_LINES, PIC_1_OFFSET, PROGRAMABLE_INTERRUPT_CONTROLERS},
+    mmio::MmioError,
+    utils::Locked,
+    x86_64::interrupts,
+};
+
+use self::{
+    io::{IoApic, PinPolarity, PinTriggerMode, RedirectionEntry},
+    local::LocalApic,
+};
+
+/// Vector the local APIC raises for interrupts that disappeared before they were delivered.
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+/// Vector the local APIC raises when it detects an error.
+pub const ERROR_VECTOR: u8 = 0xfe;
+
+/// Number of ISA interrupt lines.
+const ISA_LINES: u8 = 16;
+
+static LOCAL_APIC: OnceCell<LocalApic> = OnceCell::uninit();
+static IO_APICS: OnceCell<Locked<Vec<IoApic>>> = OnceCell::uninit();
+/// The I/O APIC pin every interrupt line is connected to.
+static ROUTES: OnceCell<[Option<Route>; IRQ_LINES as usize]> = OnceCell::uninit();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApicError {
+    /// [`acpi::init`] did not find the ACPI tables.
+    NoAcpiTables,
+    /// There is no valid MADT describing the APICs.
+    Madt(AcpiError),
+    /// The MADT does not list any I/O APIC.
+    NoIoApic,
+    /// Mapping the registers of an APIC failed.
+    Mapping(MmioError),
+    /// [`init`] was called before.
+    AlreadyInitialized,
+}
+
+/// Where an interrupt line comes in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Route {
+    /// Index into [`IO_APICS`].
+    io_apic: usize,
+    pin: u32,
+}
+
+/// Switch from the 8259 PICs over to the APIC.
+///
+/// Does nothing and returns an error if the machine has no APIC, in which case the PICs stay in
+/// charge.
+///
+/// # Panics
+/// If it is called before [`crate::memory::init`].
+pub fn init() -> Result<(), ApicError> {
+    if LOCAL_APIC.is_initialized() {
+        return Err(ApicError::AlreadyInitialized);
+    }
+    let madt = acpi::tables()
+        .ok_or(ApicError::NoAcpiTables)?
+        .madt()
+        .map_err(ApicError::Madt)?;
+
+    let local_apic =
+        unsafe { LocalApic::new(madt.local_apic_address()) }.map_err(ApicError::Mapping)?;
+    let mut io_apics = Vec::new();
+    for entry in madt.io_apics() {
+        let io_apic = unsafe { IoApic::new(entry.address, entry.global_system_interrupt_base) }
+            .map_err(ApicError::Mapping)?;
+        io_apics.push(io_apic);
+    }
+    if io_apics.is_empty() {
+        return Err(ApicError::NoIoApic);
+    }
+
+    interrupts::execute_without_interrupts(|| {
+        let destination = local_apic.id();
+        let mut routes = [None; IRQ_LINES as usize];
+        for line in 0..IRQ_LINES {
+            let (global_system_interrupt, polarity, trigger_mode) = match line_source(&madt, line) {
+                Some(source) => source,
+                None => continue,
+            };
+            let route = io_apics.iter().enumerate().find_map(|(index, io_apic)| {
+                let pin = io_apic.pin_of(global_system_interrupt)?;
+                Some(Route {
+                    io_apic: index,
+                    pin,
+                })
+            });
+            if let Some(route) = route {
+                let entry = RedirectionEntry {
+                    vector: PIC_1_OFFSET + line,
+                    polarity,
+                    trigger_mode,
+                    masked: !interrupt::has_irq_handlers(line),
+                    destination,
+                };
+                io_apics[route.io_apic].set_redirection(route.pin, entry);
+                routes[line as usize] = Some(route);
+            }
+        }
+
+        if madt.has_legacy_pics() {
+            unsafe {
+                PROGRAMABLE_INTERRUPT_CONTROLERS
+                    .lock()
+                    .write_masks(0xff, 0xff)
+            };
+        }
+        unsafe { local_apic.enable(SPURIOUS_VECTOR) };
+        local_apic.set_error_vector(ERROR_VECTOR);
+
+        ROUTES.init_once(|| routes);
+        IO_APICS.init_once(|| Locked::new(io_apics));
+        // This one goes last, as it tells everyone else that the APIC is in use.
+        LOCAL_APIC.init_once(|| local_apic);
+    });
+
+    Ok(())
+}
+
+/// Whether interrupts are delivered through the APIC (instead of the 8259 PICs).
+pub fn is_enabled() -> bool {
+    LOCAL_APIC.is_initialized()
+}
+
+/// The local APIC, once [`init`] switched over to it.
+pub fn local_apic() -> Option<&'static LocalApic> {
+    LOCAL_APIC.get()
+}
+
+/// Acknowledge the interrupt that is currently handled.
+///
+/// # Panics
+/// If the APIC is not enabled.
+pub fn end_of_interrupt() {
+    local_apic()
+        .expect("the APIC is not enabled")
+        .end_of_interrupt();
+}
+
+/// (Un)mask the I/O APIC pin of the interrupt line. Does nothing if the APIC is not enabled or the
+/// line is not connected to an I/O APIC.
+///
+/// Must be called with interrupts disabled.
+pub(crate) fn set_line_masked(line: u8, masked: bool) {
+    let route = match ROUTES.get().and_then(|routes| routes[line as usize]) {
+        Some(route) => route,
+        None => return,
+    };
+    if let Some(io_apics) = IO_APICS.get() {
+        io_apics.lock()[route.io_apic].set_masked(route.pin, masked);
+    }
+}
+
+/// The global system interrupt the line comes in on, along with its polarity and trigger mode.
+///
+/// ISA lines are connected to the pin with the same number and are active high and edge triggered,
+/// unless the MADT overrides this. Lines above use the pin with the same number and, as they
+/// belong to PCI, are active low and level triggered.
+fn line_source(madt: &Madt, line: u8) -> Option<(u32, PinPolarity, PinTriggerMode)> {
+    if line == IRQ_APIC_TIMER {
+        return None;
+    }
+
+    let (default_polarity, default_trigger_mode) = if line < ISA_LINES {
+        (PinPolarity::ActiveHigh, PinTriggerMode::Edge)
+    } else {
+        (PinPolarity::ActiveLow, PinTriggerMode::Level)
+    };
+    let source_override = madt
+        .interrupt_source_overrides()
+        .find(|source_override| source_override.source == line);
+    if let Some(source_override) = source_override {
+        let polarity = match source_override.polarity {
+            Polarity::ConformsToBus => default_polarity,
+            Polarity::ActiveHigh => PinPolarity::ActiveHigh,
+            Polarity::ActiveLow => PinPolarity::ActiveLow,
+        };
+        let trigger_mode = match source_override.trigger_mode {
+            TriggerMode::ConformsToBus => default_trigger_mode,
+            TriggerMode::Edge => PinTriggerMode::Edge,
+            TriggerMode::Level => PinTriggerMode::Level,
+        };
+        return Some((
+            source_override.global_system_interrupt,
+            polarity,
+            trigger_mode,
+        ));
+    }
+
+    // The pin with the same number may be taken by another ISA interrupt.
+    let pin_taken = madt
+        .interrupt_source_overrides()
+        .any(|source_override| source_override.global_system_interrupt == line as u32);
+    if pin_taken {
+        return None;
+    }
+    Some((line as u32, default_polarity, default_trigger_mode))
+}
+
+#[test_case]
+fn test_keyboard_line_is_routed_through_the_io_apic() {
+    use crate::interrupt::IRQ_KEYBOARD;
+
+    // QEMU has an APIC, so `crate::init` switched over to it.
+    assert!(is_enabled());
+    let route = ROUTES.get().unwrap()[IRQ_KEYBOARD as usize].unwrap();
+    let entry = interrupts::execute_without_interrupts(|| {
+        IO_APICS.get().unwrap().lock()[route.io_apic].redirection(route.pin)
+    });
+    assert_eq!(entry.vector, PIC_1_OFFSET + IRQ_KEYBOARD);
+    assert_eq!(entry.destination, local_apic().unwrap().id());
+    // The keyboard registered its handler, so the pin is unmasked.
+    assert!(!entry.masked);
+    assert_eq!(ROUTES.get().unwrap()[IRQ_APIC_TIMER as usize], None);
+}
@@ -0,0 +1,158 @@
+//! Copy-on-write mappings
+//!
+//! Sharing a page copy-on-write maps the frame behind it a second time, read-only in both places
+//! and marked with [`PageTableEntryFlags::COPY_ON_WRITE`]. Every additional mapping holds a
+//! reference to the frame in the [`crate::frame_allocator::FrameAllocator`], so it stays alive as
+//! long as one of them does. The first write to such a page causes a page fault that is resolved
+//! by [`handle_write_fault`], which gives the page a private copy of the frame.
+
+use core::ptr;
+
+use crate::{
+    memory,
+    vma::PageFaultError,
+    x86_64::{
+        address::VirtualAddress,
+        paging::{
+            Mapper, MappingError, Page, PageFrame, PageInner, PageSize, PageTableEntryFlags,
+            Size1GiB, Size2MiB, Size4KiB,
+        },
+    },
+};
+
+/// Map the frame behind `page` in `source` to the same page in `target`, copy-on-write in both.
+///
+/// Pages that are read-only to begin with are shared as they are, as there is nothing to copy
+/// them for.
+///
+/// # Safety
+/// Anything referencing the page in `source` mutably must not be used anymore, the page is
+/// read-only after this call.
+pub unsafe fn share_page(
+    source: &mut impl Mapper,
+    target: &mut impl Mapper,
+    page: Page,
+) -> Result<(), MappingError> {
+    let (frame, flags) = source.translate_page(page)?;
+    let flags = flags
+        - (PageTableEntryFlags::ACCESSED
+            | PageTableEntryFlags::DIRTY
+            | PageTableEntryFlags::HUGE_PAGE);
+    let shared_flags = if flags.contains(PageTableEntryFlags::WRITABLE) {
+        (flags - PageTableEntryFlags::WRITABLE) | PageTableEntryFlags::COPY_ON_WRITE
+    } else {
+        flags
+    };
+
+    // The page becomes read-only in `source` before `target` can see the frame, so that nothing
+    // writes to the frame while it is shared. If mapping it in `target` fails, the page just stays
+    // copy-on-write in `source` and its first write makes it writable again.
+    source.update_flags(page, shared_flags)?;
+    source.frame_allocator().lock().share_frame(frame);
+    target.map_to(page, frame, shared_flags).map_err(|error| {
+        source.frame_allocator().lock().deallocate_frame(frame);
+        error
+    })
+}
+
+/// Try to resolve a write to a present page at the given address by giving the page a private,
+/// writable copy of its frame.
+///
+/// If no other page references the frame anymore, the page is just made writable again.
+pub fn handle_write_fault(address: VirtualAddress) -> Result<(), PageFaultError> {
+    // The kernel's page tables are shared with every address space, so we need to make sure they
+    // are not being modified while we walk them.
+    let _kernel_mapper = memory::memory_mapper()
+        .try_lock()
+        .ok_or(PageFaultError::Busy)?;
+    let mut mapper = unsafe { memory::active_memory_mapper() };
+
+    let (page, frame, flags) =
+        mapped_page_containing(&mapper, address).map_err(PageFaultError::Mapping)?;
+    if !flags.contains(PageTableEntryFlags::COPY_ON_WRITE) {
+        return Err(PageFaultError::ProtectionViolation);
+    }
+
+    unsafe { copy_page(&mut mapper, page, frame, flags) }
+}
+
+/// Replace the copy-on-write `frame` behind `page` by a writable copy of it.
+///
+/// Gives up with `PageFaultError::Busy` if the frame allocator is in use, as the fault might have
+/// happened while it was locked.
+///
+/// # Safety
+/// The page must be mapped to `frame` with the given flags.
+unsafe fn copy_page(
+    mapper: &mut impl Mapper,
+    page: Page,
+    frame: PageFrame,
+    flags: PageTableEntryFlags,
+) -> Result<(), PageFaultError> {
+    let private_flags =
+        (flags - PageTableEntryFlags::COPY_ON_WRITE) | PageTableEntryFlags::WRITABLE;
+    let mut frame_allocator = mapper
+        .frame_allocator()
+        .try_lock()
+        .ok_or(PageFaultError::Busy)?;
+    if frame_allocator.reference_count(frame) == 1 {
+        drop(frame_allocator);
+        return mapper
+            .update_flags(page, private_flags)
+            .map_err(PageFaultError::Mapping);
+    }
+
+    let copy = match frame {
+        PageFrame::Normal(_) => frame_allocator.allocate_normal_frame(),
+        PageFrame::Huge(_) => frame_allocator.allocate_huge_frame(),
+        PageFrame::Giant(_) => frame_allocator.allocate_giant_frame(),
+    }
+    .ok_or(PageFaultError::Mapping(MappingError::FrameAllocationFailed))?;
+    drop(frame_allocator);
+
+    ptr::copy_nonoverlapping(
+        memory::physical_to_virtual(frame.start_address()).as_ptr::<u8>(),
+        memory::physical_to_virtual(copy.start_address()).as_mut_ptr::<u8>(),
+        frame.size() as usize,
+    );
+
+    match mapper.remap(page, copy, private_flags) {
+        Ok(shared) => {
+            // Drop the reference this page held to the shared frame.
+            mapper.frame_allocator().lock().deallocate_frame(shared);
+            Ok(())
+        }
+        Err(error) => {
+            mapper.frame_allocator().lock().deallocate_frame(copy);
+            Err(PageFaultError::Mapping(error))
+        }
+    }
+}
+
+/// Find the page (of whatever size) that maps the given address.
+fn mapped_page_containing(
+    mapper: &impl Mapper,
+    address: VirtualAddress,
+) -> Result<(Page, PageFrame, PageTableEntryFlags), MappingError> {
+    let pages = [
+        Page::Normal(PageInner::containing_address(
+            address.align_down(Size4KiB::SIZE),
+        )),
+        Page::Huge(PageInner::containing_address(
+            address.align_down(Size2MiB::SIZE),
+        )),
+        Page::Giant(PageInner::containing_address(
+            address.align_down(Size1GiB::SIZE),
+        )),
+    ];
+
+    for page in pages {
+        match mapper.translate_page(page) {
+            // the page is part of a bigger one
+            Err(MappingError::ParentEntryHugePage) => continue,
+            result => return result.map(|(frame, flags)| (page, frame, flags)),
+        }
+    }
+
+    Err(MappingError::PageNotMapped)
+}
@@ -0,0 +1,132 @@
+//! Buffers for direct memory access
+//!
+//! Devices that access memory on their own (e.g. ATA bus mastering or virtio) work with physical
+//! addresses and often need a buffer to be physically contiguous, aligned or placed below a
+//! certain address. [`allocate_contiguous`] hands out such buffers as [`DmaBuffer`]s, which also
+//! tell where the kernel can access them. That is the mapping of the complete physical memory set
+//! up by the bootloader, so no additional pages need to be mapped. The frames go back to the frame
+//! allocator once the buffer is dropped.
+
+use core::ptr;
+
+use crate::{
+    frame_allocator::FrameZone,
+    memory::{self, FRAME_ALLOCATOR},
+    x86_64::{
+        address::{PhysicalAddress, VirtualAddress},
+        paging::{PageFrame, PageFrameInner, PageSize, Size4KiB},
+    },
+};
+
+/// Physically contiguous memory along with the virtual address the kernel can access it at.
+///
+/// Dropping the buffer frees its frames, so it has to be kept around for as long as a device may
+/// still access it.
+#[derive(Debug, PartialEq, Eq)]
+#[must_use]
+pub struct DmaBuffer {
+    physical_address: PhysicalAddress,
+    virtual_address: VirtualAddress,
+    frame_count: usize,
+}
+
+impl DmaBuffer {
+    /// The address to hand to the device.
+    pub fn physical_address(&self) -> PhysicalAddress {
+        self.physical_address
+    }
+
+    /// The address the kernel can access the buffer at.
+    pub fn virtual_address(&self) -> VirtualAddress {
+        self.virtual_address
+    }
+
+    /// Size of the buffer in bytes.
+    pub fn size(&self) -> usize {
+        self.frame_count * Size4KiB::SIZE as usize
+    }
+
+    pub fn as_mut_ptr<T>(&self) -> *mut T {
+        self.virtual_address.as_mut_ptr()
+    }
+
+    /// Zone of the physical memory the buffer lies in.
+    pub fn zone(&self) -> FrameZone {
+        if self.physical_address < FrameZone::Dma.end() {
+            FrameZone::Dma
+        } else {
+            FrameZone::Normal
+        }
+    }
+}
+
+/// Allocate a zeroed buffer of `count` physically contiguous 4KiB frames that ends at or below
+/// `max_address` and starts at a multiple of `alignment` bytes.
+///
+/// Returns None if there is no such run of free frames.
+///
+/// # Panics
+/// If it is called before [`memory::init`] or the alignment is not a power of two.
+pub fn allocate_contiguous(
+    count: usize,
+    max_address: PhysicalAddress,
+    alignment: u64,
+) -> Option<DmaBuffer> {
+    let first = FRAME_ALLOCATOR
+        .lock()
+        .allocate_contiguous(count, max_address, alignment)?;
+    let physical_address = first.start_address();
+    let buffer = DmaBuffer {
+        physical_address,
+        virtual_address: memory::physical_to_virtual(physical_address),
+        frame_count: count,
+    };
+
+    // The frames can hold data of whoever used them before.
+    unsafe { ptr::write_bytes(buffer.as_mut_ptr::<u8>(), 0, buffer.size()) };
+    Some(buffer)
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        let first = PageFrame::Normal(PageFrameInner::containing_address(self.physical_address));
+        FRAME_ALLOCATOR
+            .lock()
+            .deallocate_contiguous_frames(first, self.frame_count);
+    }
+}
+
+#[test_case]
+fn test_buffer_is_zeroed_and_mapped_to_its_physical_address() {
+    use crate::{frame_allocator::MAX_PHYSICAL_MEMORY, x86_64::paging::Mapper};
+
+    let max_address = PhysicalAddress::new(MAX_PHYSICAL_MEMORY);
+    let buffer = allocate_contiguous(2, max_address, Size4KiB::SIZE).unwrap();
+    assert_eq!(buffer.size(), 2 * Size4KiB::SIZE as usize);
+    assert_eq!(buffer.zone(), FrameZone::Normal);
+    assert_eq!(
+        memory::memory_mapper()
+            .lock()
+            .translate_address(buffer.virtual_address()),
+        Some(buffer.physical_address())
+    );
+
+    let bytes = buffer.as_mut_ptr::<u8>();
+    unsafe { assert!((0..buffer.size()).all(|offset| *bytes.add(offset) == 0)) };
+}
+
+#[test_case]
+fn test_buffer_below_16_mib_comes_from_the_dma_zone() {
+    let buffer = allocate_contiguous(4, FrameZone::Dma.end(), 16 * 1024).unwrap();
+    assert_eq!(buffer.zone(), FrameZone::Dma);
+    assert_eq!(buffer.physical_address().as_u64() % (16 * 1024), 0);
+}
+
+#[test_case]
+fn test_dropping_a_buffer_frees_its_frames() {
+    let free_frames = FRAME_ALLOCATOR.lock().free_frames();
+    let buffer = allocate_contiguous(3, FrameZone::Dma.end(), Size4KiB::SIZE).unwrap();
+    assert_eq!(FRAME_ALLOCATOR.lock().free_frames(), free_frames - 3);
+    drop(buffer);
+    assert_eq!(FRAME_ALLOCATOR.lock().free_frames(), free_frames);
+}
@@ -0,0 +1,152 @@
+//! Minimal support for reading 64 bit ELF files
+//!
+//! Only the parts needed to find out how the segments of an ELF image are laid out in memory are
+//! supported.
+
+use bitflags::bitflags;
+use core::{mem::size_of, ptr};
+
+/// The magic bytes every ELF file starts with.
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+/// Value of `ElfHeader::class` for 64 bit files.
+const ELF_CLASS_64: u8 = 2;
+
+/// The header at the very start of an ELF file.
+#[derive(Debug)]
+#[repr(C)]
+pub struct ElfHeader {
+    magic: [u8; 4],
+    class: u8,
+    data: u8,
+    version: u8,
+    os_abi: u8,
+    abi_version: u8,
+    _padding: [u8; 7],
+    file_type: u16,
+    machine: u16,
+    version_2: u32,
+    entry_point: u64,
+    program_header_offset: u64,
+    section_header_offset: u64,
+    flags: u32,
+    header_size: u16,
+    program_header_entry_size: u16,
+    program_header_count: u16,
+    section_header_entry_size: u16,
+    section_header_count: u16,
+    section_names_index: u16,
+}
+
+impl ElfHeader {
+    /// Whether this looks like the header of a 64 bit ELF file whose program headers we can read.
+    pub fn is_valid(&self) -> bool {
+        self.magic == ELF_MAGIC
+            && self.class == ELF_CLASS_64
+            && self.program_header_entry_size as usize >= size_of::<ProgramHeader>()
+    }
+
+    /// The program headers describing the segments of the image.
+    ///
+    /// The entries are `program_header_entry_size` bytes apart, which may be more than the size of
+    /// a [`ProgramHeader`] (the rest of every entry is ignored). Nothing is returned if it is less.
+    ///
+    /// # Safety
+    /// The header must be the start of an ELF image that is completely in memory (at least up to
+    /// the end of the program headers).
+    pub unsafe fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + '_ {
+        let start =
+            (self as *const ElfHeader as *const u8).add(self.program_header_offset as usize);
+        let entry_size = self.program_header_entry_size as usize;
+        let count = if entry_size < size_of::<ProgramHeader>() {
+            0
+        } else {
+            self.program_header_count as usize
+        };
+        (0..count).map(move |index| {
+            ptr::read_unaligned(start.add(index * entry_size) as *const ProgramHeader)
+        })
+    }
+}
+
+/// Kind of a segment. Only the values we care about are listed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct SegmentType(u32);
+
+impl SegmentType {
+    /// The segment is loaded into memory.
+    pub const LOAD: SegmentType = SegmentType(1);
+}
+
+bitflags! {
+    /// Access permissions of a segment.
+    pub struct SegmentFlags: u32 {
+        const EXECUTABLE = 1 << 0;
+        const WRITABLE   = 1 << 1;
+        const READABLE   = 1 << 2;
+    }
+}
+
+/// Describes one segment of an ELF image.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct ProgramHeader {
+    pub segment_type: SegmentType,
+    flags: u32,
+    /// Offset of the segment's data in the file.
+    pub offset: u64,
+    /// Address at which the segment is loaded.
+    pub virtual_address: u64,
+    pub physical_address: u64,
+    /// Number of bytes of the segment that are stored in the file.
+    pub file_size: u64,
+    /// Number of bytes of the segment in memory. Anything after `file_size` is zeroed.
+    pub memory_size: u64,
+    pub alignment: u64,
+}
+
+impl ProgramHeader {
+    pub fn flags(&self) -> SegmentFlags {
+        SegmentFlags::from_bits_truncate(self.flags)
+    }
+}
+
+#[test_case]
+fn test_program_headers_are_read_with_the_entry_size_of_the_file() {
+    use core::mem;
+
+    const ENTRY_SIZE: usize = size_of::<ProgramHeader>() + 8;
+    #[repr(C)]
+    struct Image {
+        header: ElfHeader,
+        entries: [[u8; ENTRY_SIZE]; 2],
+    }
+
+    let mut image: Image = unsafe { mem::zeroed() };
+    image.header.magic = ELF_MAGIC;
+    image.header.class = ELF_CLASS_64;
+    image.header.program_header_offset = size_of::<ElfHeader>() as u64;
+    image.header.program_header_entry_size = ENTRY_SIZE as u16;
+    image.header.program_header_count = 2;
+    for (index, entry) in image.entries.iter_mut().enumerate() {
+        let program_header = ProgramHeader {
+            segment_type: SegmentType::LOAD,
+            flags: SegmentFlags::READABLE.bits(),
+            offset: 0,
+            virtual_address: 0x1000 * (index as u64 + 1),
+            physical_address: 0,
+            file_size: 0,
+            memory_size: 0x1000,
+            alignment: 0x1000,
+        };
+        unsafe { ptr::write_unaligned(entry.as_mut_ptr() as *mut ProgramHeader, program_header) };
+    }
+
+    assert!(image.header.is_valid());
+    let addresses = unsafe { image.header.program_headers() }.map(|header| header.virtual_address);
+    assert!(addresses.eq([0x1000, 0x2000]));
+
+    image.header.program_header_entry_size = size_of::<ProgramHeader>() as u16 - 1;
+    assert!(!image.header.is_valid());
+    assert_eq!(unsafe { image.header.program_headers() }.count(), 0);
+}
@@ -0,0 +1,561 @@
+//! Physical memory frame allocation
+
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+
+use core::{ptr, slice};
+
+use crate::x86_64::{
+    address::{PhysicalAddress, VirtualAddress},
+    paging::{PageFrame, PageFrameInner, PageSize, Size1GiB, Size2MiB, Size4KiB},
+};
+
+/// Amount of physical memory the [`FrameAllocator`] is able to keep track of. Usable regions
+/// beyond this address are ignored.
+pub const MAX_PHYSICAL_MEMORY: u64 = 4 * 1024 * 1024 * 1024; // 4 GiB
+
+const MAX_FRAMES: usize = (MAX_PHYSICAL_MEMORY / Size4KiB::SIZE) as usize;
+const FRAMES_PER_WORD: usize = u64::BITS as usize;
+const BITMAP_WORDS: usize = MAX_FRAMES / FRAMES_PER_WORD;
+
+/// End of the memory that legacy ISA DMA can reach.
+const DMA_ZONE_END: u64 = 16 * 1024 * 1024; // 16 MiB
+const ZONE_COUNT: usize = 2;
+
+/// Parts of physical memory that allocations are taken from in a fixed order of preference.
+///
+/// Some devices can only reach the low parts of physical memory. To keep that memory available
+/// for them, frames are taken from the highest zone that has one free, unless the caller asks for
+/// memory below a certain address (see [`FrameAllocator::allocate_contiguous`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameZone {
+    /// Memory below 16 MiB, e.g. for ISA DMA.
+    Dma,
+    /// Everything else the allocator manages. As all of it lies below [`MAX_PHYSICAL_MEMORY`] it
+    /// can be used for 32 bit DMA as well.
+    Normal,
+}
+
+impl FrameZone {
+    /// Zones in the order they are used for allocations.
+    const BY_PREFERENCE: [FrameZone; ZONE_COUNT] = [FrameZone::Normal, FrameZone::Dma];
+
+    /// First address of the zone.
+    pub fn start(&self) -> PhysicalAddress {
+        match self {
+            FrameZone::Dma => PhysicalAddress::new(0),
+            FrameZone::Normal => PhysicalAddress::new(DMA_ZONE_END),
+        }
+    }
+
+    /// End (exclusive) of the zone.
+    pub fn end(&self) -> PhysicalAddress {
+        match self {
+            FrameZone::Dma => PhysicalAddress::new(DMA_ZONE_END),
+            FrameZone::Normal => PhysicalAddress::new(MAX_PHYSICAL_MEMORY),
+        }
+    }
+
+    /// The zone the frame at the given index lies in.
+    fn of_frame(index: usize) -> FrameZone {
+        if index < index_of(FrameZone::Dma.end()) {
+            FrameZone::Dma
+        } else {
+            FrameZone::Normal
+        }
+    }
+}
+
+/// A bitmap based physical frame allocator.
+///
+/// Every 4KiB frame of physical memory is represented by a single bit in the bitmap. A set bit
+/// means that the frame is free, an unset bit means that the frame is either in use or not usable
+/// at all. This way an all zero bitmap (what we get from [`FrameAllocator::new`]) represents a
+/// machine with no usable memory and the bitmap can live in `.bss`.
+///
+/// ```text
+///  frame index  0   1   2   3   4   5   6   7   8   ...
+///             ┌───┬───┬───┬───┬───┬───┬───┬───┬───┬─────
+///  bitmap     │ 0 │ 0 │ 1 │ 1 │ 0 │ 1 │ 1 │ 1 │ 1 │ ...
+///             └───┴───┴───┴───┴───┴───┴───┴───┴───┴─────
+///                       ▲
+///                       └─── next_free of the zone (everything before this is in use)
+/// ```
+///
+/// # Operations
+///
+/// * *Allocating a frame*: We scan the bitmap a word (64 frames) at a time starting at the
+/// `next_free` of a zone, skipping words that are completely in use. The [`FrameZone`]s are
+/// searched one after the other, so low memory is only handed out once everything above it is in
+/// use. Afterwards `next_free` moves on to the first free frame of the zone.
+/// * *Allocating contiguous frames*: We look for a run of set bits of the required length that
+/// starts at the required alignment and ends below the required address. This is how
+/// [`Size2MiB`] and [`Size1GiB`] frames are handed out.
+/// * *Deallocating a frame*: We set the bits for the frame again and move the `next_free` of its
+/// zone back if the frame lies before it. A frame that was not usable at boot (e.g. a page table the bootloader
+/// set up) is managed by the allocator from then on.
+/// * *Sharing a frame*: A frame that is mapped more than once (e.g. for copy-on-write) gets an
+/// additional reference with [`FrameAllocator::share_frame`]. Deallocating such a frame only drops
+/// one reference, the frame is freed once the last one is gone. The reference counts take a byte
+/// per frame and live in frames that [`FrameAllocator::init`] takes from the usable memory.
+///
+/// # Limitation(s)
+/// * The bitmaps have a fixed size, so only the first [`MAX_PHYSICAL_MEMORY`] bytes of physical
+/// memory are managed.
+/// * Finding contiguous frames is a linear search over the bitmap.
+/// * A frame can have at most [`u8::MAX`] additional references.
+pub struct FrameAllocator {
+    bitmap: [u64; BITMAP_WORDS],
+    /// Same layout as `bitmap`, a set bit means that the frame is managed by the allocator.
+    managed: [u64; BITMAP_WORDS],
+    /// Number of references to every frame on top of the one of its original owner, one entry
+    /// for each of the `frame_count` frames.
+    additional_references: &'static mut [u8],
+    frame_count: usize,
+    usable_frames: usize,
+    free_frames: usize,
+    /// Index of the first frame of every [`FrameZone`] that might be free.
+    next_free: [usize; ZONE_COUNT],
+}
+
+impl FrameAllocator {
+    /// Create a new [`FrameAllocator`] that considers every frame to be in use.
+    pub const fn new() -> Self {
+        FrameAllocator {
+            bitmap: [0; BITMAP_WORDS],
+            managed: [0; BITMAP_WORDS],
+            additional_references: &mut [],
+            frame_count: 0,
+            usable_frames: 0,
+            free_frames: 0,
+            // The first frame of every zone, in the order of the `FrameZone` variants.
+            next_free: [0, (DMA_ZONE_END / Size4KiB::SIZE) as usize],
+        }
+    }
+
+    /// Mark all the frames that are `Usable` in the bootloader's memory map as free and set aside
+    /// enough of them to hold the reference count of every frame.
+    ///
+    /// # Safety
+    /// This function is unsafe because the caller must guarantee that the passed memory map is
+    /// valid. The main requirement is that all frames that are marked as `Usable` in it are really
+    /// unused. All of physical memory has to be mapped at `physical_memory_offset`. This method
+    /// must be called only once.
+    ///
+    /// # Panics
+    /// If there is not enough usable memory for the reference counts.
+    pub unsafe fn init(
+        &mut self,
+        memory_map: &'static MemoryMap,
+        physical_memory_offset: VirtualAddress,
+    ) {
+        let usable_regions = memory_map
+            .iter()
+            .filter(|region| region.region_type == MemoryRegionType::Usable);
+
+        for region in usable_regions {
+            let start = region.range.start_frame_number as usize;
+            let end = (region.range.end_frame_number as usize).min(MAX_FRAMES);
+            for index in start..end {
+                self.mark_managed(index);
+                self.mark_free(index);
+            }
+            self.frame_count = self.frame_count.max(end);
+        }
+        self.usable_frames = self.free_frames;
+
+        let table_frames = div_ceil(self.frame_count, Size4KiB::SIZE as usize);
+        let table = self
+            .allocate_contiguous_frames(table_frames)
+            .expect("no memory left for the frame reference counts");
+        let table_pointer: *mut u8 =
+            (physical_memory_offset + table.start_address().as_u64()).as_mut_ptr();
+        ptr::write_bytes(table_pointer, 0, self.frame_count);
+        self.additional_references = slice::from_raw_parts_mut(table_pointer, self.frame_count);
+    }
+
+    /// Number of frames the allocator manages, i.e. all frames that were usable at boot along
+    /// with the ones that were given back to it later on (e.g. page tables of the bootloader).
+    pub fn usable_frames(&self) -> usize {
+        self.usable_frames
+    }
+
+    /// Number of frames that are currently free.
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    /// Number of frames in the given zone that are currently free.
+    pub fn free_frames_in(&self, zone: FrameZone) -> usize {
+        let (start, end) = self.zone_indices(zone);
+        (start..end).filter(|&index| self.is_free(index)).count()
+    }
+
+    /// Retrun next available [`PageFrame`] of 4KiB size
+    pub fn allocate_normal_frame(&mut self) -> Option<PageFrame> {
+        let index = self.allocate_run(1, 1, self.frame_count)?;
+        Some(PageFrame::Normal(frame_at(index)))
+    }
+
+    /// Return next available [`PageFrame`] of 2MiB size
+    pub fn allocate_huge_frame(&mut self) -> Option<PageFrame> {
+        let count = frames_in::<Size2MiB>();
+        let index = self.allocate_run(count, count, self.frame_count)?;
+        Some(PageFrame::Huge(frame_at(index)))
+    }
+
+    /// Return next available [`PageFrame`] of 1GiB size
+    pub fn allocate_giant_frame(&mut self) -> Option<PageFrame> {
+        let count = frames_in::<Size1GiB>();
+        let index = self.allocate_run(count, count, self.frame_count)?;
+        Some(PageFrame::Giant(frame_at(index)))
+    }
+
+    /// Allocate `count` physically contiguous 4KiB frames and return the first one of them.
+    pub fn allocate_contiguous_frames(&mut self, count: usize) -> Option<PageFrame> {
+        self.allocate_contiguous(
+            count,
+            PhysicalAddress::new(MAX_PHYSICAL_MEMORY),
+            Size4KiB::SIZE,
+        )
+    }
+
+    /// Allocate `count` physically contiguous 4KiB frames that end at or below `max_address` and
+    /// return the first one of them. The first frame starts at a multiple of `alignment` bytes.
+    ///
+    /// Use e.g. `FrameZone::Dma.end()` as `max_address` for a device that can only reach the
+    /// first 16 MiB of memory.
+    ///
+    /// # Panics
+    /// If the alignment is not a power of two.
+    pub fn allocate_contiguous(
+        &mut self,
+        count: usize,
+        max_address: PhysicalAddress,
+        alignment: u64,
+    ) -> Option<PageFrame> {
+        assert!(
+            alignment.is_power_of_two(),
+            "alignment {:#x} is not a power of two",
+            alignment
+        );
+        if count == 0 {
+            return None;
+        }
+
+        let alignment = (alignment.max(Size4KiB::SIZE) / Size4KiB::SIZE) as usize;
+        let end = index_of(max_address).min(self.frame_count);
+        let index = self.allocate_run(count, alignment, end)?;
+        Some(PageFrame::Normal(frame_at(index)))
+    }
+
+    /// Return a [`PageFrame`] of any size back to the allocator.
+    ///
+    /// If the frame is shared (see [`FrameAllocator::share_frame`]) only one reference to it is
+    /// dropped and the frame stays in use.
+    ///
+    /// # Panics
+    /// If any part of the frame is already free (i.e. on a double free).
+    pub fn deallocate_frame(&mut self, frame: PageFrame) {
+        let index = index_of(frame.start_address());
+        if let Some(references) = self.additional_references.get_mut(index) {
+            if *references > 0 {
+                *references -= 1;
+                return;
+            }
+        }
+
+        let count = (frame.size() / Size4KiB::SIZE) as usize;
+        self.deallocate_run(index, count);
+    }
+
+    /// Add a reference to a frame that is in use, so that it takes one more
+    /// [`FrameAllocator::deallocate_frame`] until the frame is actually freed.
+    ///
+    /// # Panics
+    /// If the frame is free or already has the maximum number of references.
+    pub fn share_frame(&mut self, frame: PageFrame) {
+        let index = index_of(frame.start_address());
+        assert!(
+            index < self.additional_references.len() && !self.is_free(index),
+            "sharing frame {:?} that is not in use",
+            frame
+        );
+        self.additional_references[index] = self.additional_references[index]
+            .checked_add(1)
+            .expect("too many references to a single frame");
+    }
+
+    /// Number of references to the given frame. A frame that is in use has at least one.
+    pub fn reference_count(&self, frame: PageFrame) -> usize {
+        let index = index_of(frame.start_address());
+        if index >= MAX_FRAMES || self.is_free(index) {
+            return 0;
+        }
+        1 + self.additional_references.get(index).copied().unwrap_or(0) as usize
+    }
+
+    /// Return `count` contiguous 4KiB frames starting at `first` back to the allocator.
+    ///
+    /// # Panics
+    /// If any of the frames is already free (i.e. on a double free).
+    pub fn deallocate_contiguous_frames(&mut self, first: PageFrame, count: usize) {
+        self.deallocate_run(index_of(first.start_address()), count);
+    }
+
+    /// Allocate a run of `count` frames starting at a multiple of `alignment` that ends before the
+    /// frame at index `end`, from the most preferred zone that has one.
+    fn allocate_run(&mut self, count: usize, alignment: usize, end: usize) -> Option<usize> {
+        let (zone, start) = FrameZone::BY_PREFERENCE.iter().find_map(|&zone| {
+            let start = self.find_free_run(zone, count, alignment, end)?;
+            Some((zone, start))
+        })?;
+
+        for index in start..start + count {
+            self.mark_used(index);
+        }
+
+        // Move on to the first frame of the zone that is still free.
+        let zone_end = self.zone_indices(zone).1;
+        let next_free = self.next_free_frame(self.next_free[zone as usize], zone_end);
+        self.next_free[zone as usize] = next_free.unwrap_or(zone_end);
+        Some(start)
+    }
+
+    fn deallocate_run(&mut self, start: usize, count: usize) {
+        for index in start..start + count {
+            assert!(
+                index < MAX_FRAMES && !self.is_free(index),
+                "deallocating frame {:#x} that is not in use",
+                index as u64 * Size4KiB::SIZE
+            );
+            if !self.is_managed(index) {
+                self.mark_managed(index);
+                self.usable_frames += 1;
+            }
+            self.mark_free(index);
+
+            let next_free = &mut self.next_free[FrameZone::of_frame(index) as usize];
+            *next_free = (*next_free).min(index);
+        }
+    }
+
+    /// Find the first run of `count` free frames in the zone that ends before the frame at index
+    /// `end` and starts at a multiple of `alignment`.
+    fn find_free_run(
+        &self,
+        zone: FrameZone,
+        count: usize,
+        alignment: usize,
+        end: usize,
+    ) -> Option<usize> {
+        let end = self.zone_indices(zone).1.min(end);
+        // Everything before `next_free` is in use.
+        let mut start = align_up(self.next_free[zone as usize], alignment);
+
+        while start + count <= end {
+            match (start..start + count).find(|&index| !self.is_free(index)) {
+                None => return Some(start),
+                Some(used) => start = align_up(self.next_free_frame(used + 1, end)?, alignment),
+            }
+        }
+
+        None
+    }
+
+    /// Find the first free frame between the frames at index `start` and `end`, skipping words of
+    /// the bitmap that are completely in use.
+    fn next_free_frame(&self, start: usize, end: usize) -> Option<usize> {
+        let mut index = start;
+        while index < end {
+            let word = self.bitmap[index / FRAMES_PER_WORD] >> (index % FRAMES_PER_WORD);
+            if word != 0 {
+                let free = index + word.trailing_zeros() as usize;
+                return Some(free).filter(|&free| free < end);
+            }
+            index = (index / FRAMES_PER_WORD + 1) * FRAMES_PER_WORD;
+        }
+
+        None
+    }
+
+    /// Indices of the first frame of the zone and of the first frame after it.
+    fn zone_indices(&self, zone: FrameZone) -> (usize, usize) {
+        let start = index_of(zone.start()).min(self.frame_count);
+        let end = index_of(zone.end()).min(self.frame_count);
+        (start, end)
+    }
+
+    fn is_free(&self, index: usize) -> bool {
+        self.bitmap[index / FRAMES_PER_WORD] & (1 << (index % FRAMES_PER_WORD)) != 0
+    }
+
+    fn mark_free(&mut self, index: usize) {
+        self.bitmap[index / FRAMES_PER_WORD] |= 1 << (index % FRAMES_PER_WORD);
+        self.free_frames += 1;
+    }
+
+    fn mark_used(&mut self, index: usize) {
+        self.bitmap[index / FRAMES_PER_WORD] &= !(1 << (index % FRAMES_PER_WORD));
+        self.free_frames -= 1;
+    }
+
+    fn is_managed(&self, index: usize) -> bool {
+        self.managed[index / FRAMES_PER_WORD] & (1 << (index % FRAMES_PER_WORD)) != 0
+    }
+
+    fn mark_managed(&mut self, index: usize) {
+        self.managed[index / FRAMES_PER_WORD] |= 1 << (index % FRAMES_PER_WORD);
+    }
+}
+
+/// Number of 4KiB frames that make up a frame of size `S`.
+fn frames_in<S: PageSize>() -> usize {
+    (S::SIZE / Size4KiB::SIZE) as usize
+}
+
+fn frame_at<S: PageSize>(index: usize) -> PageFrameInner<S> {
+    PageFrameInner::containing_address(PhysicalAddress::new(index as u64 * Size4KiB::SIZE))
+}
+
+fn index_of(address: PhysicalAddress) -> usize {
+    (address.as_u64() / Size4KiB::SIZE) as usize
+}
+
+fn align_up(value: usize, alignment: usize) -> usize {
+    div_ceil(value, alignment) * alignment
+}
+
+fn div_ceil(value: usize, divisor: usize) -> usize {
+    (value + divisor - 1) / divisor
+}
+
+#[test_case]
+fn test_deallocated_frame_is_handed_out_again() {
+    use crate::memory::FRAME_ALLOCATOR;
+
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let free_frames = frame_allocator.free_frames();
+
+    let frame = frame_allocator.allocate_normal_frame().unwrap();
+    assert_eq!(frame_allocator.free_frames(), free_frames - 1);
+
+    frame_allocator.deallocate_frame(frame);
+    assert_eq!(frame_allocator.free_frames(), free_frames);
+    assert_eq!(frame_allocator.allocate_normal_frame(), Some(frame));
+    frame_allocator.deallocate_frame(frame);
+}
+
+#[test_case]
+fn test_huge_frame_is_aligned_and_contiguous() {
+    use crate::memory::FRAME_ALLOCATOR;
+
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let free_frames = frame_allocator.free_frames();
+
+    let frame = frame_allocator.allocate_huge_frame().unwrap();
+    assert_eq!(frame.start_address().as_u64() % Size2MiB::SIZE, 0);
+    assert_eq!(frame_allocator.free_frames(), free_frames - 512);
+
+    frame_allocator.deallocate_frame(frame);
+    assert_eq!(frame_allocator.free_frames(), free_frames);
+}
+
+#[test_case]
+fn test_contiguous_frames_are_adjacent() {
+    use crate::memory::FRAME_ALLOCATOR;
+
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let first = frame_allocator.allocate_contiguous_frames(4).unwrap();
+    let start = index_of(first.start_address());
+    for index in start..start + 4 {
+        assert!(!frame_allocator.is_free(index));
+    }
+
+    frame_allocator.deallocate_contiguous_frames(first, 4);
+    for index in start..start + 4 {
+        assert!(frame_allocator.is_free(index));
+    }
+}
+
+#[test_case]
+fn test_shared_frame_is_freed_with_its_last_reference() {
+    use crate::memory::FRAME_ALLOCATOR;
+
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let free_frames = frame_allocator.free_frames();
+
+    let frame = frame_allocator.allocate_normal_frame().unwrap();
+    frame_allocator.share_frame(frame);
+    frame_allocator.share_frame(frame);
+    assert_eq!(frame_allocator.reference_count(frame), 3);
+
+    frame_allocator.deallocate_frame(frame);
+    frame_allocator.deallocate_frame(frame);
+    assert_eq!(frame_allocator.reference_count(frame), 1);
+    assert_eq!(frame_allocator.free_frames(), free_frames - 1);
+
+    frame_allocator.deallocate_frame(frame);
+    assert_eq!(frame_allocator.reference_count(frame), 0);
+    assert_eq!(frame_allocator.free_frames(), free_frames);
+}
+
+#[test_case]
+fn test_contiguous_frames_respect_max_address_and_alignment() {
+    use crate::memory::FRAME_ALLOCATOR;
+
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let free_dma_frames = frame_allocator.free_frames_in(FrameZone::Dma);
+    let alignment = 64 * 1024;
+
+    let first = frame_allocator
+        .allocate_contiguous(8, FrameZone::Dma.end(), alignment)
+        .unwrap();
+    let start = first.start_address().as_u64();
+    assert_eq!(start % alignment, 0);
+    assert!(start + 8 * Size4KiB::SIZE <= FrameZone::Dma.end().as_u64());
+    assert_eq!(
+        frame_allocator.free_frames_in(FrameZone::Dma),
+        free_dma_frames - 8
+    );
+
+    frame_allocator.deallocate_contiguous_frames(first, 8);
+    assert_eq!(
+        frame_allocator.free_frames_in(FrameZone::Dma),
+        free_dma_frames
+    );
+    assert_eq!(
+        frame_allocator.allocate_contiguous(0, FrameZone::Dma.end(), alignment),
+        None
+    );
+}
+
+#[test_case]
+fn test_frames_come_from_the_dma_zone_last() {
+    use crate::memory::FRAME_ALLOCATOR;
+
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let free_dma_frames = frame_allocator.free_frames_in(FrameZone::Dma);
+    assert!(free_dma_frames > 0);
+
+    let frame = frame_allocator.allocate_normal_frame().unwrap();
+    assert!(frame.start_address() >= FrameZone::Normal.start());
+    assert_eq!(
+        frame_allocator.free_frames_in(FrameZone::Dma),
+        free_dma_frames
+    );
+    frame_allocator.deallocate_frame(frame);
+}
+
+#[test_case]
+fn test_next_free_moves_past_frames_of_the_normal_zone() {
+    use crate::memory::FRAME_ALLOCATOR;
+
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let frame = frame_allocator.allocate_normal_frame().unwrap();
+    let index = index_of(frame.start_address());
+    assert_eq!(FrameZone::of_frame(index), FrameZone::Normal);
+    assert!(frame_allocator.next_free[FrameZone::Normal as usize] > index);
+
+    frame_allocator.deallocate_frame(frame);
+    assert_eq!(frame_allocator.next_free[FrameZone::Normal as usize], index);
+}
@@ -5,42 +5,36 @@
 //! * kernel/user mode switching
 //! * Task state Segment loading
 
+use core::ptr;
 use lazy_static::lazy_static;
 
-use crate::x86_64::{
-    address::VirtualAddress,
-    descriptor::Descriptor,
-    gdt::GlobalDescriptorTable,
-    segmentation::{set_code_segment_selector, SegmentSelector},
-    tss::{load_task_state_segment, TaskStateSegment},
+use crate::{
+    kernel_stack::{self, DEFAULT_STACK_PAGES},
+    x86_64::{
+        address::VirtualAddress,
+        descriptor::Descriptor,
+        gdt::GlobalDescriptorTable,
+        segmentation::{set_code_segment_selector, SegmentSelector},
+        tss::{load_task_state_segment, TaskStateSegment, NUMBER_OF_INTERRUPT_STACKS},
+    },
 };
 
 /// Index of a well known stack that we ought to switch to before we go about handling a Double
 /// Fault.
 pub const INTERRUPT_STACK_TABLE_INDEX_DOUBLE_FAULT: u16 = 0;
 
-lazy_static! {
-    static ref TASK_STATE_SEGMENT: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[INTERRUPT_STACK_TABLE_INDEX_DOUBLE_FAULT as usize] = {
-            // We haven’t implemented memory management yet, so we don’t have a proper way to
-            // allocate a new stack. Instead, we use a static mut array as stack storage for now.
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtualAddress::from_ptr(unsafe { &STACK });
-            let stack_end = stack_start + STACK_SIZE as u64;
-            stack_end
-        };
-        tss
-    };
-}
+/// The interrupt stacks are allocated using the memory system, which is only set up after the GDT
+/// is loaded. So the task state segment starts without stacks and they are filled in by
+/// [`init_interrupt_stacks`].
+static mut TASK_STATE_SEGMENT: TaskStateSegment = TaskStateSegment::new();
 
 lazy_static! {
     static ref GLOBAL_DESCRIPTOR_TABLE: (GlobalDescriptorTable, SegmentSelector, SegmentSelector) = {
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TASK_STATE_SEGMENT));
+        let tss_selector = gdt.add_entry(Descriptor::tss_segment(unsafe {
+            &*ptr::addr_of!(TASK_STATE_SEGMENT)
+        }));
 
         (gdt, code_selector, tss_selector)
     };
@@ -52,15 +46,38 @@ lazy_static! {
 /// * Load the Global Descriptor Table Register with the address of the GDT.
 /// * Reload the code segment register to make use of the GDT (that was initialized in the previous
 /// step)
-/// * Load the task state segment.
+///
+/// The task state segment is loaded by [`init_interrupt_stacks`] once the memory system is up.
 pub fn init() {
     GLOBAL_DESCRIPTOR_TABLE.0.load();
     unsafe {
         // We need to reload the code segment register to switch to the new GDT. The old value can
         // point to an invalid GDT location.
         set_code_segment_selector(GLOBAL_DESCRIPTOR_TABLE.1);
-        // We need to tell the CPU to use the new TSS segment. The old value can point to an
-        // invalid TSS location.
-        load_task_state_segment(GLOBAL_DESCRIPTOR_TABLE.2);
     }
 }
+
+/// Allocate the interrupt stacks and load the task state segment that holds them.
+///
+/// Every entry of the interrupt stack table gets its own stack with a guard page below it, so that
+/// overflowing one of them causes a fault instead of corrupting whatever is mapped below the stack.
+///
+/// # Panics
+/// If it is called before [`init`] and [`crate::memory::init`], or a stack can't be allocated.
+///
+/// # Safety
+/// Must only be called once, the stacks of the task state segment must not change while they can
+/// be in use.
+pub unsafe fn init_interrupt_stacks() {
+    let mut interrupt_stack_table = [VirtualAddress::zero(); NUMBER_OF_INTERRUPT_STACKS];
+    for stack_top in interrupt_stack_table.iter_mut() {
+        *stack_top = kernel_stack::allocate_stack(DEFAULT_STACK_PAGES)
+            .expect("failed to allocate an interrupt stack")
+            .top();
+    }
+    TASK_STATE_SEGMENT.interrupt_stack_table = interrupt_stack_table;
+
+    // We need to tell the CPU to use the new TSS segment. The old value can point to an invalid
+    // TSS location.
+    load_task_state_segment(GLOBAL_DESCRIPTOR_TABLE.2);
+}
@@ -2,15 +2,17 @@
 use lazy_static::lazy_static;
 
 use crate::{
-    error, errorln,
+    apic, error, errorln,
     gdt::INTERRUPT_STACK_TABLE_INDEX_DOUBLE_FAULT,
-    keyboard,
     pic8258::ChainedPics,
-    utils::halt_loop,
+    utils::{halt_loop, Locked},
+    vma,
     x86_64::{
-        idt::{ExceptionStackFrame, InterruptDescriptorTable, PageFaultErrorCode},
+        idt::{
+            ExceptionStackFrame, InterruptDescriptorTable, PageFaultErrorCode, SelectorErrorCode,
+        },
         instructions::read_control_register_2,
-        port::Port,
+        interrupts,
     },
 };
 
@@ -19,7 +21,20 @@ pub const PIC_1_OFFSET: u8 = 32;
 /// Offset of the secondary PIC in the PIC chain.
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
-const PS_2_CONTROLLER_PORT: u16 = 0x60;
+/// Number of hardware interrupt lines. Line `n` raises the interrupt vector `PIC_1_OFFSET + n`.
+///
+/// Lines 0 to 15 are the ISA interrupts. The lines above only exist once the [`apic`] is in use:
+/// lines 16 to 23 are the remaining pins of the I/O APIC (usually PCI) and the last one is
+/// [`IRQ_APIC_TIMER`].
+pub const IRQ_LINES: u8 = 25;
+/// Interrupt line of the timer.
+pub const IRQ_TIMER: u8 = 0;
+/// Interrupt line of the PS/2 keyboard.
+pub const IRQ_KEYBOARD: u8 = 1;
+/// Interrupt line of the local APIC's timer, see [`apic::local::LocalApic::start_timer`].
+pub const IRQ_APIC_TIMER: u8 = 24;
+/// Maximum number of handlers that can share a single interrupt line.
+pub const MAX_HANDLERS_PER_IRQ: usize = 4;
 
 /// Loads the IDT into the CPU.
 pub fn init() {
@@ -31,24 +46,56 @@ lazy_static! {
     ///
     /// Thi has the following handlers setup for following interrupts:
     /// * Breakpoint - Just prints the message along with the [`ExceptionStackFrame`]
+    /// * Debug, Non Maskable Interrupt and Overflow - Print the message along with the
+    /// [`ExceptionStackFrame`] and continue.
+    /// * Every other exception (divide error, invalid opcode, general protection fault, ...) -
+    /// Prints the message along with the [`ExceptionStackFrame`] and the decoded error code, if
+    /// there is one. Afterwards it just loops indefinitely, as returning would only run into the
+    /// same exception again.
     /// * Double Fault - Just prints the message along with the [`ExceptionStackFrame`] and then
     /// loops indefinitely.
-    /// * Page Fault - Prints the message along with the [`ExceptionStackFrame`] along with the
+    /// * Page Fault - Faults inside one of the [`vma::KERNEL_AREAS`] are resolved by mapping the
+    /// page. Otherwise it prints the message along with the [`ExceptionStackFrame`] along with the
     /// [`VirtualAddress`] that caused the page fault. Afterwards it just loops indefinitely.
-    /// * Timer Interrupt - Notifyes the [`PROGRAMABLE_INTERRUPT_CONTROLERS`] that it is the end of
-    /// interrupt and nothing else. i.e. it effectively does nothing.
-    /// * Keyboard Interrupt - Makes use of the Colemak keypoard layout configuration to print the
-    /// keycode to the screen. It prints the defult keycode if the key is not printable.
+    /// * Hardware Interrupts - Run every handler registered for the line with [`register_irq`] and
+    /// then notify the [`PROGRAMABLE_INTERRUPT_CONTROLERS`] or the local APIC (once the [`apic`]
+    /// is in use) that it is the end of interrupt.
+    /// * APIC spurious interrupts - Ignored.
+    /// * APIC errors - Prints the error status of the local APIC.
     pub static ref INTERRUPT_DESCRIPTOR_TABLE: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
+        idt.set_divide_error_handler(divide_error_handler);
+        idt.set_debug_handler(debug_handler);
+        idt.set_non_maskable_interrupt_handler(non_maskable_interrupt_handler);
         idt.set_breakpoint_handler(breakpoint_handler);
+        idt.set_overflow_handler(overflow_handler);
+        idt.set_bound_range_exceeded_handler(bound_range_exceeded_handler);
+        idt.set_invalid_opcode_handler(invalid_opcode_handler);
+        idt.set_device_not_available_handler(device_not_available_handler);
         unsafe {
             idt.set_double_fault_handler(double_fault_handler)
                 .set_stack_index(INTERRUPT_STACK_TABLE_INDEX_DOUBLE_FAULT);
         }
+        idt.set_coprocessor_segment_overrun_handler(coprocessor_segment_overrun_handler);
+        idt.set_invalid_tss_handler(invalid_tss_handler);
+        idt.set_segment_not_present_handler(segment_not_present_handler);
+        idt.set_stack_segment_fault_handler(stack_segment_fault_handler);
+        idt.set_general_protection_fault_handler(general_protection_fault_handler);
         idt.set_page_fault_handler(page_fault_handler);
-        idt.set_interrupt_handler(InterruptIndex::Timer.as_u8(), timer_interrupt_handler);
-        idt.set_interrupt_handler(InterruptIndex::Keyboard.as_u8(), keyboard_interrupt_handler);
+        idt.set_x87_floating_point_handler(x87_floating_point_handler);
+        idt.set_alignment_check_handler(alignment_check_handler);
+        idt.set_machine_check_handler(machine_check_handler);
+        idt.set_simd_floating_point_handler(simd_floating_point_handler);
+        idt.set_virtualization_handler(virtualization_handler);
+        idt.set_control_protection_handler(control_protection_handler);
+        idt.set_hypervisor_injection_handler(hypervisor_injection_handler);
+        idt.set_vmm_communication_handler(vmm_communication_handler);
+        idt.set_security_handler(security_handler);
+        for (line, &stub) in IRQ_STUBS.iter().enumerate() {
+            idt.set_interrupt_handler(PIC_1_OFFSET + line as u8, stub);
+        }
+        idt.set_interrupt_handler(apic::SPURIOUS_VECTOR, apic_spurious_interrupt_handler);
+        idt.set_interrupt_handler(apic::ERROR_VECTOR, apic_error_handler);
         idt
     };
 }
@@ -61,10 +108,87 @@ lazy_static! {
 
 // Exception Handlers
 
+/// Define a handler that prints the name of the exception along with the [`ExceptionStackFrame`]
+/// (and the error code if the exception has one) and then either returns (`continue`) or loops
+/// indefinitely (`halt`).
+macro_rules! exception_handler {
+    ($name:ident, $message:literal, continue) => {
+        extern "x86-interrupt" fn $name(stack_frame: ExceptionStackFrame) {
+            errorln!("EXCEPTION: {}\n{:#?}", $message, stack_frame);
+        }
+    };
+    ($name:ident, $message:literal, halt) => {
+        extern "x86-interrupt" fn $name(stack_frame: ExceptionStackFrame) {
+            errorln!("EXCEPTION: {}\n{:#?}", $message, stack_frame);
+            halt_loop();
+        }
+    };
+    ($name:ident, $message:literal, $error_code:ty, halt) => {
+        extern "x86-interrupt" fn $name(stack_frame: ExceptionStackFrame, error_code: $error_code) {
+            errorln!(
+                "EXCEPTION: {}\nError Code: {:#x?}\n{:#?}",
+                $message,
+                error_code,
+                stack_frame
+            );
+            halt_loop();
+        }
+    };
+}
+
+exception_handler!(divide_error_handler, "DIVIDE ERROR", halt);
+exception_handler!(debug_handler, "DEBUG", continue);
+exception_handler!(
+    non_maskable_interrupt_handler,
+    "NON MASKABLE INTERRUPT",
+    continue
+);
+exception_handler!(overflow_handler, "OVERFLOW", continue);
+exception_handler!(bound_range_exceeded_handler, "BOUND RANGE EXCEEDED", halt);
+exception_handler!(invalid_opcode_handler, "INVALID OPCODE", halt);
+exception_handler!(device_not_available_handler, "DEVICE NOT AVAILABLE", halt);
+exception_handler!(
+    coprocessor_segment_overrun_handler,
+    "COPROCESSOR SEGMENT OVERRUN",
+    halt
+);
+exception_handler!(invalid_tss_handler, "INVALID TSS", SelectorErrorCode, halt);
+exception_handler!(
+    segment_not_present_handler,
+    "SEGMENT NOT PRESENT",
+    SelectorErrorCode,
+    halt
+);
+exception_handler!(
+    stack_segment_fault_handler,
+    "STACK SEGMENT FAULT",
+    SelectorErrorCode,
+    halt
+);
+exception_handler!(
+    general_protection_fault_handler,
+    "GENERAL PROTECTION FAULT",
+    SelectorErrorCode,
+    halt
+);
+exception_handler!(x87_floating_point_handler, "x87 FLOATING POINT", halt);
+exception_handler!(alignment_check_handler, "ALIGNMENT CHECK", u64, halt);
+exception_handler!(simd_floating_point_handler, "SIMD FLOATING POINT", halt);
+exception_handler!(virtualization_handler, "VIRTUALIZATION", halt);
+exception_handler!(control_protection_handler, "CONTROL PROTECTION", u64, halt);
+exception_handler!(hypervisor_injection_handler, "HYPERVISOR INJECTION", halt);
+exception_handler!(vmm_communication_handler, "VMM COMMUNICATION", u64, halt);
+exception_handler!(security_handler, "SECURITY", u64, halt);
+
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: ExceptionStackFrame) {
     errorln!("EXCEPTION: BREAKPOINT ERROR\n{:#?}", stack_frame);
 }
 
+extern "x86-interrupt" fn machine_check_handler(stack_frame: ExceptionStackFrame) -> ! {
+    errorln!("EXCEPTION: MACHINE CHECK\n{:#?}", stack_frame);
+    halt_loop();
+}
+
 extern "x86-interrupt" fn double_fault_handler(
     stack_frame: ExceptionStackFrame,
     _error_code: u64,
@@ -78,6 +202,10 @@ extern "x86-interrupt" fn page_fault_handler(
     error_code: PageFaultErrorCode,
 ) {
     let responsible_virtual_address = read_control_register_2();
+    let unhandled_reason = match vma::handle_page_fault(responsible_virtual_address, error_code) {
+        Ok(()) => return,
+        Err(reason) => reason,
+    };
 
     errorln!("EXCEPTION: PAGE FAULT");
     errorln!("EXCEPTION: PAGE FAULT: Error Code: {:?}", error_code);
@@ -85,46 +213,167 @@ extern "x86-interrupt" fn page_fault_handler(
         "EXCEPTION: PAGE FAULT: Virtual address responsible {:?}",
         responsible_virtual_address
     );
+    errorln!("EXCEPTION: PAGE FAULT: Not handled: {:?}", unhandled_reason);
     errorln!("EXCEPTION: PAGE FAULT: Stack Frame\n{:#?}", stack_frame);
     halt_loop();
 }
 
-// Hardware PIC Interrupt Handlers
+// Hardware Interrupt Handlers
+
+/// A function that is run whenever its interrupt line is raised.
+///
+/// It runs with interrupts disabled, so it must not block and should do as little work as
+/// possible. Lines can be shared, so a handler has to cope with being called for an interrupt of
+/// another device.
+pub type IrqHandler = fn();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    /// There is no interrupt line with this number.
+    InvalidLine,
+    /// The line already has [`MAX_HANDLERS_PER_IRQ`] handlers.
+    LineFull,
+}
 
-/// Hardware PIC Intrerrupt Handler offsets
-#[derive(Debug, Clone, Copy)]
-#[repr(u8)]
-pub enum InterruptIndex {
-    Timer = PIC_1_OFFSET,
-    Keyboard,
+/// Proof that a handler was registered with [`register_irq`]. It is needed to [`unregister`] the
+/// handler again.
+#[derive(Debug, PartialEq, Eq)]
+pub struct IrqRegistration {
+    line: u8,
+    slot: usize,
 }
 
-impl InterruptIndex {
-    fn as_u8(self) -> u8 {
-        self as u8
+impl IrqRegistration {
+    pub fn line(&self) -> u8 {
+        self.line
     }
 }
 
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: ExceptionStackFrame) {
-    unsafe {
-        PROGRAMABLE_INTERRUPT_CONTROLERS
-            .lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+/// The handlers registered for every interrupt line.
+///
+/// It is only locked with interrupts disabled, so an interrupt can never find it locked.
+static IRQ_HANDLERS: Locked<[[Option<IrqHandler>; MAX_HANDLERS_PER_IRQ]; IRQ_LINES as usize]> =
+    Locked::new([[None; MAX_HANDLERS_PER_IRQ]; IRQ_LINES as usize]);
+
+/// Run `handler` every time the interrupt `line` is raised, after the handlers that were
+/// registered for it before. Sending the end of interrupt is taken care of.
+pub fn register_irq(line: u8, handler: IrqHandler) -> Result<IrqRegistration, IrqError> {
+    if line >= IRQ_LINES {
+        return Err(IrqError::InvalidLine);
     }
+
+    interrupts::execute_without_interrupts(|| {
+        let mut irq_handlers = IRQ_HANDLERS.lock();
+        let handlers = &mut irq_handlers[line as usize];
+        let slot = handlers
+            .iter()
+            .position(Option::is_none)
+            .ok_or(IrqError::LineFull)?;
+        handlers[slot] = Some(handler);
+        apic::set_line_masked(line, false);
+        Ok(IrqRegistration { line, slot })
+    })
+}
+
+/// Stop running the handler for its interrupt line.
+pub fn unregister(registration: IrqRegistration) {
+    interrupts::execute_without_interrupts(|| {
+        let mut irq_handlers = IRQ_HANDLERS.lock();
+        let handlers = &mut irq_handlers[registration.line as usize];
+        handlers[registration.slot] = None;
+        if handlers.iter().all(Option::is_none) {
+            apic::set_line_masked(registration.line, true);
+        }
+    });
 }
 
-extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: ExceptionStackFrame) {
-    let port = Port::new(PS_2_CONTROLLER_PORT);
-    let scancode: u8 = unsafe { port.read() };
-    keyboard::add_scancode(scancode);
+/// Whether any handler is registered for the line.
+pub(crate) fn has_irq_handlers(line: u8) -> bool {
+    interrupts::execute_without_interrupts(|| {
+        IRQ_HANDLERS.lock()[line as usize]
+            .iter()
+            .any(Option::is_some)
+    })
+}
+
+/// Run the handlers registered for the line and acknowledge the interrupt.
+fn dispatch_irq(line: u8) {
+    // Copy the handlers out, so they are free to (un)register handlers themselves.
+    let handlers = IRQ_HANDLERS.lock()[line as usize];
+    for handler in handlers.iter().flatten() {
+        handler();
+    }
 
+    end_of_interrupt(line);
+}
+
+/// Tell the interrupt controller that the interrupt on the line was handled.
+fn end_of_interrupt(line: u8) {
+    if apic::is_enabled() {
+        apic::end_of_interrupt();
+        return;
+    }
     unsafe {
         PROGRAMABLE_INTERRUPT_CONTROLERS
             .lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
+            .notify_end_of_interrupt(PIC_1_OFFSET + line);
     }
 }
 
+/// The local APIC raises this for interrupts that went away before they could be delivered. They
+/// must not be acknowledged.
+extern "x86-interrupt" fn apic_spurious_interrupt_handler(_stack_frame: ExceptionStackFrame) {}
+
+extern "x86-interrupt" fn apic_error_handler(_stack_frame: ExceptionStackFrame) {
+    if let Some(local_apic) = apic::local_apic() {
+        errorln!("APIC ERROR: Error Status: {:#x}", local_apic.error_status());
+        local_apic.end_of_interrupt();
+    }
+}
+
+/// Define an interrupt handler for every line that hands the interrupt to [`dispatch_irq`] and
+/// collect them in `IRQ_STUBS`, ordered by their line.
+macro_rules! irq_stubs {
+    ($($line:literal => $name:ident),* $(,)?) => {
+        $(
+            extern "x86-interrupt" fn $name(_stack_frame: ExceptionStackFrame) {
+                dispatch_irq($line);
+            }
+        )*
+
+        const IRQ_STUBS: [extern "x86-interrupt" fn(ExceptionStackFrame); IRQ_LINES as usize] =
+            [$($name),*];
+    };
+}
+
+irq_stubs!(
+    0 => irq_0_handler,
+    1 => irq_1_handler,
+    2 => irq_2_handler,
+    3 => irq_3_handler,
+    4 => irq_4_handler,
+    5 => irq_5_handler,
+    6 => irq_6_handler,
+    7 => irq_7_handler,
+    8 => irq_8_handler,
+    9 => irq_9_handler,
+    10 => irq_10_handler,
+    11 => irq_11_handler,
+    12 => irq_12_handler,
+    13 => irq_13_handler,
+    14 => irq_14_handler,
+    15 => irq_15_handler,
+    16 => irq_16_handler,
+    17 => irq_17_handler,
+    18 => irq_18_handler,
+    19 => irq_19_handler,
+    20 => irq_20_handler,
+    21 => irq_21_handler,
+    22 => irq_22_handler,
+    23 => irq_23_handler,
+    24 => irq_24_handler,
+);
+
 // utilities
 
 /// Cause a page fault to occur
@@ -148,3 +397,31 @@ fn test_breakpoint_exception() {
     // Execution continues => Breakpoint handler is working
     invoke_breakpoint_exception();
 }
+
+#[test_case]
+fn test_irq_handlers_share_a_line_and_can_be_unregistered() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    fn count_call() {
+        CALLS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    // Line 20 is not used by anything in QEMU's default setup.
+    let line = 20;
+    let registrations = [(); MAX_HANDLERS_PER_IRQ].map(|_| register_irq(line, count_call).unwrap());
+    assert_eq!(register_irq(line, count_call), Err(IrqError::LineFull));
+    assert_eq!(
+        register_irq(IRQ_LINES, count_call),
+        Err(IrqError::InvalidLine)
+    );
+
+    interrupts::execute_without_interrupts(|| dispatch_irq(line));
+    assert_eq!(CALLS.load(Ordering::SeqCst), MAX_HANDLERS_PER_IRQ);
+
+    for registration in registrations {
+        unregister(registration);
+    }
+    interrupts::execute_without_interrupts(|| dispatch_irq(line));
+    assert_eq!(CALLS.load(Ordering::SeqCst), MAX_HANDLERS_PER_IRQ);
+}
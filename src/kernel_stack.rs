@@ -0,0 +1,141 @@
+//! Kernel stacks
+//!
+//! Every stack handed out here gets its own range of the kernel's virtual address space (see
+//! [`crate::virtual_memory`]) that starts with a guard page which is never mapped. Stacks grow
+//! downwards, so a stack overflow runs into the guard page and causes a page fault instead of
+//! silently overwriting whatever lies below the stack.
+//!
+//! ```text
+//! ┌───────┬───────────────┬───────┬─────────────────────┬─────
+//! │ guard │ stack 0       │ guard │ stack 1             │ ...
+//! └───────┴───────────────┴───────┴─────────────────────┴─────
+//!                         ▲                             ▲
+//!                         top of stack 0                top of stack 1
+//! ```
+
+use crate::{
+    memory,
+    virtual_memory::{RangeOwner, KERNEL_VIRTUAL_MEMORY},
+    x86_64::{
+        address::VirtualAddress,
+        paging::{Mapper, MappingError, Page, PageInner, PageSize, PageTableEntryFlags, Size4KiB},
+    },
+};
+
+/// Number of pages a stack gets if there is no reason to pick something else.
+pub const DEFAULT_STACK_PAGES: u64 = 5;
+
+const PAGE_SIZE: u64 = Size4KiB::SIZE;
+
+/// A mapped kernel stack with an unmapped guard page right below it.
+#[derive(Debug, PartialEq, Eq)]
+pub struct KernelStack {
+    bottom: VirtualAddress,
+    top: VirtualAddress,
+}
+
+impl KernelStack {
+    /// The address right after the end of the stack. This is the value the stack pointer starts
+    /// out with.
+    pub fn top(&self) -> VirtualAddress {
+        self.top
+    }
+
+    /// Lowest address of the stack. The guard page ends right before it.
+    pub fn bottom(&self) -> VirtualAddress {
+        self.bottom
+    }
+
+    /// Start of the guard page below the stack.
+    pub fn guard_page(&self) -> VirtualAddress {
+        VirtualAddress::new(self.bottom.as_u64() - PAGE_SIZE)
+    }
+
+    /// Iterate over the (mapped) pages of the stack.
+    fn pages(&self) -> impl Iterator<Item = Page> {
+        (self.bottom.as_u64()..self.top.as_u64())
+            .step_by(PAGE_SIZE as usize)
+            .map(|address| {
+                Page::Normal(PageInner::containing_address(VirtualAddress::new(address)))
+            })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackAllocationError {
+    /// The stack was asked to have no pages at all.
+    EmptyStack,
+    /// There is no room for the stack in the kernel's virtual address space.
+    OutOfVirtualMemory,
+    /// Mapping the pages of the stack failed.
+    Mapping(MappingError),
+}
+
+/// Allocate a kernel stack of `pages` pages. All pages of the stack are mapped right away, so the
+/// stack can be used in places where a page fault can't be handled (e.g. the double fault handler).
+///
+/// # Panics
+/// If it is called before [`memory::init`].
+pub fn allocate_stack(pages: u64) -> Result<KernelStack, StackAllocationError> {
+    if pages == 0 {
+        return Err(StackAllocationError::EmptyStack);
+    }
+
+    let range = KERNEL_VIRTUAL_MEMORY
+        .lock()
+        .allocate((pages + 1) * PAGE_SIZE, PAGE_SIZE, RangeOwner::KernelStack)
+        .map_err(|_| StackAllocationError::OutOfVirtualMemory)?;
+    // The range starts with the guard page.
+    let bottom = range.start.as_u64() + PAGE_SIZE;
+    let stack = KernelStack {
+        bottom: VirtualAddress::new(bottom),
+        top: VirtualAddress::new(bottom + pages * PAGE_SIZE),
+    };
+
+    let mut mapper = memory::memory_mapper().lock();
+    for page in stack.pages() {
+        if let Err(error) = map_stack_page(&mut *mapper, page) {
+            // Give back whatever got mapped so far.
+            drop(mapper);
+            unsafe { free_stack(stack) };
+            return Err(StackAllocationError::Mapping(error));
+        }
+    }
+
+    Ok(stack)
+}
+
+/// Unmap the pages of the stack, free their frames and give back its virtual memory.
+///
+/// # Safety
+/// The caller must ensure that the stack is not in use anymore.
+pub unsafe fn free_stack(stack: KernelStack) {
+    let mut mapper = memory::memory_mapper().lock();
+    for page in stack.pages() {
+        if let Ok(frame) = mapper.unmap(page) {
+            mapper.frame_allocator().lock().deallocate_frame(frame);
+        }
+    }
+    drop(mapper);
+
+    KERNEL_VIRTUAL_MEMORY
+        .lock()
+        .free(stack.guard_page())
+        .expect("the stack was not allocated by allocate_stack");
+}
+
+/// Map the stack page to a newly allocated, non-executable frame.
+fn map_stack_page(mapper: &mut impl Mapper, page: Page) -> Result<(), MappingError> {
+    let frame = mapper
+        .frame_allocator()
+        .lock()
+        .allocate_normal_frame()
+        .ok_or(MappingError::FrameAllocationFailed)?;
+    let flags = PageTableEntryFlags::PRESENT
+        | PageTableEntryFlags::WRITABLE
+        | PageTableEntryFlags::NO_EXECUTE;
+    unsafe { mapper.map_to(page, frame, flags) }.map_err(|error| {
+        mapper.frame_allocator().lock().deallocate_frame(frame);
+        error
+    })
+}
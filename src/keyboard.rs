@@ -9,11 +9,15 @@ use crossbeam_queue::ArrayQueue;
 use futures_util::{stream::Stream, task::AtomicWaker, StreamExt};
 
 use crate::{
+    interrupt::{self, IRQ_KEYBOARD},
     print, println,
     ps2_keyboard_decoder::{ColemakDHm, DecodedKey, HandleControl, Keyboard, ScancodeSet1},
     warn,
+    x86_64::port::Port,
 };
 
+const PS_2_CONTROLLER_PORT: u16 = 0x60;
+
 /// Pre-allocated fixed size lock-free queue.
 ///
 /// Note: We need a one time heap allocation for this to work. Rust can't do it yet for statics so
@@ -21,6 +25,22 @@ use crate::{
 static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
 static WAKER: AtomicWaker = AtomicWaker::new();
 
+/// Start handing the scancodes from the keyboard interrupt to [`add_scancode`].
+///
+/// # Panics
+/// If the keyboard's interrupt line has no room for another handler.
+pub fn init() {
+    interrupt::register_irq(IRQ_KEYBOARD, handle_keyboard_interrupt)
+        .expect("registering the keyboard interrupt handler failed");
+}
+
+/// Read the scancode of the key that was pressed or released from the PS/2 controller.
+fn handle_keyboard_interrupt() {
+    let port = Port::new(PS_2_CONTROLLER_PORT);
+    let scancode: u8 = unsafe { port.read() };
+    add_scancode(scancode);
+}
+
 /// Given a scancode it adds it to the global scancode queue for processing.
 ///
 /// It requires the global static `SCANCODE_QUEUE` to be initialized to work properly.
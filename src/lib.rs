@@ -2,11 +2,17 @@
 //!
 //! - Print to the screen
 //! - Handle Breakpoint Exception (INT3)
-//! - Handle Page Fault Exception (PF) [does not do anything special yet, just prints the error]
+//! - Handle Page Fault Exception (PF) [maps pages of registered lazy areas on demand and copies
+//!   copy-on-write pages on write, otherwise just prints the error]
 //! - Handle Double Fault Exception (DF) [does not do anything special yet, just prints the error]
-//! - Handle Timer interrupts
+//! - Report every other CPU exception (divide error, invalid opcode, general protection fault, ...)
+//!   along with its decoded error code
+//! - Handle Timer interrupts (counting the ticks of the PIT to tell the uptime)
 //! - Handle Keyboard interrupts (Has support for even Colemak)
-//! - Can translate Virtual addresses to Physical addresses using offset based paging.
+//! - Deliver interrupts through the local and I/O APIC (found via the ACPI tables), falling back to
+//!   the 8259 PICs on machines without them
+//! - Can translate Virtual addresses to Physical addresses using offset based or recursive paging.
+//! - Maps its own code read-only and its data, heap and stacks non-executable (W^X)
 
 #![no_std]
 #![cfg_attr(test, no_main)]
@@ -15,26 +21,40 @@
 #![reexport_test_harness_main = "test_main"]
 #![feature(abi_x86_interrupt)]
 #![feature(alloc_error_handler)]
+#![feature(allocator_api)]
 #![feature(const_mut_refs)]
 #![feature(generators)]
 #![feature(generator_trait)]
 
 extern crate alloc;
 
+pub mod acpi;
+pub mod address_space;
 pub mod allocation;
 pub mod allocator;
+pub mod apic;
 pub mod async_runtime;
+pub mod copy_on_write;
+pub mod dma;
+pub mod elf;
+pub mod frame_allocator;
 pub mod gdt;
 pub mod interrupt;
+pub mod kernel_stack;
 pub mod keyboard;
 pub mod memory;
+pub mod mmio;
 pub mod pic8258;
+pub mod pit;
 pub mod ps2_keyboard_decoder;
 pub mod screen_printing;
 pub mod serial;
 pub mod shell;
+pub mod time;
 pub mod utils;
 pub mod vga;
+pub mod virtual_memory;
+pub mod vma;
 pub mod x86_64;
 
 use async_runtime::{Executor, Task};
@@ -57,20 +77,34 @@ const ISA_EXIT_DEVICE_IOBASE: u16 = 0xf4;
 ///
 /// * Setup Global Descriptor Table
 /// * Setup Interrupt Descriptor Table
-/// * Setup Programable Interrupt Controllers
-/// * Enable interrupts
 /// * Setup offset based memory mapping
 /// * Setup heap allocator
+/// * Setup the interrupt stacks of the Task State Segment (they are allocated using the memory
+/// system)
+/// * Setup Programable Interrupt Controllers
+/// * Find the ACPI tables and switch over from the PICs to the APIC (if there is one)
+/// * Program the PIT and start counting the timer ticks
+/// * Register the keyboard interrupt handler
+/// * Enable interrupts
 pub fn init(boot_info: &'static BootInfo) {
     gdt::init();
     interrupt::init();
+    memory::init(boot_info);
+    unsafe { gdt::init_interrupt_stacks() };
     unsafe {
         interrupt::PROGRAMABLE_INTERRUPT_CONTROLERS
             .lock()
             .initialize()
     };
+    if let Err(error) = acpi::init() {
+        warn!("No ACPI tables found ({:?})\n", error);
+    }
+    if let Err(error) = apic::init() {
+        warn!("Not using the APIC, staying with the PICs ({:?})\n", error);
+    }
+    time::init(time::DEFAULT_TIMER_FREQUENCY).expect("setting up the timer failed");
+    keyboard::init();
     x86_64::interrupts::enable();
-    memory::init(boot_info);
 }
 
 /// Initialize async jobs
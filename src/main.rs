@@ -8,12 +8,14 @@ extern crate alloc;
 
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
-use rosy::{async_runtime::Executor, print, println, shell::Shell};
+use rosy::{async_runtime::Executor, memory, print, println, serial_println, shell::Shell};
 
 entry_point!(kernel_main);
 
 pub fn kernel_main(boot_info: &'static BootInfo) -> ! {
     rosy::init(boot_info);
+    serial_println!("{}", memory::memory_map_report());
+
     let mut executor = Executor::new();
     let mut shell = Shell::default();
     rosy::init_async_tasks(&mut executor, &mut shell);
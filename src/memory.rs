@@ -1,16 +1,89 @@
 //! Memory related operations
 
-use bootloader::BootInfo;
+use alloc::format;
+use bootloader::{
+    bootinfo::{MemoryMap, MemoryRegionType},
+    BootInfo,
+};
+use conquer_once::spin::OnceCell;
+use core::fmt;
 
 use crate::{
     allocation,
+    elf::{ElfHeader, SegmentFlags, SegmentType},
+    frame_allocator::FrameAllocator,
+    serial_println,
+    utils::Locked,
     x86_64::{
-        address::VirtualAddress,
-        instructions::read_control_register_3,
-        paging::{FrameAllocator, OffsetMemoryMapper, PageTable},
+        address::{PhysicalAddress, VirtualAddress},
+        instructions::{
+            read_control_register_3, read_extended_feature_enable_register,
+            write_extended_feature_enable_register, EferFlags,
+        },
+        paging::{
+            self, Mapper, MappingError, OffsetMemoryMapper, Page, PageInner, PageSize, PageTable,
+            PageTableEntryFlags, Size4KiB,
+        },
     },
 };
 
+extern "C" {
+    /// The ELF header of the kernel image. The linker defines this symbol at the start of the
+    /// first loaded segment, which contains the headers.
+    static __ehdr_start: ElfHeader;
+}
+
+/// The physical frame allocator used by the kernel. It starts out with no usable memory and is
+/// populated from the bootloader's memory map in [`init`].
+pub static FRAME_ALLOCATOR: Locked<FrameAllocator> = Locked::new(FrameAllocator::new());
+
+/// The mapper for the kernel's page tables. It is created in [`init`] from the page tables that
+/// the bootloader set up.
+static MEMORY_MAPPER: OnceCell<Locked<OffsetMemoryMapper>> = OnceCell::uninit();
+
+/// The physical memory map handed to us by the bootloader.
+static MEMORY_MAP: OnceCell<&'static MemoryMap> = OnceCell::uninit();
+
+/// The virtual address at which the bootloader mapped the complete physical memory.
+static PHYSICAL_MEMORY_OFFSET: OnceCell<VirtualAddress> = OnceCell::uninit();
+
+/// Get the virtual address through which the given physical address can be accessed.
+///
+/// # Panics
+/// If it is called before [`init`].
+pub fn physical_to_virtual(address: PhysicalAddress) -> VirtualAddress {
+    let physical_memory_offset = PHYSICAL_MEMORY_OFFSET
+        .try_get()
+        .expect("memory::init should be called before accessing physical memory");
+    *physical_memory_offset + address.as_u64()
+}
+
+/// Get the kernel's [`OffsetMemoryMapper`].
+///
+/// # Panics
+/// If it is called before [`init`].
+pub fn memory_mapper() -> &'static Locked<OffsetMemoryMapper> {
+    MEMORY_MAPPER
+        .try_get()
+        .expect("memory::init should be called before using the memory mapper")
+}
+
+/// Create an [`OffsetMemoryMapper`] for the page table hierarchy that is currently loaded in CR3.
+/// Unlike [`memory_mapper`] this sees the mappings of whatever address space is active.
+///
+/// # Safety
+/// The caller must ensure that the active page tables are not modified through any other mapper
+/// while the returned one is in use.
+///
+/// # Panics
+/// If it is called before [`init`].
+pub unsafe fn active_memory_mapper() -> OffsetMemoryMapper {
+    let physical_memory_offset = PHYSICAL_MEMORY_OFFSET
+        .try_get()
+        .expect("memory::init should be called before using the memory mapper");
+    OffsetMemoryMapper::new(*physical_memory_offset, &FRAME_ALLOCATOR)
+}
+
 /// Get the level 4 page table
 ///
 /// # Safety
@@ -29,15 +102,203 @@ pub unsafe fn active_level4_page_table(
 
 /// Initialize memory system
 ///
+/// * Sets up physical frame allocator
 /// * Sets up offset based memory mapping
+/// * Enables the `NO_EXECUTE` flag and remaps the kernel image with the permissions of its segments
 /// * Sets up heap allocator.
 pub fn init(boot_info: &'static BootInfo) {
     let physical_memory_offset = VirtualAddress::new(boot_info.physical_memory_offset);
-    let offset_memory_mapper: &mut OffsetMemoryMapper = unsafe {
-        &mut OffsetMemoryMapper::new(
-            physical_memory_offset,
-            FrameAllocator::new(&boot_info.memory_map),
-        )
+    unsafe {
+        FRAME_ALLOCATOR
+            .lock()
+            .init(&boot_info.memory_map, physical_memory_offset)
     };
-    allocation::init_heap(offset_memory_mapper).expect("heap initialization failed");
+    MEMORY_MAP
+        .try_init_once(|| &boot_info.memory_map)
+        .expect("memory::init should only be called once");
+
+    PHYSICAL_MEMORY_OFFSET
+        .try_init_once(|| physical_memory_offset)
+        .expect("memory::init should only be called once");
+    let offset_memory_mapper =
+        unsafe { OffsetMemoryMapper::new(physical_memory_offset, &FRAME_ALLOCATOR) };
+    MEMORY_MAPPER
+        .try_init_once(|| Locked::new(offset_memory_mapper))
+        .expect("memory::init should only be called once");
+
+    enable_no_execute();
+    protect_kernel_image(&mut *memory_mapper().lock()).expect("remapping the kernel failed");
+    allocation::init_heap(&mut *memory_mapper().lock()).expect("heap initialization failed");
+}
+
+/// Make the CPU honour the `NO_EXECUTE` flag of page table entries. Before this is enabled the bit
+/// is reserved.
+fn enable_no_execute() {
+    let flags = read_extended_feature_enable_register();
+    unsafe { write_extended_feature_enable_register(flags | EferFlags::NO_EXECUTE_ENABLE) };
+}
+
+/// Remap every loaded segment of the kernel image with the permissions from its program header.
+///
+/// The linker groups sections with the same permissions into one segment, so this results in
+/// * `.text` being executable but read-only,
+/// * `.rodata` being neither writable nor executable,
+/// * `.data` and `.bss` being writable but not executable.
+///
+/// # Panics
+/// If a segment is both writable and executable.
+fn protect_kernel_image(mapper: &mut impl Mapper) -> Result<(), MappingError> {
+    let header = unsafe { &__ehdr_start };
+    assert!(
+        header.is_valid(),
+        "the kernel image has no valid ELF header"
+    );
+
+    let segments = unsafe { header.program_headers() }
+        .filter(|segment| segment.segment_type == SegmentType::LOAD);
+    for segment in segments {
+        let segment_flags = segment.flags();
+        assert!(
+            !segment_flags.contains(SegmentFlags::WRITABLE | SegmentFlags::EXECUTABLE),
+            "kernel segment at {:#x} is both writable and executable",
+            segment.virtual_address
+        );
+
+        let start = VirtualAddress::new(segment.virtual_address).align_down(Size4KiB::SIZE);
+        let end = segment.virtual_address + segment.memory_size;
+        for address in (start.as_u64()..end).step_by(Size4KiB::SIZE as usize) {
+            let page = Page::Normal(PageInner::containing_address(VirtualAddress::new(address)));
+            let (_, flags) = mapper.translate_page(page)?;
+
+            let mut flags =
+                flags - (PageTableEntryFlags::WRITABLE | PageTableEntryFlags::NO_EXECUTE);
+            if segment_flags.contains(SegmentFlags::WRITABLE) {
+                flags |= PageTableEntryFlags::WRITABLE;
+            }
+            if !segment_flags.contains(SegmentFlags::EXECUTABLE) {
+                flags |= PageTableEntryFlags::NO_EXECUTE;
+            }
+            unsafe { mapper.update_flags(page, flags)? };
+        }
+    }
+
+    Ok(())
+}
+
+/// Get a [`MemoryMapReport`] of the physical memory.
+///
+/// # Panics
+/// If it is called before [`init`].
+pub fn memory_map_report() -> MemoryMapReport {
+    let memory_map = MEMORY_MAP
+        .try_get()
+        .expect("memory::init should be called before reading the memory map");
+    let frame_allocator = FRAME_ALLOCATOR.lock();
+    MemoryMapReport {
+        memory_map,
+        usable_frames: frame_allocator.usable_frames(),
+        free_frames: frame_allocator.free_frames(),
+    }
+}
+
+/// The regions of the physical memory map from the bootloader along with how much of the memory
+/// is usable, reserved and in use. Formatting it with `{}` prints one region per line followed by
+/// the totals.
+pub struct MemoryMapReport {
+    memory_map: &'static MemoryMap,
+    usable_frames: usize,
+    free_frames: usize,
+}
+
+impl MemoryMapReport {
+    /// Number of bytes in regions the bootloader marked as usable.
+    pub fn usable(&self) -> u64 {
+        self.memory_map
+            .iter()
+            .filter(|region| region.region_type == MemoryRegionType::Usable)
+            .map(|region| region.range.end_addr() - region.range.start_addr())
+            .sum()
+    }
+
+    /// Number of bytes in regions that are not usable (reserved by the firmware, used by the
+    /// kernel image, the bootloader, page tables, ...).
+    pub fn reserved(&self) -> u64 {
+        self.memory_map
+            .iter()
+            .filter(|region| region.region_type != MemoryRegionType::Usable)
+            .map(|region| region.range.end_addr() - region.range.start_addr())
+            .sum()
+    }
+
+    /// Number of bytes of the memory managed by the frame allocator that it has handed out.
+    pub fn in_use(&self) -> u64 {
+        ((self.usable_frames - self.free_frames) as u64) * Size4KiB::SIZE
+    }
+}
+
+impl fmt::Display for MemoryMapReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<16} {:<14} {:<14} {:>10}",
+            "type", "start", "end", "size"
+        )?;
+        for region in self.memory_map.iter() {
+            let (start, end) = (region.range.start_addr(), region.range.end_addr());
+            writeln!(
+                f,
+                "{:<16} {:#014x} {:#014x} {:>10}",
+                // Debug of the region type ignores the width, so format it first
+                format!("{:?}", region.region_type),
+                start,
+                end,
+                ByteSize(end - start)
+            )?;
+        }
+        writeln!(f, "usable:   {}", ByteSize(self.usable()))?;
+        writeln!(f, "reserved: {}", ByteSize(self.reserved()))?;
+        write!(f, "in use:   {}", ByteSize(self.in_use()))
+    }
+}
+
+/// A number of bytes formatted with the biggest binary unit that leaves at least 1 of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteSize(pub u64);
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+        let mut value = self.0;
+        let mut unit = 0;
+        while value >= 1024 && unit < UNITS.len() - 1 {
+            value /= 1024;
+            unit += 1;
+        }
+        f.pad(&format!("{} {}", value, UNITS[unit]))
+    }
+}
+
+/// Print every mapped region of the kernel's address space to the serial interface.
+pub fn print_address_space() {
+    serial_println!(
+        "{:<18} {:<18}    {:<14} {:<4}   {:<6} flags",
+        "virtual start",
+        "virtual end",
+        "physical start",
+        "size",
+        "count"
+    );
+    for region in paging::mapped_regions(&*memory_mapper().lock()) {
+        serial_println!("{}", region);
+    }
+}
+
+#[test_case]
+fn test_byte_size_uses_the_biggest_fitting_unit() {
+    assert_eq!(format!("{}", ByteSize(1023)), "1023 B");
+    assert_eq!(format!("{}", ByteSize(640 * 1024)), "640 KiB");
+    assert_eq!(format!("{}", ByteSize(128 * 1024 * 1024 + 1)), "128 MiB");
+    assert_eq!(format!("{}", ByteSize(4 * 1024 * 1024 * 1024)), "4 GiB");
+    assert_eq!(format!("{:>8}", ByteSize(2048)), "   2 KiB");
 }
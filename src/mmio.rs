@@ -0,0 +1,222 @@
+//! Memory mapped I/O
+//!
+//! Devices like the APIC, the HPET or PCI devices expose their registers as physical memory.
+//! [`map_mmio`] maps such a range into a range of the kernel's virtual address space (see
+//! [`crate::virtual_memory`]) with caching disabled, so every access actually reaches the device,
+//! and hands out an [`Mmio`] accessor for it.
+
+use core::{marker::PhantomData, mem};
+
+use crate::{
+    memory,
+    virtual_memory::{RangeOwner, KERNEL_VIRTUAL_MEMORY},
+    x86_64::{
+        address::{PhysicalAddress, VirtualAddress},
+        paging::{
+            Mapper, MappingError, Page, PageFrame, PageFrameInner, PageInner, PageSize,
+            PageTableEntryFlags, Size4KiB,
+        },
+    },
+};
+
+const PAGE_SIZE: u64 = Size4KiB::SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MmioError {
+    /// The range to map is empty or smaller than the type accessing it.
+    InvalidLength,
+    /// There is no room for the mapping in the kernel's virtual address space.
+    OutOfVirtualMemory,
+    /// Mapping the pages failed.
+    Mapping(MappingError),
+}
+
+/// Access to a range of memory mapped I/O through a `T` at its start.
+///
+/// All accesses are volatile. The range is unmapped and its virtual memory given back once this is
+/// dropped.
+pub struct Mmio<T> {
+    /// Start of the first mapped page.
+    pages_start: VirtualAddress,
+    page_count: u64,
+    /// Address the physical start of the range is mapped to.
+    start: VirtualAddress,
+    length: usize,
+    _type: PhantomData<*mut T>,
+}
+
+impl<T> Mmio<T> {
+    /// The virtual address the start of the physical range is mapped to.
+    pub fn virtual_address(&self) -> VirtualAddress {
+        self.start
+    }
+
+    /// Length of the range in bytes.
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    pub fn as_ptr(&self) -> *mut T {
+        self.start.as_mut_ptr()
+    }
+
+    /// Read the `T` at the start of the range.
+    pub fn read(&self) -> T
+    where
+        T: Copy,
+    {
+        unsafe { self.as_ptr().read_volatile() }
+    }
+
+    /// Write the `T` at the start of the range.
+    pub fn write(&mut self, value: T) {
+        unsafe { self.as_ptr().write_volatile(value) }
+    }
+
+    /// Read a `U` at `offset` bytes into the range.
+    ///
+    /// # Panics
+    /// If the value does not lie within the range or the offset is not aligned for `U`.
+    pub fn read_at<U: Copy>(&self, offset: usize) -> U {
+        unsafe { self.pointer_at::<U>(offset).read_volatile() }
+    }
+
+    /// Write a `U` at `offset` bytes into the range.
+    ///
+    /// # Panics
+    /// If the value does not lie within the range or the offset is not aligned for `U`.
+    pub fn write_at<U>(&mut self, offset: usize, value: U) {
+        unsafe { self.pointer_at::<U>(offset).write_volatile(value) }
+    }
+
+    fn pointer_at<U>(&self, offset: usize) -> *mut U {
+        assert!(
+            offset + mem::size_of::<U>() <= self.length,
+            "offset {:#x} is outside of the MMIO range of {:#x} bytes",
+            offset,
+            self.length
+        );
+        let address = self.start + offset as u64;
+        assert_eq!(
+            address.as_u64() % mem::align_of::<U>() as u64,
+            0,
+            "unaligned MMIO access at offset {:#x}",
+            offset
+        );
+        address.as_mut_ptr()
+    }
+
+    fn pages(&self) -> impl Iterator<Item = Page> {
+        let start = self.pages_start.as_u64();
+        (0..self.page_count).map(move |index| {
+            let address = VirtualAddress::new(start + index * PAGE_SIZE);
+            Page::Normal(PageInner::containing_address(address))
+        })
+    }
+}
+
+impl<T> Drop for Mmio<T> {
+    fn drop(&mut self) {
+        let mut mapper = memory::memory_mapper().lock();
+        for page in self.pages() {
+            // The frames belong to the device, so they are not given to the frame allocator.
+            let _ = unsafe { mapper.unmap(page) };
+        }
+        drop(mapper);
+
+        free_range(self.pages_start);
+    }
+}
+
+/// Map `length` bytes of device memory starting at `physical` with the kernel's memory mapper, see
+/// [`MmioMapper::map_mmio`].
+///
+/// The kernel's memory mapper must not be locked when this is called. Callers that already hold
+/// it use [`MmioMapper::map_mmio`] instead.
+///
+/// # Safety
+/// The same as for [`MmioMapper::map_mmio`] applies.
+pub unsafe fn map_mmio<T>(physical: PhysicalAddress, length: usize) -> Result<Mmio<T>, MmioError> {
+    memory::memory_mapper().lock().map_mmio(physical, length)
+}
+
+/// Extends every [`Mapper`] of the kernel's address space with [`MmioMapper::map_mmio`].
+pub trait MmioMapper: Mapper {
+    /// Map `length` bytes of device memory starting at `physical` with `NO_CACHE | WRITE_THROUGH`
+    /// and return an accessor for it. The mapping is not executable.
+    ///
+    /// The range does not have to be page aligned. The returned [`Mmio`] unmaps the range with the
+    /// kernel's memory mapper, so that must not be locked when it is dropped.
+    ///
+    /// # Safety
+    /// The physical range must belong to a device (i.e. not be normal memory that is in use
+    /// elsewhere) and `T` must be a valid way to access its start. The mapper must map the kernel
+    /// part of the active address space.
+    unsafe fn map_mmio<T>(
+        &mut self,
+        physical: PhysicalAddress,
+        length: usize,
+    ) -> Result<Mmio<T>, MmioError>;
+}
+
+impl<M: Mapper> MmioMapper for M {
+    unsafe fn map_mmio<T>(
+        &mut self,
+        physical: PhysicalAddress,
+        length: usize,
+    ) -> Result<Mmio<T>, MmioError> {
+        if length == 0 || length < mem::size_of::<T>() {
+            return Err(MmioError::InvalidLength);
+        }
+
+        let physical_pages_start = physical.align_down(PAGE_SIZE);
+        let offset = physical.as_u64() - physical_pages_start.as_u64();
+        let page_count = offset
+            .checked_add(length as u64)
+            .and_then(|end| end.checked_add(PAGE_SIZE - 1))
+            .ok_or(MmioError::InvalidLength)?
+            / PAGE_SIZE;
+
+        let pages_start = KERNEL_VIRTUAL_MEMORY
+            .lock()
+            .allocate(page_count * PAGE_SIZE, PAGE_SIZE, RangeOwner::Mmio)
+            .map_err(|_| MmioError::OutOfVirtualMemory)?
+            .start;
+        let mmio = Mmio {
+            pages_start,
+            page_count,
+            start: pages_start + offset,
+            length,
+            _type: PhantomData,
+        };
+
+        let flags = PageTableEntryFlags::PRESENT
+            | PageTableEntryFlags::WRITABLE
+            | PageTableEntryFlags::NO_CACHE
+            | PageTableEntryFlags::WRITE_THROUGH
+            | PageTableEntryFlags::NO_EXECUTE;
+        for (index, page) in mmio.pages().enumerate() {
+            let frame = PageFrame::Normal(PageFrameInner::containing_address(
+                physical_pages_start + index as u64 * PAGE_SIZE,
+            ));
+            if let Err(error) = self.map_to(page, frame, flags) {
+                // Dropping `mmio` would need the kernel's memory mapper, which might be `self`.
+                for page in mmio.pages().take(index) {
+                    let _ = self.unmap(page);
+                }
+                mem::forget(mmio);
+                free_range(pages_start);
+                return Err(MmioError::Mapping(error));
+            }
+        }
+
+        Ok(mmio)
+    }
+}
+
+fn free_range(pages_start: VirtualAddress) {
+    KERNEL_VIRTUAL_MEMORY
+        .lock()
+        .free(pages_start)
+        .expect("the MMIO range was not allocated by map_mmio");
+}
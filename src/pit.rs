@@ -0,0 +1,83 @@
+//! Implementation of the Intel 8253/8254 Programmable Interval Timer (PIT).
+//!
+//! The PIT has an oscillator running at [`BASE_FREQUENCY`] and three channels, each a 16 bit
+//! counter that counts down from a reload value (the divisor) on every oscillation. Only channel 0
+//! is used here: its output is connected to ISA interrupt line 0, so with a divisor of `n` the
+//! interrupt fires `BASE_FREQUENCY / n` times a second.
+
+use crate::x86_64::port::Port;
+
+/// Frequency of the PIT's oscillator in Hz.
+pub const BASE_FREQUENCY: u32 = 1_193_182;
+/// Lowest frequency the PIT can be programmed to (with the largest divisor, 65536).
+pub const MIN_FREQUENCY: u32 = BASE_FREQUENCY / MAX_DIVISOR + 1;
+/// Highest frequency the PIT can be programmed to. A divisor of 1 is not allowed in the rate
+/// generator mode.
+pub const MAX_FREQUENCY: u32 = BASE_FREQUENCY / 2;
+
+const MAX_DIVISOR: u32 = 0x10000;
+
+const CHANNEL_0_DATA_PORT: u16 = 0x40;
+const MODE_COMMAND_PORT: u16 = 0x43;
+
+/// Select channel 0 (bits 6-7), send the divisor as low byte followed by high byte (bits 4-5), use
+/// the rate generator mode (mode 2, bits 1-3) and count in binary (bit 0).
+const CHANNEL_0_RATE_GENERATOR: u8 = 0b0011_0100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PitError {
+    /// The frequency is below [`MIN_FREQUENCY`] or above [`MAX_FREQUENCY`].
+    UnsupportedFrequency,
+}
+
+/// Channel 0 of the PIT.
+pub struct ProgrammableIntervalTimer {
+    command: Port<u8>,
+    channel_0: Port<u8>,
+}
+
+impl ProgrammableIntervalTimer {
+    pub const fn new() -> Self {
+        ProgrammableIntervalTimer {
+            command: Port::new(MODE_COMMAND_PORT),
+            channel_0: Port::new(CHANNEL_0_DATA_PORT),
+        }
+    }
+
+    /// Make channel 0 raise its interrupt every `divisor` oscillations, see [`divisor_for`].
+    ///
+    /// # Safety
+    /// Changes the rate of the timer interrupt, which whoever relies on it has to be aware of.
+    pub unsafe fn set_divisor(&mut self, divisor: u32) {
+        // A divisor of 0 stands for 65536.
+        let divisor = (divisor % MAX_DIVISOR) as u16;
+        self.command.write(CHANNEL_0_RATE_GENERATOR);
+        self.channel_0.write(divisor as u8);
+        self.channel_0.write((divisor >> 8) as u8);
+    }
+}
+
+/// The divisor that gets channel 0 closest to the given frequency (in Hz).
+pub fn divisor_for(frequency: u32) -> Result<u32, PitError> {
+    if !(MIN_FREQUENCY..=MAX_FREQUENCY).contains(&frequency) {
+        return Err(PitError::UnsupportedFrequency);
+    }
+    // Round to the nearest divisor.
+    Ok((BASE_FREQUENCY + frequency / 2) / frequency)
+}
+
+#[test_case]
+fn test_divisor_is_rounded_and_bounded() {
+    assert_eq!(divisor_for(1000), Ok(1193));
+    assert_eq!(divisor_for(100), Ok(11932));
+    assert_eq!(divisor_for(MAX_FREQUENCY), Ok(2));
+    assert!(divisor_for(MIN_FREQUENCY).unwrap() <= MAX_DIVISOR);
+    assert_eq!(
+        divisor_for(MIN_FREQUENCY - 1),
+        Err(PitError::UnsupportedFrequency)
+    );
+    assert_eq!(
+        divisor_for(MAX_FREQUENCY + 1),
+        Err(PitError::UnsupportedFrequency)
+    );
+}
@@ -1,25 +1,42 @@
 //! User shell
 
-use alloc::string::String;
+use alloc::{boxed::Box, string::String, vec::Vec};
 use futures_util::StreamExt;
 
 use crate::{
+    allocation,
+    allocator::arena::Arena,
+    error, errorln,
     keyboard::ScancodeStream,
-    print, println,
+    memory, print, println,
     ps2_keyboard_decoder::{ColemakDHm, DecodedKey, HandleControl, Keyboard, ScancodeSet1},
     screen_printing::WRITER,
+    time,
+    virtual_memory::KERNEL_VIRTUAL_MEMORY,
     x86_64::interrupts,
 };
 
 const ENTER: char = '\n';
 const BACKSPACE: char = 0x08 as char;
+/// Bytes available for parsing a single command.
+const COMMAND_ARENA_SIZE: usize = 4096;
 
 /// Represents a user shell.
 ///
-/// Current capabilities are fairly limited. It just echos back what the user types.
+/// Current capabilities are fairly limited. It echos back what the user types and understands the
+/// following commands:
+///
+/// * `meminfo`: Print statistics about the kernel heap.
+/// * `memmap`: Print the physical memory map along with how much of it is used.
+/// * `vmmap`: Print the ranges of the kernel's virtual address space that are in use.
+/// * `uptime`: Print the time since the kernel started.
 pub struct Shell {
     scancodes: ScancodeStream,
     keyboard: Keyboard<ColemakDHm, ScancodeSet1>,
+    /// Holds whatever is allocated while running a command. It is reset after every command.
+    ///
+    /// The shell runs for as long as the kernel does, so its arena is never given back.
+    arena: &'static mut Arena,
 }
 
 impl Shell {
@@ -28,6 +45,7 @@ impl Shell {
         Shell {
             scancodes,
             keyboard,
+            arena: Box::leak(Box::new(Arena::new(COMMAND_ARENA_SIZE))),
         }
     }
 
@@ -75,11 +93,58 @@ impl Shell {
         loop {
             self.print_prompt().await;
             let command = self.get_input_while_echoing().await;
-            println!("{}", command);
+            self.execute(&command);
+            self.arena.reset();
+        }
+    }
+
+    /// Run the given command. Anything that is not a command is echoed back.
+    fn execute(&self, command: &str) {
+        let mut words = Vec::new_in(&*self.arena);
+        let word_count = command.split_whitespace().count();
+        if words.try_reserve_exact(word_count).is_err() {
+            errorln!(
+                "too many words ({}) to fit into the command arena",
+                word_count
+            );
+            return;
+        }
+        words.extend(command.split_whitespace());
+
+        match words.as_slice() {
+            ["meminfo"] => print_meminfo(),
+            ["memmap"] => println!("{}", memory::memory_map_report()),
+            ["vmmap"] => println!("{}", *KERNEL_VIRTUAL_MEMORY.lock()),
+            ["uptime"] => print_uptime(),
+            _ => println!("{}", command),
         }
     }
 }
 
+/// Print the [`allocation::heap_stats`] of the kernel heap.
+fn print_meminfo() {
+    let stats = allocation::heap_stats();
+    println!("allocator:          {}", allocation::HEAP_ALLOCATOR_NAME);
+    println!("heap size:          {} bytes", allocation::heap_size());
+    println!("allocated:          {} bytes", stats.bytes_allocated);
+    println!("free:               {} bytes", stats.bytes_free);
+    println!("peak allocated:     {} bytes", stats.peak_bytes_allocated);
+    println!("live allocations:   {}", stats.live_allocations);
+    println!("free list length:   {}", stats.free_list_length);
+    println!("largest free block: {} bytes", stats.largest_free_block);
+}
+
+/// Print the [`time::uptime`] along with the number of timer ticks.
+fn print_uptime() {
+    let uptime = time::uptime();
+    println!(
+        "up {}.{:03}s ({} ticks)",
+        uptime.as_secs(),
+        uptime.subsec_millis(),
+        time::ticks()
+    );
+}
+
 impl Default for Shell {
     fn default() -> Self {
         Shell::new(
@@ -0,0 +1,113 @@
+//! Time keeping
+//!
+//! [`init`] programs the [`crate::pit`] to raise the timer interrupt a given number of times a
+//! second. Every timer interrupt increments a global tick counter, which makes for a monotonic
+//! clock that starts at (roughly) boot: [`ticks`] and [`uptime`].
+//!
+//! # Limitation(s)
+//! * The resolution is the period of the timer interrupt, so it is 1ms with the
+//! [`DEFAULT_TIMER_FREQUENCY`].
+//! * Ticks are lost while interrupts stay disabled for longer than a period.
+
+use core::{
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    interrupt::{self, IrqError, IRQ_TIMER},
+    pit::{self, PitError, ProgrammableIntervalTimer},
+    x86_64::interrupts,
+};
+
+/// Frequency (in Hz) of the timer interrupt if there is no reason to pick something else.
+pub const DEFAULT_TIMER_FREQUENCY: u32 = 1000;
+
+const NANOSECONDS_PER_SECOND: u128 = 1_000_000_000;
+
+/// Number of timer interrupts since [`init`].
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// The divisor the PIT was programmed with, 0 until [`init`] is done.
+static DIVISOR: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeError {
+    /// The PIT can't be programmed to the frequency.
+    Pit(PitError),
+    /// Registering the handler of the timer interrupt failed.
+    Irq(IrqError),
+    /// [`init`] was called before.
+    AlreadyInitialized,
+}
+
+/// Program the PIT to raise the timer interrupt `frequency` times a second (as close as the PIT
+/// gets to it) and start counting the ticks.
+pub fn init(frequency: u32) -> Result<(), TimeError> {
+    let divisor = pit::divisor_for(frequency).map_err(TimeError::Pit)?;
+    if DIVISOR
+        .compare_exchange(0, divisor, Ordering::SeqCst, Ordering::SeqCst)
+        .is_err()
+    {
+        return Err(TimeError::AlreadyInitialized);
+    }
+
+    interrupts::execute_without_interrupts(|| {
+        unsafe { ProgrammableIntervalTimer::new().set_divisor(divisor) };
+        // The handler stays registered forever, so the registration is not needed.
+        interrupt::register_irq(IRQ_TIMER, tick).map(|_| ())
+    })
+    .map_err(|error| {
+        DIVISOR.store(0, Ordering::SeqCst);
+        TimeError::Irq(error)
+    })
+}
+
+/// Number of timer interrupts since [`init`].
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Time between two ticks, None before [`init`].
+pub fn tick_period() -> Option<Duration> {
+    match DIVISOR.load(Ordering::Relaxed) {
+        0 => None,
+        divisor => Some(ticks_to_duration(1, divisor)),
+    }
+}
+
+/// Time since [`init`], zero before it.
+pub fn uptime() -> Duration {
+    ticks_to_duration(ticks(), DIVISOR.load(Ordering::Relaxed))
+}
+
+/// Every tick takes `divisor` oscillations of the PIT.
+fn ticks_to_duration(ticks: u64, divisor: u32) -> Duration {
+    let nanoseconds =
+        ticks as u128 * divisor as u128 * NANOSECONDS_PER_SECOND / pit::BASE_FREQUENCY as u128;
+    Duration::from_nanos(nanoseconds as u64)
+}
+
+/// Handler of the timer interrupt.
+fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+#[test_case]
+fn test_uptime_advances_with_the_timer_interrupt() {
+    use crate::x86_64::instructions::halt_cpu_till_next_interrupt;
+
+    let period = tick_period().unwrap();
+    assert_eq!(period.as_micros(), 999);
+
+    let start = uptime();
+    let start_ticks = ticks();
+    while ticks() < start_ticks + 10 {
+        halt_cpu_till_next_interrupt();
+    }
+    assert!(uptime() - start >= period * 10);
+
+    assert_eq!(
+        init(DEFAULT_TIMER_FREQUENCY),
+        Err(TimeError::AlreadyInitialized)
+    );
+}
@@ -36,6 +36,11 @@ impl<A> Locked<A> {
     pub fn lock(&self) -> spin::MutexGuard<A> {
         self.inner.lock()
     }
+
+    /// Tries to lock the wrapped Mutex without spinning. Returns None if it is already locked.
+    pub fn try_lock(&self) -> Option<spin::MutexGuard<A>> {
+        self.inner.try_lock()
+    }
 }
 
 // Take from https://github.com/tinaun/gen-iter/blob/master/src/lib.rs
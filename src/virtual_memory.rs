@@ -0,0 +1,283 @@
+//! Kernel virtual address space management
+//!
+//! Instead of every subsystem picking its own addresses, the part of the kernel's virtual address
+//! space between [`KERNEL_VIRTUAL_START`] and [`KERNEL_VIRTUAL_END`] is handed out in page aligned
+//! [`VirtualRange`]s by [`KERNEL_VIRTUAL_MEMORY`]. Every range remembers its [`RangeOwner`], so
+//! it is easy to tell what an address is used for.
+//!
+//! The allocator only keeps track of the ranges, mapping them is up to their owner.
+//!
+//! # Limitation(s)
+//! * Addresses outside of the managed part are not tracked. This includes everything the bootloader
+//! set up (the kernel image, the physical memory mapping, the VGA buffer at `0xb8000`) and the
+//! user space of [`crate::address_space`], which starts right below [`KERNEL_VIRTUAL_START`].
+
+use core::fmt;
+
+use crate::{
+    address_space::USER_SPACE_END,
+    memory::ByteSize,
+    utils::Locked,
+    x86_64::{
+        address::VirtualAddress,
+        paging::{PageSize, Size4KiB},
+    },
+};
+
+/// Start of the part of the virtual address space that is managed by [`KERNEL_VIRTUAL_MEMORY`]
+/// (level 4 entry 128).
+pub const KERNEL_VIRTUAL_START: u64 = USER_SPACE_END;
+/// End (exclusive) of the part of the virtual address space that is managed by
+/// [`KERNEL_VIRTUAL_MEMORY`] (level 4 entry 160).
+pub const KERNEL_VIRTUAL_END: u64 = 0x0000_5000_0000_0000;
+/// Maximum number of ranges that can be allocated at the same time.
+pub const MAX_RANGES: usize = 256;
+
+const PAGE_SIZE: u64 = Size4KiB::SIZE;
+
+/// The virtual ranges in use by the kernel.
+pub static KERNEL_VIRTUAL_MEMORY: Locked<VirtualRangeAllocator> = Locked::new(
+    VirtualRangeAllocator::new(KERNEL_VIRTUAL_START, KERNEL_VIRTUAL_END),
+);
+
+/// What a [`VirtualRange`] is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeOwner {
+    /// The kernel heap, including the room it can grow into.
+    Heap,
+    /// A kernel stack along with its guard page.
+    KernelStack,
+    /// Memory mapped I/O.
+    Mmio,
+    /// Short lived mappings, e.g. to access a frame that is not mapped anywhere else.
+    TemporaryMapping,
+    /// A [`crate::vma::VirtualMemoryArea`] whose pages are mapped on demand.
+    Area,
+}
+
+/// A page aligned range of the kernel's virtual address space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VirtualRange {
+    pub start: VirtualAddress,
+    /// Length in bytes
+    pub size: u64,
+    pub owner: RangeOwner,
+}
+
+impl VirtualRange {
+    /// First address after the range.
+    pub fn end(&self) -> u64 {
+        self.start.as_u64() + self.size
+    }
+
+    pub fn contains(&self, address: VirtualAddress) -> bool {
+        self.start <= address && address.as_u64() < self.end()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VirtualRangeError {
+    /// The size is zero or not a multiple of the page size.
+    InvalidSize,
+    /// The alignment is not a power of two.
+    InvalidAlignment,
+    /// There is no gap big enough for the range.
+    OutOfVirtualMemory,
+    /// [`MAX_RANGES`] ranges are allocated already.
+    TooManyRanges,
+    /// No range starts at the given address.
+    NotAllocated,
+}
+
+/// Hands out non overlapping [`VirtualRange`]s from a part of the virtual address space, placing
+/// each one in the first gap it fits in.
+///
+/// The ranges are kept sorted by their start address in a fixed size array. This way the
+/// allocator works before the heap exists, which is important as the heap gets its range from it.
+pub struct VirtualRangeAllocator {
+    start: u64,
+    end: u64,
+    ranges: [VirtualRange; MAX_RANGES],
+    count: usize,
+}
+
+impl VirtualRangeAllocator {
+    /// Create an allocator for the addresses from `start` up to (excluding) `end`.
+    pub const fn new(start: u64, end: u64) -> Self {
+        const UNUSED: VirtualRange = VirtualRange {
+            start: VirtualAddress::zero(),
+            size: 0,
+            owner: RangeOwner::TemporaryMapping,
+        };
+
+        VirtualRangeAllocator {
+            start,
+            end,
+            ranges: [UNUSED; MAX_RANGES],
+            count: 0,
+        }
+    }
+
+    /// Allocate `size` bytes starting at a multiple of `alignment` (at least page aligned).
+    pub fn allocate(
+        &mut self,
+        size: u64,
+        alignment: u64,
+        owner: RangeOwner,
+    ) -> Result<VirtualRange, VirtualRangeError> {
+        if size == 0 || size % PAGE_SIZE != 0 {
+            return Err(VirtualRangeError::InvalidSize);
+        }
+        if !alignment.is_power_of_two() {
+            return Err(VirtualRangeError::InvalidAlignment);
+        }
+        if self.count == MAX_RANGES {
+            return Err(VirtualRangeError::TooManyRanges);
+        }
+        let alignment = alignment.max(PAGE_SIZE);
+
+        // Walk the gaps in front of every range and the one after the last range. A size or
+        // alignment that does not fit into the address space leaves us without a candidate.
+        let fits_below =
+            |start: u64, limit: u64| start.checked_add(size).map_or(false, |end| end <= limit);
+        let mut candidate = checked_align_up(self.start, alignment);
+        let mut index = 0;
+        while let Some(start) = candidate {
+            if index == self.count || fits_below(start, self.ranges[index].start.as_u64()) {
+                break;
+            }
+            candidate = checked_align_up(self.ranges[index].end(), alignment);
+            index += 1;
+        }
+        let candidate = candidate
+            .filter(|&start| fits_below(start, self.end))
+            .ok_or(VirtualRangeError::OutOfVirtualMemory)?;
+
+        let range = VirtualRange {
+            start: VirtualAddress::new(candidate),
+            size,
+            owner,
+        };
+        self.ranges.copy_within(index..self.count, index + 1);
+        self.ranges[index] = range;
+        self.count += 1;
+        Ok(range)
+    }
+
+    /// Give back the range starting at the given address.
+    pub fn free(&mut self, start: VirtualAddress) -> Result<VirtualRange, VirtualRangeError> {
+        let index = self
+            .iter()
+            .position(|range| range.start == start)
+            .ok_or(VirtualRangeError::NotAllocated)?;
+        let range = self.ranges[index];
+
+        self.ranges.copy_within(index + 1..self.count, index);
+        self.count -= 1;
+        Ok(range)
+    }
+
+    /// Find the range that contains the given address.
+    pub fn find(&self, address: VirtualAddress) -> Option<&VirtualRange> {
+        self.iter().find(|range| range.contains(address))
+    }
+
+    /// Iterate over the allocated ranges in the order of their addresses.
+    pub fn iter(&self) -> impl Iterator<Item = &VirtualRange> {
+        self.ranges[..self.count].iter()
+    }
+}
+
+/// Align `address` upwards to the power of two `alignment`, or None if that overflows.
+fn checked_align_up(address: u64, alignment: u64) -> Option<u64> {
+    address
+        .checked_add(alignment - 1)
+        .map(|address| address & !(alignment - 1))
+}
+
+/// Prints one range per line along with its owner.
+impl fmt::Display for VirtualRangeAllocator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:<18} {:<18} {:>10} owner", "start", "end", "size")?;
+        for range in self.iter() {
+            write!(
+                f,
+                "\n{:#018x} {:#018x} {:>10} {:?}",
+                range.start.as_u64(),
+                range.end(),
+                ByteSize(range.size),
+                range.owner
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_allocate_places_ranges_in_the_first_fitting_gap() {
+    let start = 0x1000_0000;
+    let mut allocator = VirtualRangeAllocator::new(start, start + 16 * PAGE_SIZE);
+    let allocate = |allocator: &mut VirtualRangeAllocator, pages, alignment| {
+        allocator
+            .allocate(pages * PAGE_SIZE, alignment, RangeOwner::Area)
+            .map(|range| (range.start.as_u64() - start) / PAGE_SIZE)
+    };
+
+    assert_eq!(allocate(&mut allocator, 2, PAGE_SIZE), Ok(0));
+    assert_eq!(allocate(&mut allocator, 3, PAGE_SIZE), Ok(2));
+    assert_eq!(allocate(&mut allocator, 1, 4 * PAGE_SIZE), Ok(8));
+    // The gap between the second and the third range is used.
+    assert_eq!(allocate(&mut allocator, 3, PAGE_SIZE), Ok(5));
+
+    let freed = allocator.free(VirtualAddress::new(start + 2 * PAGE_SIZE));
+    assert_eq!(freed.map(|range| range.size), Ok(3 * PAGE_SIZE));
+    assert_eq!(allocate(&mut allocator, 2, PAGE_SIZE), Ok(2));
+
+    assert_eq!(
+        allocate(&mut allocator, 8, PAGE_SIZE),
+        Err(VirtualRangeError::OutOfVirtualMemory)
+    );
+    assert_eq!(allocate(&mut allocator, 7, PAGE_SIZE), Ok(9));
+    let starts = allocator.iter().map(|range| range.start.as_u64() - start);
+    assert!(starts.eq([0, 2, 5, 8, 9].map(|page| page * PAGE_SIZE)));
+}
+
+#[test_case]
+fn test_allocate_rejects_invalid_ranges_and_free_unknown_ones() {
+    let mut allocator = VirtualRangeAllocator::new(0x1000_0000, 0x2000_0000);
+
+    assert_eq!(
+        allocator.allocate(0, PAGE_SIZE, RangeOwner::Mmio),
+        Err(VirtualRangeError::InvalidSize)
+    );
+    assert_eq!(
+        allocator.allocate(PAGE_SIZE + 1, PAGE_SIZE, RangeOwner::Mmio),
+        Err(VirtualRangeError::InvalidSize)
+    );
+    assert_eq!(
+        allocator.allocate(PAGE_SIZE, 3 * PAGE_SIZE, RangeOwner::Mmio),
+        Err(VirtualRangeError::InvalidAlignment)
+    );
+    assert_eq!(
+        allocator.allocate(u64::MAX - PAGE_SIZE + 1, PAGE_SIZE, RangeOwner::Mmio),
+        Err(VirtualRangeError::OutOfVirtualMemory)
+    );
+    assert_eq!(
+        allocator.allocate(PAGE_SIZE, 1 << 63, RangeOwner::Mmio),
+        Err(VirtualRangeError::OutOfVirtualMemory)
+    );
+
+    let range = allocator
+        .allocate(PAGE_SIZE, PAGE_SIZE, RangeOwner::Mmio)
+        .unwrap();
+    assert_eq!(
+        allocator.find(VirtualAddress::new(range.start.as_u64() + 8)),
+        Some(&range)
+    );
+    assert_eq!(
+        allocator.free(VirtualAddress::new(range.start.as_u64() + PAGE_SIZE)),
+        Err(VirtualRangeError::NotAllocated)
+    );
+    assert_eq!(allocator.free(range.start), Ok(range));
+    assert_eq!(allocator.find(range.start), None);
+}
@@ -0,0 +1,335 @@
+//! Virtual memory areas and demand paging
+//!
+//! A [`VirtualMemoryArea`] describes a range of the virtual address space that the kernel intends
+//! to use, without the range having to be mapped yet. When a page inside a registered area is
+//! accessed for the first time the CPU raises a page fault, which is resolved by
+//! [`handle_page_fault`] by mapping the page to a fresh frame and resuming execution.
+//!
+//! [`allocate_area`] takes the range of a new area from the kernel's virtual address space (see
+//! [`crate::virtual_memory`]), so it can't collide with the heap, the stacks or MMIO.
+
+use alloc::vec::Vec;
+use core::ptr;
+
+use crate::{
+    copy_on_write, memory,
+    utils::Locked,
+    virtual_memory::{RangeOwner, KERNEL_VIRTUAL_MEMORY},
+    x86_64::{
+        address::VirtualAddress,
+        idt::PageFaultErrorCode,
+        paging::{Mapper, MappingError, Page, PageInner, PageSize, PageTableEntryFlags, Size4KiB},
+    },
+};
+
+/// The areas registered for the kernel's address space.
+pub static KERNEL_AREAS: Locked<AreaRegistry> = Locked::new(AreaRegistry::new());
+
+/// How the memory of a [`VirtualMemoryArea`] is provided.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backing {
+    /// Nothing is mapped up front. Each page is mapped to a newly allocated, zeroed frame when it
+    /// is first accessed.
+    Lazy,
+}
+
+/// A page aligned range of virtual memory along with how it should be mapped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VirtualMemoryArea {
+    pub start: VirtualAddress,
+    /// Length in bytes
+    pub length: u64,
+    /// Flags the pages of the area are mapped with. `PRESENT` is always added.
+    pub flags: PageTableEntryFlags,
+    pub backing: Backing,
+}
+
+impl VirtualMemoryArea {
+    pub fn new(
+        start: VirtualAddress,
+        length: u64,
+        flags: PageTableEntryFlags,
+        backing: Backing,
+    ) -> Self {
+        VirtualMemoryArea {
+            start,
+            length,
+            flags,
+            backing,
+        }
+    }
+
+    /// First address after the area.
+    pub fn end(&self) -> u64 {
+        self.start.as_u64() + self.length
+    }
+
+    pub fn contains(&self, address: VirtualAddress) -> bool {
+        self.start <= address && address.as_u64() < self.end()
+    }
+
+    fn overlaps(&self, other: &VirtualMemoryArea) -> bool {
+        self.start.as_u64() < other.end() && other.start.as_u64() < self.end()
+    }
+
+    /// Iterate over the pages of the area.
+    pub fn pages(&self) -> impl Iterator<Item = Page> {
+        let start = self.start.as_u64();
+        (start..self.end())
+            .step_by(Size4KiB::SIZE as usize)
+            .map(|address| {
+                Page::Normal(PageInner::containing_address(VirtualAddress::new(address)))
+            })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AreaError {
+    /// The start or the length of the area is not a multiple of the page size, or it is empty.
+    NotPageAligned,
+    /// The area overlaps an already registered area.
+    Overlapping,
+    /// There is no room for the area in the kernel's virtual address space.
+    OutOfVirtualMemory,
+}
+
+/// A set of non overlapping [`VirtualMemoryArea`]s, kept sorted by their start address.
+pub struct AreaRegistry {
+    areas: Vec<VirtualMemoryArea>,
+}
+
+impl AreaRegistry {
+    pub const fn new() -> Self {
+        AreaRegistry { areas: Vec::new() }
+    }
+
+    /// Add an area to the registry.
+    pub fn register(&mut self, area: VirtualMemoryArea) -> Result<(), AreaError> {
+        let page_size = Size4KiB::SIZE;
+        if area.length == 0 || area.start.as_u64() % page_size != 0 || area.length % page_size != 0
+        {
+            return Err(AreaError::NotPageAligned);
+        }
+        if self
+            .areas
+            .iter()
+            .any(|registered| registered.overlaps(&area))
+        {
+            return Err(AreaError::Overlapping);
+        }
+
+        let index = self
+            .areas
+            .partition_point(|registered| registered.start < area.start);
+        self.areas.insert(index, area);
+        Ok(())
+    }
+
+    /// Remove the area starting at the given address from the registry and return it.
+    pub fn unregister(&mut self, start: VirtualAddress) -> Option<VirtualMemoryArea> {
+        let index = self.areas.iter().position(|area| area.start == start)?;
+        Some(self.areas.remove(index))
+    }
+
+    /// Find the area that contains the given address.
+    pub fn find(&self, address: VirtualAddress) -> Option<&VirtualMemoryArea> {
+        self.areas.iter().find(|area| area.contains(address))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &VirtualMemoryArea> {
+        self.areas.iter()
+    }
+}
+
+/// Reasons for which a page fault could not be resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageFaultError {
+    /// The page is present, the access itself was not allowed (and it is not a write to a
+    /// copy-on-write page).
+    ProtectionViolation,
+    /// The address is not part of any registered area.
+    NoArea,
+    /// A write to an area that is not writable.
+    WriteToReadOnlyArea,
+    /// The area registry, the memory mapper or the frame allocator were in use when the fault
+    /// happened.
+    Busy,
+    /// Mapping the page failed.
+    Mapping(MappingError),
+}
+
+/// Try to resolve a page fault at the given address by mapping the page if it belongs to one of
+/// the [`KERNEL_AREAS`]. Writes to present pages are handed to
+/// [`copy_on_write::handle_write_fault`].
+///
+/// On success the faulting instruction can simply be executed again.
+pub fn handle_page_fault(
+    address: VirtualAddress,
+    error_code: PageFaultErrorCode,
+) -> Result<(), PageFaultError> {
+    if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+            return copy_on_write::handle_write_fault(address);
+        }
+        return Err(PageFaultError::ProtectionViolation);
+    }
+
+    let area = *KERNEL_AREAS
+        .try_lock()
+        .ok_or(PageFaultError::Busy)?
+        .find(address)
+        .ok_or(PageFaultError::NoArea)?;
+    if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE)
+        && !area.flags.contains(PageTableEntryFlags::WRITABLE)
+    {
+        return Err(PageFaultError::WriteToReadOnlyArea);
+    }
+
+    let mut mapper = memory::memory_mapper()
+        .try_lock()
+        .ok_or(PageFaultError::Busy)?;
+    let page = Page::Normal(PageInner::containing_address(
+        address.align_down(Size4KiB::SIZE),
+    ));
+    match area.backing {
+        Backing::Lazy => map_zeroed_page(&mut *mapper, page, area.flags),
+    }
+}
+
+/// Take `length` bytes (a multiple of the page size) from the kernel's virtual address space and
+/// register them as an area in the [`KERNEL_AREAS`].
+pub fn allocate_area(
+    length: u64,
+    flags: PageTableEntryFlags,
+    backing: Backing,
+) -> Result<VirtualMemoryArea, AreaError> {
+    if length == 0 || length % Size4KiB::SIZE != 0 {
+        return Err(AreaError::NotPageAligned);
+    }
+    let range = KERNEL_VIRTUAL_MEMORY
+        .lock()
+        .allocate(length, Size4KiB::SIZE, RangeOwner::Area)
+        .map_err(|_| AreaError::OutOfVirtualMemory)?;
+
+    let area = VirtualMemoryArea::new(range.start, length, flags, backing);
+    KERNEL_AREAS.lock().register(area).map_err(|error| {
+        free_range(area.start);
+        error
+    })?;
+    Ok(area)
+}
+
+/// Remove the area starting at `start` from the [`KERNEL_AREAS`], unmapping all of its pages that
+/// were mapped and freeing their frames. The virtual range of an area from [`allocate_area`] is
+/// given back as well.
+///
+/// # Safety
+/// The caller must ensure that nothing uses the memory of the area anymore.
+pub unsafe fn release_area(start: VirtualAddress) -> Option<VirtualMemoryArea> {
+    let area = KERNEL_AREAS.lock().unregister(start)?;
+
+    let mut mapper = memory::memory_mapper().lock();
+    for page in area.pages() {
+        if let Ok(frame) = mapper.unmap(page) {
+            mapper.frame_allocator().lock().deallocate_frame(frame);
+        }
+    }
+    drop(mapper);
+
+    free_range(area.start);
+    Some(area)
+}
+
+/// Give the virtual range of an area back, if it was taken by [`allocate_area`].
+fn free_range(start: VirtualAddress) {
+    let mut ranges = KERNEL_VIRTUAL_MEMORY.lock();
+    let owner = ranges
+        .find(start)
+        .filter(|range| range.start == start)
+        .map(|range| range.owner);
+    if owner == Some(RangeOwner::Area) {
+        ranges
+            .free(start)
+            .expect("the range was found right before");
+    }
+}
+
+/// Map the page to a newly allocated frame that is filled with zeros.
+///
+/// Like the rest of the page fault handling it gives up with `PageFaultError::Busy` instead of
+/// waiting for the frame allocator, as the fault might have happened while it was locked.
+fn map_zeroed_page(
+    mapper: &mut impl Mapper,
+    page: Page,
+    flags: PageTableEntryFlags,
+) -> Result<(), PageFaultError> {
+    let frame = mapper
+        .frame_allocator()
+        .try_lock()
+        .ok_or(PageFaultError::Busy)?
+        .allocate_normal_frame()
+        .ok_or(PageFaultError::Mapping(MappingError::FrameAllocationFailed))?;
+
+    // The frame can hold anything, we clear it before it becomes visible through the page.
+    let frame_pointer: *mut u8 = memory::physical_to_virtual(frame.start_address()).as_mut_ptr();
+    unsafe { ptr::write_bytes(frame_pointer, 0, Size4KiB::SIZE as usize) };
+
+    unsafe { mapper.map_to(page, frame, flags | PageTableEntryFlags::PRESENT) }.map_err(|error| {
+        mapper.frame_allocator().lock().deallocate_frame(frame);
+        PageFaultError::Mapping(error)
+    })
+}
+
+#[cfg(test)]
+fn test_area(start: u64, pages: u64) -> VirtualMemoryArea {
+    VirtualMemoryArea::new(
+        VirtualAddress::new(start),
+        pages * Size4KiB::SIZE,
+        PageTableEntryFlags::WRITABLE,
+        Backing::Lazy,
+    )
+}
+
+#[test_case]
+fn test_registry_rejects_overlapping_and_unaligned_areas() {
+    let mut registry = AreaRegistry::new();
+    assert_eq!(registry.register(test_area(0x10000, 4)), Ok(()));
+
+    assert_eq!(
+        registry.register(test_area(0x12000, 4)),
+        Err(AreaError::Overlapping)
+    );
+    assert_eq!(
+        registry.register(test_area(0x8000, 0)),
+        Err(AreaError::NotPageAligned)
+    );
+    assert_eq!(
+        registry.register(test_area(0x8800, 1)),
+        Err(AreaError::NotPageAligned)
+    );
+    // directly adjacent areas do not overlap
+    assert_eq!(registry.register(test_area(0xc000, 4)), Ok(()));
+    assert_eq!(registry.register(test_area(0x14000, 1)), Ok(()));
+}
+
+#[test_case]
+fn test_registry_finds_the_area_containing_an_address() {
+    let mut registry = AreaRegistry::new();
+    registry.register(test_area(0x20000, 2)).unwrap();
+    registry.register(test_area(0x10000, 2)).unwrap();
+
+    let starts = registry.iter().map(|area| area.start.as_u64());
+    assert!(starts.eq([0x10000, 0x20000]));
+
+    let find = |registry: &AreaRegistry, address| {
+        registry
+            .find(VirtualAddress::new(address))
+            .map(|area| area.start.as_u64())
+    };
+    assert_eq!(find(&registry, 0x11fff), Some(0x10000));
+    assert_eq!(find(&registry, 0x12000), None);
+    assert_eq!(find(&registry, 0x20000), Some(0x20000));
+
+    assert!(registry.unregister(VirtualAddress::new(0x10000)).is_some());
+    assert_eq!(find(&registry, 0x10000), None);
+}
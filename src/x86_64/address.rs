@@ -72,12 +72,26 @@ impl VirtualAddress {
         Self(((addr << len) as i64 >> len) as u64)
     }
 
+    /// Creates the canonical virtual address of the page with the given page table indices.
+    pub fn from_page_table_indices(
+        p4_index: PageTableIndex,
+        p3_index: PageTableIndex,
+        p2_index: PageTableIndex,
+        p1_index: PageTableIndex,
+    ) -> Self {
+        let mut address = 0;
+        for index in [p4_index, p3_index, p2_index, p1_index] {
+            address = (address << PAGE_TABLE_INDEX_BITS) | u64::from(index);
+        }
+        Self::new_truncate(address << OFFSET_BITS)
+    }
+
     #[cfg(test)]
     pub fn from_raw(address: u64) -> Self {
         Self(address)
     }
 
-    pub fn zero() -> Self {
+    pub const fn zero() -> Self {
         Self(0)
     }
 
@@ -114,7 +128,7 @@ impl VirtualAddress {
                 PageOffset::Huge(PageOffsetInner::new_truncate(self.0 as u32))
             }
             PageTableLevel::Level3 => {
-                PageOffset::Huge(PageOffsetInner::new_truncate(self.0 as u32))
+                PageOffset::Giant(PageOffsetInner::new_truncate(self.0 as u32))
             }
             PageTableLevel::Level4 => {
                 panic!("VirtualAddress::page_offset: level 4 is not supported");
@@ -122,6 +136,13 @@ impl VirtualAddress {
         }
     }
 
+    pub fn align_down(&self, alignment: u64) -> Self {
+        if !alignment.is_power_of_two() {
+            panic!("alignment must be a power of two");
+        }
+        VirtualAddress::new(self.0 & !(alignment - 1))
+    }
+
     /// Returns the page table index of level 1 page table
     pub fn p1_index(self) -> PageTableIndex {
         PageTableIndex::new_truncate((self.0 >> OFFSET_BITS) as u16)
@@ -149,6 +170,16 @@ impl VirtualAddress {
                 >> PAGE_TABLE_INDEX_BITS) as u16,
         )
     }
+
+    /// Returns the page table index of the page table at the given level
+    pub fn page_table_index(self, level: PageTableLevel) -> PageTableIndex {
+        match level {
+            PageTableLevel::Level1 => self.p1_index(),
+            PageTableLevel::Level2 => self.p2_index(),
+            PageTableLevel::Level3 => self.p3_index(),
+            PageTableLevel::Level4 => self.p4_index(),
+        }
+    }
 }
 
 impl Add<u64> for VirtualAddress {
@@ -306,6 +337,20 @@ fn test_page_table_index_extraction_works() {
     );
 }
 
+#[test_case]
+fn test_address_from_page_table_indices_is_inverse_of_index_extraction() {
+    let address = VirtualAddress::new(0o177_777_401_000_777_177_0000);
+    assert_eq!(
+        VirtualAddress::from_page_table_indices(
+            address.p4_index(),
+            address.p3_index(),
+            address.p2_index(),
+            address.p1_index(),
+        ),
+        address
+    );
+}
+
 #[test_case]
 fn test_page_table_offset_exstaction_works() {
     let address: u64 = 0o001_000_777_177_2716;
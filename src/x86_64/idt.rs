@@ -1,6 +1,6 @@
 //! Provides types for the Interrupt Descriptor Table and its entries.
 
-use core::{arch::asm, ops::Range};
+use core::{arch::asm, fmt, ops::Range};
 
 use bit_field::BitField;
 use bitflags::bitflags;
@@ -12,39 +12,79 @@ use super::{
 };
 
 const DEFAULT_RESERVED: u32 = 0;
-const IDT_SIZE: usize = 64;
+const IDT_SIZE: usize = 256;
 
+const IDT_INDEX_DIVIDE_ERROR_EXCEPTION: u8 = 0;
+const IDT_INDEX_DEBUG_EXCEPTION: u8 = 1;
+const IDT_INDEX_NON_MASKABLE_INTERRUPT: u8 = 2;
 const IDT_INDEX_BREAKPOINT_EXCEPTION: u8 = 3;
+const IDT_INDEX_OVERFLOW_EXCEPTION: u8 = 4;
+const IDT_INDEX_BOUND_RANGE_EXCEEDED_EXCEPTION: u8 = 5;
+const IDT_INDEX_INVALID_OPCODE_EXCEPTION: u8 = 6;
+const IDT_INDEX_DEVICE_NOT_AVAILABLE_EXCEPTION: u8 = 7;
 const IDT_INDEX_DOUBLE_FAULT_EXCEPTION: u8 = 8;
+const IDT_INDEX_COPROCESSOR_SEGMENT_OVERRUN_EXCEPTION: u8 = 9;
+const IDT_INDEX_INVALID_TSS_EXCEPTION: u8 = 10;
+const IDT_INDEX_SEGMENT_NOT_PRESENT_EXCEPTION: u8 = 11;
+const IDT_INDEX_STACK_SEGMENT_FAULT_EXCEPTION: u8 = 12;
+const IDT_INDEX_GENERAL_PROTECTION_FAULT_EXCEPTION: u8 = 13;
 const IDT_INDEX_PAGE_FAULT_EXCEPTION: u8 = 14;
+const IDT_INDEX_X87_FLOATING_POINT_EXCEPTION: u8 = 16;
+const IDT_INDEX_ALIGNMENT_CHECK_EXCEPTION: u8 = 17;
+const IDT_INDEX_MACHINE_CHECK_EXCEPTION: u8 = 18;
+const IDT_INDEX_SIMD_FLOATING_POINT_EXCEPTION: u8 = 19;
+const IDT_INDEX_VIRTUALIZATION_EXCEPTION: u8 = 20;
+const IDT_INDEX_CONTROL_PROTECTION_EXCEPTION: u8 = 21;
+const IDT_INDEX_HYPERVISOR_INJECTION_EXCEPTION: u8 = 28;
+const IDT_INDEX_VMM_COMMUNICATION_EXCEPTION: u8 = 29;
+const IDT_INDEX_SECURITY_EXCEPTION: u8 = 30;
 
 const NUMBER_OF_EXCEPTION_HANDLERS: u8 = 32;
 
 const ENTRY_OPTIONS_IST_INDEX_BITS: Range<usize> = 0..3;
 
+const SELECTOR_ERROR_CODE_EXTERNAL_BIT: usize = 0;
+const SELECTOR_ERROR_CODE_TABLE_BITS: Range<usize> = 1..3;
+const SELECTOR_ERROR_CODE_INDEX_BITS: Range<usize> = 3..16;
+
 /// The harware calls the Interrupt Descriptor Table (IDT) to handle all the interrupts that can
 /// occur. The hardware uses this table directly so we need to follow a predefined format.
 ///
-/// We create an Interrupt Descriptor Table (IDT) with 16 entries. Ideally it has 256 entries.
-/// When the entries are missing the CPU simply generates a double fault.
+/// We create an Interrupt Descriptor Table (IDT) with all 256 entries. When an entry is missing
+/// the CPU simply generates a double fault.
+///
+/// The first 32 entries are reserved for the exceptions defined by the architecture. Each of them
+/// has its own setter, as the handler signature depends on whether the CPU pushes an error code
+/// and what it means:
 ///
-/// The 16 entries that we take care of here are:
-/// - Divide by zero
-/// - Debug
-/// - Non maskable interrupt
-/// - Breakpoint
-/// - Overflow
-/// - Bound range exceeded
-/// - Invalid opcode
-/// - device not available
-/// - double fault
-/// - coprocessor segment overrun
-/// - invalid TSS
-/// - segment not Present
-/// - stack fault
-/// - general protection fault
-/// - page fault
-/// - reserved
+/// | Index | Exception                   | Error code             |
+/// | ----- | --------------------------- | ---------------------- |
+/// | 0     | Divide error                |                        |
+/// | 1     | Debug                       |                        |
+/// | 2     | Non maskable interrupt      |                        |
+/// | 3     | Breakpoint                  |                        |
+/// | 4     | Overflow                    |                        |
+/// | 5     | Bound range exceeded        |                        |
+/// | 6     | Invalid opcode              |                        |
+/// | 7     | Device not available        |                        |
+/// | 8     | Double fault                | always zero            |
+/// | 9     | Coprocessor segment overrun |                        |
+/// | 10    | Invalid TSS                 | [`SelectorErrorCode`]  |
+/// | 11    | Segment not present         | [`SelectorErrorCode`]  |
+/// | 12    | Stack segment fault         | [`SelectorErrorCode`]  |
+/// | 13    | General protection fault    | [`SelectorErrorCode`]  |
+/// | 14    | Page fault                  | [`PageFaultErrorCode`] |
+/// | 16    | x87 floating point          |                        |
+/// | 17    | Alignment check             | always zero            |
+/// | 18    | Machine check               |                        |
+/// | 19    | SIMD floating point         |                        |
+/// | 20    | Virtualization              |                        |
+/// | 21    | Control protection          | cause of the fault     |
+/// | 28    | Hypervisor injection        |                        |
+/// | 29    | VMM communication           | cause of the exit      |
+/// | 30    | Security                    | cause of the exception |
+///
+/// The remaining ones (15, 22-27 and 31) are reserved and never raised, so they can't be set.
 pub struct InterruptDescriptorTable([Entry; IDT_SIZE]);
 
 /// An Interrupt Descriptor Table entry.
@@ -174,16 +214,92 @@ impl InterruptDescriptorTable {
         InterruptDescriptorTable([Entry::missing(); IDT_SIZE])
     }
 
-    fn set_handler(&mut self, index: u8, handler_func: HandlerFunc) {
-        self.0[index as usize] = Entry::new(get_current_code_segment(), handler_func as u64);
+    /// Point the entry at `index` to the handler at the given address and return its options.
+    #[allow(unaligned_references)]
+    fn set_handler_address(&mut self, index: u8, handler_address: u64) -> &mut EntryOptions {
+        self.0[index as usize] = Entry::new(get_current_code_segment(), handler_address);
+        &mut self.0[index as usize].options
+    }
+
+    fn set_handler(&mut self, index: u8, handler_func: HandlerFunc) -> &mut EntryOptions {
+        self.set_handler_address(index, handler_func as u64)
+    }
+
+    /// A divide error occurs when the denominator of a `DIV` or `IDIV` instruction is 0, or the
+    /// result is too big for the destination.
+    ///
+    /// The saved instruction pointer points to the instruction that caused the exception.
+    pub fn set_divide_error_handler(&mut self, handler_func: HandlerFunc) -> &mut EntryOptions {
+        self.set_handler(IDT_INDEX_DIVIDE_ERROR_EXCEPTION, handler_func)
+    }
+
+    /// A debug exception occurs on instruction or data breakpoints set in the debug registers,
+    /// after every instruction while single stepping (`RFLAGS.TF`) and on task switches to a task
+    /// with the debug trap flag set.
+    ///
+    /// Whether the saved instruction pointer points to the instruction that caused the exception
+    /// or the one after it depends on the cause (see `DR6`).
+    pub fn set_debug_handler(&mut self, handler_func: HandlerFunc) -> &mut EntryOptions {
+        self.set_handler(IDT_INDEX_DEBUG_EXCEPTION, handler_func)
+    }
+
+    /// A non maskable interrupt (NMI) is raised by external hardware, usually to report hardware
+    /// errors or a watchdog timeout. It can't be disabled with `CLI`.
+    ///
+    /// The saved instruction pointer points to the instruction that would have been executed
+    /// next.
+    pub fn set_non_maskable_interrupt_handler(
+        &mut self,
+        handler_func: HandlerFunc,
+    ) -> &mut EntryOptions {
+        self.set_handler(IDT_INDEX_NON_MASKABLE_INTERRUPT, handler_func)
     }
 
     /// A breakpoint exception occurs when an `INT3` instruction is executed. The `INT3` is
     /// normally used by debug software to set instruction breakpoints by replacing
     ///
     /// The saved instruction pointer points to the byte after the `INT3` instruction.
-    pub fn set_breakpoint_handler(&mut self, handler_func: HandlerFunc) {
-        self.set_handler(IDT_INDEX_BREAKPOINT_EXCEPTION, handler_func);
+    pub fn set_breakpoint_handler(&mut self, handler_func: HandlerFunc) -> &mut EntryOptions {
+        self.set_handler(IDT_INDEX_BREAKPOINT_EXCEPTION, handler_func)
+    }
+
+    /// An overflow exception occurs when an `INTO` instruction is executed while `RFLAGS.OF` is
+    /// set.
+    ///
+    /// The saved instruction pointer points to the instruction after the `INTO`.
+    pub fn set_overflow_handler(&mut self, handler_func: HandlerFunc) -> &mut EntryOptions {
+        self.set_handler(IDT_INDEX_OVERFLOW_EXCEPTION, handler_func)
+    }
+
+    /// A bound range exceeded exception occurs when a `BOUND` instruction finds its index outside
+    /// of the given bounds.
+    ///
+    /// The saved instruction pointer points to the `BOUND` instruction.
+    pub fn set_bound_range_exceeded_handler(
+        &mut self,
+        handler_func: HandlerFunc,
+    ) -> &mut EntryOptions {
+        self.set_handler(IDT_INDEX_BOUND_RANGE_EXCEEDED_EXCEPTION, handler_func)
+    }
+
+    /// An invalid opcode exception occurs when the processor tries to execute an instruction that
+    /// is undefined, reserved, not supported or not allowed in the current mode (e.g. `UD2`).
+    ///
+    /// The saved instruction pointer points to the instruction that caused the exception.
+    pub fn set_invalid_opcode_handler(&mut self, handler_func: HandlerFunc) -> &mut EntryOptions {
+        self.set_handler(IDT_INDEX_INVALID_OPCODE_EXCEPTION, handler_func)
+    }
+
+    /// A device not available exception occurs when an x87, MMX or SSE instruction is executed
+    /// while the FPU is disabled (`CR0.EM`) or its state was not restored after a task switch
+    /// (`CR0.TS`).
+    ///
+    /// The saved instruction pointer points to the instruction that caused the exception.
+    pub fn set_device_not_available_handler(
+        &mut self,
+        handler_func: HandlerFunc,
+    ) -> &mut EntryOptions {
+        self.set_handler(IDT_INDEX_DEVICE_NOT_AVAILABLE_EXCEPTION, handler_func)
     }
 
     /// Double fault exception can occur when a second exception occurs during the handling of a
@@ -209,15 +325,78 @@ impl InterruptDescriptorTable {
     ///
     /// If a third interrupting event occurs while transferring control to the `#DF` handler, the
     /// processor shuts down.
-    #[allow(unaligned_references)]
     pub fn set_double_fault_handler(
         &mut self,
         handler_func: DoubleFaultHandlerFunc,
     ) -> &mut EntryOptions {
-        let entry = Entry::new(get_current_code_segment(), handler_func as u64);
-        self.0[IDT_INDEX_DOUBLE_FAULT_EXCEPTION as usize] = entry;
+        self.set_handler_address(IDT_INDEX_DOUBLE_FAULT_EXCEPTION, handler_func as u64)
+    }
+
+    /// The coprocessor segment overrun exception is only raised by 386 processors with an
+    /// external FPU. Current processors raise a general protection fault instead.
+    pub fn set_coprocessor_segment_overrun_handler(
+        &mut self,
+        handler_func: HandlerFunc,
+    ) -> &mut EntryOptions {
+        self.set_handler(
+            IDT_INDEX_COPROCESSOR_SEGMENT_OVERRUN_EXCEPTION,
+            handler_func,
+        )
+    }
+
+    /// An invalid TSS exception occurs when a task switch or a stack switch through the TSS runs
+    /// into an invalid segment selector or descriptor.
+    ///
+    /// The error code is the [`SelectorErrorCode`] of the offending selector. The saved
+    /// instruction pointer points to the instruction that caused the exception.
+    pub fn set_invalid_tss_handler(
+        &mut self,
+        handler_func: SelectorErrorHandlerFunc,
+    ) -> &mut EntryOptions {
+        self.set_handler_address(IDT_INDEX_INVALID_TSS_EXCEPTION, handler_func as u64)
+    }
 
-        &mut self.0[IDT_INDEX_DOUBLE_FAULT_EXCEPTION as usize].options
+    /// A segment not present exception occurs when loading a segment or gate descriptor whose
+    /// present bit is cleared (except for the stack segment, see
+    /// [`InterruptDescriptorTable::set_stack_segment_fault_handler`]).
+    ///
+    /// The error code is the [`SelectorErrorCode`] of the offending selector. The saved
+    /// instruction pointer points to the instruction that caused the exception.
+    pub fn set_segment_not_present_handler(
+        &mut self,
+        handler_func: SelectorErrorHandlerFunc,
+    ) -> &mut EntryOptions {
+        self.set_handler_address(IDT_INDEX_SEGMENT_NOT_PRESENT_EXCEPTION, handler_func as u64)
+    }
+
+    /// A stack segment fault occurs when loading a stack segment that is not present or when a
+    /// stack access uses a non-canonical address.
+    ///
+    /// The error code is the [`SelectorErrorCode`] of the offending selector, or zero if the
+    /// fault was not caused by a selector. The saved instruction pointer points to the
+    /// instruction that caused the exception.
+    pub fn set_stack_segment_fault_handler(
+        &mut self,
+        handler_func: SelectorErrorHandlerFunc,
+    ) -> &mut EntryOptions {
+        self.set_handler_address(IDT_INDEX_STACK_SEGMENT_FAULT_EXCEPTION, handler_func as u64)
+    }
+
+    /// A general protection fault occurs on a lot of protection violations, e.g. when loading an
+    /// invalid segment selector, accessing a non-canonical address, executing a privileged
+    /// instruction outside of ring 0 or writing a reserved bit of a control register.
+    ///
+    /// The error code is the [`SelectorErrorCode`] of the offending selector, or zero if the
+    /// fault was not caused by a selector. The saved instruction pointer points to the
+    /// instruction that caused the exception.
+    pub fn set_general_protection_fault_handler(
+        &mut self,
+        handler_func: SelectorErrorHandlerFunc,
+    ) -> &mut EntryOptions {
+        self.set_handler_address(
+            IDT_INDEX_GENERAL_PROTECTION_FAULT_EXCEPTION,
+            handler_func as u64,
+        )
     }
 
     /// A page fault can occur during a memory access in any of the following situations:
@@ -237,9 +416,105 @@ impl InterruptDescriptorTable {
     ///
     /// The page-fault error code is described by the
     /// [`PageFaultErrorCode`](struct.PageFaultErrorCode.html) struct.
-    pub fn set_page_fault_handler(&mut self, handler_func: PageFaultHandlerFunc) {
-        self.0[IDT_INDEX_PAGE_FAULT_EXCEPTION as usize] =
-            Entry::new(get_current_code_segment(), handler_func as u64);
+    pub fn set_page_fault_handler(
+        &mut self,
+        handler_func: PageFaultHandlerFunc,
+    ) -> &mut EntryOptions {
+        self.set_handler_address(IDT_INDEX_PAGE_FAULT_EXCEPTION, handler_func as u64)
+    }
+
+    /// An x87 floating point exception occurs on an unmasked x87 floating point error (e.g. a
+    /// division by zero with `FDIV`). It is reported on the next x87 instruction.
+    ///
+    /// The saved instruction pointer points to the instruction that is about to be executed when
+    /// the exception is reported.
+    pub fn set_x87_floating_point_handler(
+        &mut self,
+        handler_func: HandlerFunc,
+    ) -> &mut EntryOptions {
+        self.set_handler(IDT_INDEX_X87_FLOATING_POINT_EXCEPTION, handler_func)
+    }
+
+    /// An alignment check exception occurs on unaligned memory accesses in ring 3 while alignment
+    /// checking is enabled (`CR0.AM` and `RFLAGS.AC`).
+    ///
+    /// The error code is always zero. The saved instruction pointer points to the instruction
+    /// that caused the exception.
+    pub fn set_alignment_check_handler(
+        &mut self,
+        handler_func: HandlerFuncWithErrorCode,
+    ) -> &mut EntryOptions {
+        self.set_handler_address(IDT_INDEX_ALIGNMENT_CHECK_EXCEPTION, handler_func as u64)
+    }
+
+    /// A machine check exception occurs when the processor detects an internal error or a bus
+    /// error (if enabled in `CR4.MCE`). The details are found in the machine check MSRs.
+    ///
+    /// There is no reliable way to continue execution after it, so the handler must not return.
+    pub fn set_machine_check_handler(
+        &mut self,
+        handler_func: DivergingHandlerFunc,
+    ) -> &mut EntryOptions {
+        self.set_handler_address(IDT_INDEX_MACHINE_CHECK_EXCEPTION, handler_func as u64)
+    }
+
+    /// A SIMD floating point exception occurs on an unmasked SSE floating point error. The cause
+    /// is found in the `MXCSR` register.
+    ///
+    /// The saved instruction pointer points to the instruction that caused the exception.
+    pub fn set_simd_floating_point_handler(
+        &mut self,
+        handler_func: HandlerFunc,
+    ) -> &mut EntryOptions {
+        self.set_handler(IDT_INDEX_SIMD_FLOATING_POINT_EXCEPTION, handler_func)
+    }
+
+    /// A virtualization exception occurs on EPT violations inside of a guest if the hypervisor
+    /// enabled it.
+    pub fn set_virtualization_handler(&mut self, handler_func: HandlerFunc) -> &mut EntryOptions {
+        self.set_handler(IDT_INDEX_VIRTUALIZATION_EXCEPTION, handler_func)
+    }
+
+    /// A control protection exception occurs when control flow enforcement (CET) detects a return
+    /// address or indirect branch target that is not allowed.
+    ///
+    /// The error code tells which kind of control transfer caused it.
+    pub fn set_control_protection_handler(
+        &mut self,
+        handler_func: HandlerFuncWithErrorCode,
+    ) -> &mut EntryOptions {
+        self.set_handler_address(IDT_INDEX_CONTROL_PROTECTION_EXCEPTION, handler_func as u64)
+    }
+
+    /// The hypervisor injection exception is raised by a hypervisor into a guest running with
+    /// AMD SEV-SNP restricted injection.
+    pub fn set_hypervisor_injection_handler(
+        &mut self,
+        handler_func: HandlerFunc,
+    ) -> &mut EntryOptions {
+        self.set_handler(IDT_INDEX_HYPERVISOR_INJECTION_EXCEPTION, handler_func)
+    }
+
+    /// The VMM communication exception is raised inside of an AMD SEV-ES guest for events that
+    /// would normally cause an exit to the hypervisor.
+    ///
+    /// The error code holds the reason for the exit.
+    pub fn set_vmm_communication_handler(
+        &mut self,
+        handler_func: HandlerFuncWithErrorCode,
+    ) -> &mut EntryOptions {
+        self.set_handler_address(IDT_INDEX_VMM_COMMUNICATION_EXCEPTION, handler_func as u64)
+    }
+
+    /// The security exception is raised by AMD processors on security sensitive events, e.g. an
+    /// `INIT` signal redirected by SVM.
+    ///
+    /// The error code tells what caused it.
+    pub fn set_security_handler(
+        &mut self,
+        handler_func: HandlerFuncWithErrorCode,
+    ) -> &mut EntryOptions {
+        self.set_handler_address(IDT_INDEX_SECURITY_EXCEPTION, handler_func as u64)
     }
 
     /// User-defined interrupts can be initiated either by system logic or software. They occur
@@ -326,10 +601,82 @@ pub struct ExceptionStackFrame {
 /// Given that Entry has pointers to actual handlers we can use different types in the HandlerFunc
 type HandlerFunc = extern "x86-interrupt" fn(ExceptionStackFrame);
 
+type HandlerFuncWithErrorCode = extern "x86-interrupt" fn(ExceptionStackFrame, u64);
+
+type DivergingHandlerFunc = extern "x86-interrupt" fn(ExceptionStackFrame) -> !;
+
 type DoubleFaultHandlerFunc = extern "x86-interrupt" fn(ExceptionStackFrame, u64) -> !;
 
+type SelectorErrorHandlerFunc = extern "x86-interrupt" fn(ExceptionStackFrame, SelectorErrorCode);
+
 type PageFaultHandlerFunc = extern "x86-interrupt" fn(ExceptionStackFrame, PageFaultErrorCode);
 
+/// The error code of exceptions that are caused by a segment selector (invalid TSS, segment not
+/// present, stack segment fault and general protection fault).
+///
+/// | Bits  | Name     | Description                                                    |
+/// | ----- | -------- | -------------------------------------------------------------- |
+/// | 0     | External | The exception was caused by an event external to the program.  |
+/// | 1-2   | Table    | The table the selector refers to (see [`DescriptorTable`]).    |
+/// | 3-15  | Index    | The index of the descriptor in that table.                     |
+///
+/// An error code of zero means that the exception was not caused by a selector.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct SelectorErrorCode(u64);
+
+/// The descriptor table a [`SelectorErrorCode`] refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescriptorTable {
+    Gdt,
+    Idt,
+    Ldt,
+}
+
+impl SelectorErrorCode {
+    pub fn new(error_code: u64) -> Self {
+        SelectorErrorCode(error_code)
+    }
+
+    /// Whether the exception was caused by something outside of the program, e.g. a hardware
+    /// interrupt.
+    pub fn is_external(&self) -> bool {
+        self.0.get_bit(SELECTOR_ERROR_CODE_EXTERNAL_BIT)
+    }
+
+    pub fn descriptor_table(&self) -> DescriptorTable {
+        match self.0.get_bits(SELECTOR_ERROR_CODE_TABLE_BITS) {
+            0b00 => DescriptorTable::Gdt,
+            0b10 => DescriptorTable::Ldt,
+            // 0b01 and 0b11 both refer to the IDT
+            _ => DescriptorTable::Idt,
+        }
+    }
+
+    /// Index of the descriptor in the [`SelectorErrorCode::descriptor_table`].
+    pub fn index(&self) -> u16 {
+        self.0.get_bits(SELECTOR_ERROR_CODE_INDEX_BITS) as u16
+    }
+
+    /// Whether the exception was not caused by a selector at all.
+    pub fn is_null(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl fmt::Debug for SelectorErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_null() {
+            return f.write_str("SelectorErrorCode(none)");
+        }
+        f.debug_struct("SelectorErrorCode")
+            .field("external", &self.is_external())
+            .field("descriptor_table", &self.descriptor_table())
+            .field("index", &self.index())
+            .finish()
+    }
+}
+
 bitflags! {
     #[repr(transparent)]
     /// The page fault error code.
@@ -359,3 +706,22 @@ bitflags! {
         const CAUSED_BY_SHADOW_STACK   = 1 << 6;
     }
 }
+
+#[test_case]
+fn test_selector_error_code_is_decoded() {
+    let error_code = SelectorErrorCode::new(0x1230);
+    assert!(!error_code.is_external());
+    assert_eq!(error_code.descriptor_table(), DescriptorTable::Gdt);
+    assert_eq!(error_code.index(), 0x246);
+
+    let error_code = SelectorErrorCode::new((8 << 3) | 0b011);
+    assert!(error_code.is_external());
+    assert_eq!(error_code.descriptor_table(), DescriptorTable::Idt);
+    assert_eq!(error_code.index(), 8);
+
+    assert_eq!(
+        SelectorErrorCode::new(0b100).descriptor_table(),
+        DescriptorTable::Ldt
+    );
+    assert!(SelectorErrorCode::new(0).is_null());
+}
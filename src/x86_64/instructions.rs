@@ -10,6 +10,8 @@ use super::{
 
 const CR3_PHYSICAL_ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
 const CR3_FLAGS_MASK: u64 = 0xfff;
+/// Number of the model specific register that holds the Extended Feature Enable Register.
+const IA32_EFER: u32 = 0xc000_0080;
 
 /// Puts the CPU to sleep till it encounters the next interrupt. Calling in a loop can be
 /// significantly less resourse intensive than a busy-loop.
@@ -54,6 +56,58 @@ pub unsafe fn write_control_register_3(page_frame: PageFrame, flags: Cr3Flags) {
     asm!("mov cr3, {}", in(reg) value, options(nostack, preserves_flags));
 }
 
+bitflags! {
+    /// Flags of the Extended Feature Enable Register (EFER).
+    pub struct EferFlags: u64 {
+        /// Enables the `syscall` and `sysret` instructions.
+        const SYSTEM_CALL_EXTENSIONS = 1 << 0;
+        /// Enables long mode (once paging is enabled).
+        const LONG_MODE_ENABLE = 1 << 8;
+        /// Set by the CPU while long mode is active.
+        const LONG_MODE_ACTIVE = 1 << 10;
+        /// Enables the `NO_EXECUTE` page table entry flag. Without it the bit is reserved and
+        /// setting it causes a page fault.
+        const NO_EXECUTE_ENABLE = 1 << 11;
+    }
+}
+
+/// Read the model specific register with the given number.
+///
+/// # Safety
+/// The register must exist on this CPU, reading a register that doesn't causes a general
+/// protection fault.
+pub unsafe fn read_model_specific_register(register: u32) -> u64 {
+    let (high, low): (u32, u32);
+    asm!("rdmsr", in("ecx") register, out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+    ((high as u64) << 32) | (low as u64)
+}
+
+/// Write the model specific register with the given number.
+///
+/// # Safety
+/// The register must exist on this CPU. Writing a model specific register can change the
+/// behaviour of the CPU in ways that break memory safety.
+pub unsafe fn write_model_specific_register(register: u32, value: u64) {
+    let low = value as u32;
+    let high = (value >> 32) as u32;
+    asm!("wrmsr", in("ecx") register, in("eax") low, in("edx") high, options(nostack, preserves_flags));
+}
+
+/// Read the flags of the Extended Feature Enable Register.
+pub fn read_extended_feature_enable_register() -> EferFlags {
+    EferFlags::from_bits_truncate(unsafe { read_model_specific_register(IA32_EFER) })
+}
+
+/// Write the flags of the Extended Feature Enable Register. Reserved bits are left untouched.
+///
+/// # Safety
+/// Unsetting some of the flags (e.g. `LONG_MODE_ENABLE` or `NO_EXECUTE_ENABLE` while it is
+/// used) breaks the assumptions the kernel runs under.
+pub unsafe fn write_extended_feature_enable_register(flags: EferFlags) {
+    let reserved = read_model_specific_register(IA32_EFER) & !EferFlags::all().bits();
+    write_model_specific_register(IA32_EFER, reserved | flags.bits());
+}
+
 fn u64_to_page_table_frame_and_cr3_flags(value: u64) -> (PageFrame, Cr3Flags) {
     let physical_address = PhysicalAddress::new(value & CR3_PHYSICAL_ADDRESS_MASK);
     let flags = Cr3Flags::from_bits_truncate(value & CR3_FLAGS_MASK);
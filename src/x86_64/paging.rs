@@ -8,14 +8,15 @@ use core::{
 };
 
 use bitflags::bitflags;
-use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
 
 use super::{
     address::{PhysicalAddress, VirtualAddress},
     instructions::{read_control_register_3, write_control_register_3},
 };
+use crate::{frame_allocator::FrameAllocator, gen_iter, utils::Locked};
 
 const ENTRY_COUNT: usize = 512;
+const PAGE_TABLE_INDEX_BITS: usize = 9;
 const PAGE_TABLE_ENTRY_PHYSICAL_ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
 
 /// Representation of a page table
@@ -29,11 +30,15 @@ impl PageTable {
         self.entries.iter()
     }
 
-    fn clear_all_entries(&mut self) {
+    pub(crate) fn clear_all_entries(&mut self) {
         for entry in self.entries.iter_mut() {
             *entry = PageTableEntry::new();
         }
     }
+
+    fn is_empty(&self) -> bool {
+        self.entries.iter().all(PageTableEntry::is_unused)
+    }
 }
 
 impl Index<PageTableIndex> for PageTable {
@@ -62,6 +67,7 @@ impl IndexMut<PageTableIndex> for PageTable {
 /// - 2, then it points to a frame that is 2MiB in size.
 ///
 /// NOTE: HUGE flag can't be set if the entry is at Level 1 or Level 4
+#[derive(Clone, Copy)]
 #[repr(transparent)]
 pub struct PageTableEntry {
     entry: u64,
@@ -95,6 +101,8 @@ bitflags! {
         // the CPU that don't flush this page from the TLB
         const GLOBAL          = 1 << 8;
         // 9-11 and 52-62 are available for us to use as we see fit (e.g. custom flags etc)
+        // The page is shared and mapped read-only, it gets copied on the first write to it
+        const COPY_ON_WRITE   = 1 << 9;
         // Forbid code execution from this page
         const NO_EXECUTE      = 1 << 63;
     }
@@ -135,10 +143,10 @@ impl PageTableEntry {
             Err(FrameError::FrameNotPresent)
         } else if self.flags().contains(PageTableEntryFlags::HUGE_PAGE) {
             match entry_level {
-                PageTableLevel::Level3 => Ok(PageFrame::Huge(PageFrameInner::containing_address(
+                PageTableLevel::Level3 => Ok(PageFrame::Giant(PageFrameInner::containing_address(
                     self.address(),
                 ))),
-                PageTableLevel::Level2 => Ok(PageFrame::Giant(PageFrameInner::containing_address(
+                PageTableLevel::Level2 => Ok(PageFrame::Huge(PageFrameInner::containing_address(
                     self.address(),
                 ))),
                 _ => panic!(
@@ -153,10 +161,19 @@ impl PageTableEntry {
         }
     }
 
-    fn set_address(&mut self, address: PhysicalAddress, flags: PageTableEntryFlags) {
+    pub(crate) fn set_address(&mut self, address: PhysicalAddress, flags: PageTableEntryFlags) {
         self.entry = (address.as_u64()) | (self.flags().bits() | flags.bits());
     }
 
+    /// Replace the flags of this entry while keeping the address it points to.
+    fn set_flags(&mut self, flags: PageTableEntryFlags) {
+        self.entry = self.address().as_u64() | flags.bits();
+    }
+
+    fn set_unused(&mut self) {
+        self.entry = 0;
+    }
+
     fn is_used(&self) -> bool {
         !self.is_unused()
     }
@@ -186,6 +203,12 @@ impl PageTableIndex {
     }
 }
 
+impl From<PageTableIndex> for u64 {
+    fn from(index: PageTableIndex) -> Self {
+        u64::from(index.0)
+    }
+}
+
 /// Trait for abstracting over the three possible page sizes on x86_64, 4KiB, 2MiB, 1GiB.
 pub trait PageSize: Copy + Eq + PartialOrd + Ord {
     const SIZE: u64;
@@ -244,22 +267,6 @@ where
         self.start_address
     }
 
-    fn p4_index(&self) -> PageTableIndex {
-        self.start_address.p4_index()
-    }
-
-    fn p3_index(&self) -> PageTableIndex {
-        self.start_address.p3_index()
-    }
-
-    fn p2_index(&self) -> PageTableIndex {
-        self.start_address.p2_index()
-    }
-
-    fn p1_index(&self) -> PageTableIndex {
-        self.start_address.p1_index()
-    }
-
     pub fn range_inclusive(start: Self, end: Self) -> PageRangeInclusive<S> {
         PageRangeInclusive { start, end }
     }
@@ -280,6 +287,24 @@ impl Page {
             Page::Giant(inner) => inner.start_address(),
         }
     }
+
+    /// Returns the size of this page in bytes.
+    pub fn size(&self) -> u64 {
+        match self {
+            Page::Normal(_) => Size4KiB::SIZE,
+            Page::Huge(_) => Size2MiB::SIZE,
+            Page::Giant(_) => Size1GiB::SIZE,
+        }
+    }
+
+    /// Level of the [`PageTable`] whose entry maps this page directly.
+    fn mapping_level(&self) -> PageTableLevel {
+        match self {
+            Page::Normal(_) => PageTableLevel::Level1,
+            Page::Huge(_) => PageTableLevel::Level2,
+            Page::Giant(_) => PageTableLevel::Level3,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -378,6 +403,15 @@ impl PageFrame {
         }
     }
 
+    /// Returns the size of this frame in bytes.
+    pub fn size(&self) -> u64 {
+        match self {
+            PageFrame::Normal(_) => Size4KiB::SIZE,
+            PageFrame::Huge(_) => Size2MiB::SIZE,
+            PageFrame::Giant(_) => Size1GiB::SIZE,
+        }
+    }
+
     /// Converts a given [`VirtualAddress`] to a [`PhysicalAddress`].
     fn convert(&self, addr: VirtualAddress) -> PhysicalAddress {
         match self {
@@ -459,6 +493,19 @@ pub enum PageTableLevel {
     Level4,
 }
 
+impl PageTableLevel {
+    /// The level of the [`PageTable`]s that the entries of this level point to. None for
+    /// [`PageTableLevel::Level1`] as its entries only point to [`PageFrame`]s.
+    pub fn next_lower_level(self) -> Option<PageTableLevel> {
+        match self {
+            PageTableLevel::Level1 => None,
+            PageTableLevel::Level2 => Some(PageTableLevel::Level1),
+            PageTableLevel::Level3 => Some(PageTableLevel::Level2),
+            PageTableLevel::Level4 => Some(PageTableLevel::Level3),
+        }
+    }
+}
+
 /// Represents a SIZE-bit offset into a frame of size SIZE.
 ///
 /// - For [`Size4KiB`] this is a 12-bit offset. Can only every contain 0-4095.
@@ -507,38 +554,41 @@ where
     }
 }
 
-/// A Mapper implementation that requires that the complete physically memory is mapped at some
-/// offset in the virtual address space.
-pub struct OffsetMemoryMapper {
-    physical_memory_offset: VirtualAddress,
-    l4_table_address: PageFrame,
-    pub frame_allocator: FrameAllocator,
-}
-
-impl OffsetMemoryMapper {
-    /// Creates a new `OffsetPageTable` that uses the given offset for converting virtual
-    /// to physical addresses.
-    ///
-    /// The complete physical memory must be mapped in the virtual address space starting at
-    /// address `phys_offset`. This means that for example physical address `0x5000` can be
-    /// accessed through virtual address `phys_offset + 0x5000`. This mapping is required because
-    /// the mapper needs to access page tables, which are not mapped into the virtual address
-    /// space by default.
+/// Operations to create, inspect and remove mappings in a [`PageTable`] hierarchy.
+///
+/// The CPU only knows the physical addresses of the page tables, while the kernel can only access
+/// memory through virtual addresses. Implementations differ in how they get from one to the other,
+/// which is captured by [`Mapper::level4_table`] and [`Mapper::child_table`]. Everything else is
+/// provided on top of these two methods and works with pages of all sizes.
+pub trait Mapper {
+    /// Pointer to the level 4 [`PageTable`] of the address space.
+    fn level4_table(&self) -> *mut PageTable;
+
+    /// Pointer to the [`PageTable`] that the entry at `index` of `table` points to. `frame` is the
+    /// frame of that entry.
     ///
-    /// ## Safety
+    /// # Safety
+    /// The caller must guarantee that `table` was obtained from this mapper and that the entry at
+    /// `index` points to `frame` which holds a [`PageTable`].
+    unsafe fn child_table(
+        &self,
+        table: *mut PageTable,
+        index: PageTableIndex,
+        frame: PageFrame,
+    ) -> *mut PageTable;
+
+    /// The [`FrameAllocator`] used to allocate and free [`PageTable`] frames.
+    fn frame_allocator(&self) -> &'static Locked<FrameAllocator>;
+
+    /// Whether the level 3 [`PageTable`] of the level 4 entry at `index` may be freed once it has
+    /// no entries left.
     ///
-    /// This function is unsafe because the caller must guarantee that the passed `phys_offset`
-    /// is correct.
-    pub unsafe fn new(
-        physical_memory_offset: VirtualAddress,
-        frame_allocator: FrameAllocator,
-    ) -> Self {
-        let (level4_table_physical_address, _) = read_control_register_3();
-        OffsetMemoryMapper {
-            physical_memory_offset,
-            l4_table_address: level4_table_physical_address,
-            frame_allocator,
-        }
+    /// The kernel's level 4 entries are copied into every
+    /// [`AddressSpace`](crate::address_space::AddressSpace), so their level 3 tables are shared
+    /// and have to stay around even when the kernel no longer maps anything below them. By default
+    /// level 3 tables are never freed.
+    fn owns_level3_table(&self, _index: PageTableIndex) -> bool {
+        false
     }
 
     /// Return the physical address that the given virtual address is mapped to.
@@ -551,32 +601,32 @@ impl OffsetMemoryMapper {
     /// # Panics
     /// If for some reason it detects there is a frame that is huge at level 4 or level 1 it will
     /// panic as these ase impossible states to be in
-    pub fn translate_address(&self, address: VirtualAddress) -> Option<PhysicalAddress> {
-        let cr3_frame = self.l4_table_address;
-
-        let l4_table: &PageTable = unsafe { &*(self.frame_to_pointer(cr3_frame)) };
-        let entry = &l4_table[address.p4_index()];
-        let l4_frame: PageFrame = entry.frame(PageTableLevel::Level4).ok()?;
-
-        let l3_table: &PageTable = unsafe { &*(self.frame_to_pointer(l4_frame)) };
-        let entry = &l3_table[address.p3_index()];
-        let l3_frame: PageFrame = entry.frame(PageTableLevel::Level3).ok()?;
-        if l3_frame.is_huge() {
-            return Some(l3_frame.convert(address));
-        }
+    fn translate_address(&self, address: VirtualAddress) -> Option<PhysicalAddress> {
+        let mut table = self.level4_table();
+        let mut level = PageTableLevel::Level4;
+
+        loop {
+            let index = address.page_table_index(level);
+            let frame = unsafe { &*table }[index].frame(level).ok()?;
+            if level == PageTableLevel::Level1 || frame.is_huge() {
+                return Some(frame.convert(address));
+            }
 
-        let l2_table: &PageTable = unsafe { &*(self.frame_to_pointer(l3_frame)) };
-        let entry = &l2_table[address.p2_index()];
-        let l2_frame: PageFrame = entry.frame(PageTableLevel::Level2).ok()?;
-        if l2_frame.is_huge() {
-            return Some(l2_frame.convert(address));
+            table = unsafe { self.child_table(table, index, frame) };
+            level = level.next_lower_level()?;
         }
+    }
 
-        let l1_table: &PageTable = unsafe { &*(self.frame_to_pointer(l2_frame)) };
-        let entry = &l1_table[address.p1_index()];
-        let l1_frame: PageFrame = entry.frame(PageTableLevel::Level1).ok()?;
-
-        Some(l1_frame.convert(address))
+    /// Return the [`PageFrame`] the given [`Page`] is mapped to along with the flags of the
+    /// mapping.
+    ///
+    /// Returns the same errors as [`Mapper::unmap`].
+    fn translate_page(&self, page: Page) -> Result<(PageFrame, PageTableEntryFlags), MappingError> {
+        let entry = unsafe { leaf_entry(self, page, None)? };
+        let frame = entry
+            .frame(page.mapping_level())
+            .map_err(|_| MappingError::PageNotMapped)?;
+        Ok((frame, entry.flags()))
     }
 
     /// Create a new mapping in the [`PageTable`]
@@ -608,140 +658,275 @@ impl OffsetMemoryMapper {
     /// the GLOBAL flag. For example, a global mapping must be the same in all address spaces,
     /// otherwise undefined behavior can occur because of TLB races. It’s worth noting that all the
     /// above requirements also apply to shared mappings, including the aliasing requirements.
-    pub unsafe fn map_to(
+    unsafe fn map_to(
         &mut self,
         page: Page,
         frame: PageFrame,
         flags: PageTableEntryFlags,
     ) -> Result<(), MappingError> {
-        let cr3_frame = self.l4_table_address;
-
-        match (page, frame) {
-            (Page::Normal(page), PageFrame::Normal(frame)) => {
-                let l4_table: &mut PageTable = &mut *(self.frame_to_pointer(cr3_frame));
-                let l4_entry = &mut l4_table[page.p4_index()];
-
-                let l4_frame: PageFrame = l4_entry
-                    .frame(PageTableLevel::Level4)
-                    .or_else(|_| self.create_table_frame(l4_entry, flags))?;
-
-                let l3_table: &mut PageTable = &mut *(self.frame_to_pointer(l4_frame));
-                let l3_entry = &mut l3_table[page.p3_index()];
-
-                let l3_frame: PageFrame = l3_entry
-                    .frame(PageTableLevel::Level3)
-                    .or_else(|_| self.create_table_frame(l3_entry, flags))?;
-
-                let l2_table: &mut PageTable = &mut *(self.frame_to_pointer(l3_frame));
-                let l2_entry = &mut l2_table[page.p2_index()];
-
-                let l2_frame: PageFrame = l2_entry
-                    .frame(PageTableLevel::Level2)
-                    .or_else(|_| self.create_table_frame(l2_entry, flags))?;
-
-                let l1_table: &mut PageTable = &mut *(self.frame_to_pointer(l2_frame));
-                let l1_entry = &mut l1_table[page.p1_index()];
-
-                if l1_entry.is_used() {
-                    return Err(MappingError::PageTableEntryAlreadyUsed);
-                }
-
-                l1_entry.set_address(frame.start_address(), flags);
-
-                // Flush any previous mapping that this [`VirtualAddress`] might have had.
-                flush_address_from_tlb(page.start_address());
-
-                Ok(())
+        let flags = match (page, frame) {
+            (Page::Normal(_), PageFrame::Normal(_)) => flags,
+            (Page::Huge(_), PageFrame::Huge(_)) | (Page::Giant(_), PageFrame::Giant(_)) => {
+                flags | PageTableEntryFlags::HUGE_PAGE
             }
-            (Page::Huge(page), PageFrame::Huge(frame)) => {
-                let l4_table: &mut PageTable = &mut *(self.frame_to_pointer(cr3_frame));
-                let l4_entry = &mut l4_table[page.p4_index()];
+            // Other combinations are not invaild
+            _ => return Err(MappingError::InvalidPageFrameMapping),
+        };
 
-                let l4_frame: PageFrame = l4_entry
-                    .frame(PageTableLevel::Level4)
-                    .or_else(|_| self.create_table_frame(l4_entry, flags))?;
+        let entry = leaf_entry(self, page, Some(flags))?;
+        if entry.is_used() {
+            return Err(MappingError::PageTableEntryAlreadyUsed);
+        }
 
-                let l3_table: &mut PageTable = &mut *(self.frame_to_pointer(l4_frame));
-                let l3_entry = &mut l3_table[page.p3_index()];
+        entry.set_address(frame.start_address(), flags);
 
-                let l3_frame: PageFrame = l3_entry
-                    .frame(PageTableLevel::Level3)
-                    .or_else(|_| self.create_table_frame(l3_entry, flags))?;
+        // Flush any previous mapping that this [`VirtualAddress`] might have had.
+        flush_address_from_tlb(page.start_address());
 
-                let l2_table: &mut PageTable = &mut *(self.frame_to_pointer(l3_frame));
-                let l2_entry = &mut l2_table[page.p1_index()];
+        Ok(())
+    }
 
-                if l2_entry.is_used() {
-                    return Err(MappingError::PageTableEntryAlreadyUsed);
-                }
+    /// Remove the mapping of the given [`Page`] and return the [`PageFrame`] it was mapped to.
+    ///
+    /// The returned frame is not deallocated, that is left to the caller as the frame might still
+    /// be mapped somewhere else. Intermediate [`PageTable`]s that are left without any entries are
+    /// returned to the [`FrameAllocator`] though (level 3 tables only if
+    /// [`Mapper::owns_level3_table`]).
+    ///
+    /// Returns the following errors:
+    ///
+    /// - `MappingError::PageNotMapped` if the page (or one of its parent tables) is not mapped.
+    /// - `MappingError::ParentEntryHugePage` if the page is part of a bigger huge page.
+    /// - `MappingError::InvalidPageFrameMapping` if the page is mapped with a different size.
+    ///
+    /// # Safety
+    /// The caller must ensure that nothing uses the page anymore. Any access to it after this
+    /// call will cause a page fault.
+    unsafe fn unmap(&mut self, page: Page) -> Result<PageFrame, MappingError> {
+        let entry = leaf_entry(self, page, None)?;
+        let frame = entry
+            .frame(page.mapping_level())
+            .map_err(|_| MappingError::PageNotMapped)?;
+
+        entry.set_unused();
+        flush_address_from_tlb(page.start_address());
+        free_empty_tables(self, page.start_address());
 
-                l2_entry.set_address(
-                    frame.start_address(),
-                    flags | PageTableEntryFlags::HUGE_PAGE,
-                );
+        Ok(frame)
+    }
 
-                // Flush any previous mapping that this [`VirtualAddress`] might have had.
-                flush_address_from_tlb(page.start_address());
+    /// Point an existing mapping of the given [`Page`] to a different [`PageFrame`] of the same
+    /// size with new flags, returning the frame it was mapped to before.
+    ///
+    /// Like for [`Mapper::unmap`] the old frame is not deallocated. Returns the same errors as
+    /// [`Mapper::unmap`].
+    ///
+    /// # Safety
+    /// The same as for [`Mapper::map_to`] applies to the new frame. Anything referencing the page
+    /// will see the contents of the new frame from now on.
+    unsafe fn remap(
+        &mut self,
+        page: Page,
+        frame: PageFrame,
+        flags: PageTableEntryFlags,
+    ) -> Result<PageFrame, MappingError> {
+        let entry = leaf_entry(self, page, None)?;
+        let old_frame = entry
+            .frame(page.mapping_level())
+            .map_err(|_| MappingError::PageNotMapped)?;
+        if old_frame.size() != frame.size() {
+            return Err(MappingError::InvalidPageFrameMapping);
+        }
 
-                Ok(())
-            }
-            (Page::Giant(page), PageFrame::Giant(frame)) => {
-                let l4_table: &mut PageTable = &mut *(self.frame_to_pointer(cr3_frame));
-                let l4_entry = &mut l4_table[page.p4_index()];
+        let flags = match page {
+            Page::Normal(_) => flags,
+            Page::Huge(_) | Page::Giant(_) => flags | PageTableEntryFlags::HUGE_PAGE,
+        };
+        entry.set_unused();
+        entry.set_address(frame.start_address(), flags);
+        flush_address_from_tlb(page.start_address());
 
-                let l4_frame: PageFrame = l4_entry
-                    .frame(PageTableLevel::Level4)
-                    .or_else(|_| self.create_table_frame(l4_entry, flags))?;
+        Ok(old_frame)
+    }
 
-                let l3_table: &mut PageTable = &mut *(self.frame_to_pointer(l4_frame));
-                let l3_entry = &mut l3_table[page.p1_index()];
+    /// Replace the flags of an existing mapping of the given [`Page`].
+    ///
+    /// The `HUGE_PAGE` flag is added automatically for [`Page::Huge`] and [`Page::Giant`] pages.
+    /// Returns the same errors as [`Mapper::unmap`].
+    ///
+    /// # Safety
+    /// Changing the flags can break memory safety, e.g. by making a page that is referenced
+    /// through a `&mut` read-only.
+    unsafe fn update_flags(
+        &mut self,
+        page: Page,
+        flags: PageTableEntryFlags,
+    ) -> Result<(), MappingError> {
+        let entry = leaf_entry(self, page, None)?;
+        entry
+            .frame(page.mapping_level())
+            .map_err(|_| MappingError::PageNotMapped)?;
+
+        let flags = match page {
+            Page::Normal(_) => flags,
+            Page::Huge(_) | Page::Giant(_) => flags | PageTableEntryFlags::HUGE_PAGE,
+        };
+        entry.set_flags(flags);
+        flush_address_from_tlb(page.start_address());
+
+        Ok(())
+    }
+}
 
-                if l3_entry.is_used() {
-                    return Err(MappingError::PageTableEntryAlreadyUsed);
-                }
+/// Walk down the [`PageTable`] hierarchy to the [`PageTableEntry`] that maps the given page.
+///
+/// Missing intermediate tables are created with the given flags if `create_with_flags` is
+/// set, otherwise `MappingError::PageNotMapped` is returned.
+///
+/// # Safety
+/// The returned reference is not tied to the lifetime of the mapper, the caller must ensure
+/// that the page tables are not modified through any other path while it is in use.
+unsafe fn leaf_entry<'a, M: Mapper + ?Sized>(
+    mapper: &M,
+    page: Page,
+    create_with_flags: Option<PageTableEntryFlags>,
+) -> Result<&'a mut PageTableEntry, MappingError> {
+    let address = page.start_address();
+    let mapping_level = page.mapping_level();
+    let mut table = mapper.level4_table();
+    let mut level = PageTableLevel::Level4;
+
+    while level != mapping_level {
+        let index = address.page_table_index(level);
+        let frame = match ((&*table)[index].frame(level), create_with_flags) {
+            (Ok(frame), _) if frame.is_huge() => return Err(MappingError::ParentEntryHugePage),
+            (Ok(frame), _) => frame,
+            (Err(_), Some(flags)) => create_table_frame(mapper, table, index, flags)?,
+            (Err(_), None) => return Err(MappingError::PageNotMapped),
+        };
+
+        table = mapper.child_table(table, index, frame);
+        level = level
+            .next_lower_level()
+            .expect("there is always a level below the one that is not the mapping level");
+    }
 
-                l3_entry.set_address(
-                    frame.start_address(),
-                    flags | PageTableEntryFlags::HUGE_PAGE,
-                );
+    // Make sure that the entry we are about to change maps a frame of the expected size.
+    let entry = &mut (&mut *table)[address.page_table_index(level)];
+    let huge = level != PageTableLevel::Level1;
+    if entry.is_used() && entry.has_huge_frame() != huge {
+        return Err(MappingError::InvalidPageFrameMapping);
+    }
+    Ok(entry)
+}
 
-                // Flush any previous mapping that this [`VirtualAddress`] might have had.
-                flush_address_from_tlb(page.start_address());
+/// Walk down the [`PageTable`] hierarchy of the given address and return every table that no
+/// longer has any entries to the [`FrameAllocator`], removing the entry pointing to it.
+///
+/// The level 4 table is never freed and level 3 tables only if the mapper owns them (see
+/// [`Mapper::owns_level3_table`]).
+unsafe fn free_empty_tables<M: Mapper + ?Sized>(mapper: &M, address: VirtualAddress) {
+    // The entries pointing to the level 3, 2 and 1 tables (if present) along with the table
+    // containing them.
+    let mut parents: [Option<(*mut PageTable, PageTableIndex, PageFrame)>; 3] = [None; 3];
+    let mut table = mapper.level4_table();
+    let mut level = PageTableLevel::Level4;
+
+    for parent in parents.iter_mut() {
+        let index = address.page_table_index(level);
+        let frame = match (&*table)[index].frame(level) {
+            Ok(frame) if !frame.is_huge() => frame,
+            _ => break,
+        };
+        *parent = Some((table, index, frame));
+
+        table = mapper.child_table(table, index, frame);
+        level = match level.next_lower_level() {
+            Some(level) => level,
+            None => break,
+        };
+    }
 
-                Ok(())
-            }
-            // Other combinations are not invaild
-            _ => Err(MappingError::InvalidPageFrameMapping),
+    for (parent, index, frame) in parents.into_iter().rev().flatten() {
+        let child = mapper.child_table(parent, index, frame);
+        if !(&*child).is_empty() {
+            break;
         }
+        if parent == mapper.level4_table() && !mapper.owns_level3_table(index) {
+            break;
+        }
+
+        (&mut *parent)[index].set_unused();
+        // The table might have been accessible through a virtual address that depends on the
+        // entry we just removed.
+        flush_address_from_tlb(VirtualAddress::from_ptr(child));
+        mapper.frame_allocator().lock().deallocate_frame(frame);
     }
+}
 
-    /// Create a new [`PageTable`] frame and map it to the given [`PageTableEntry`].
-    ///
-    /// Makes use of the [`FrameAllocator`] set in this class to allocate a new [`PageFrame`].
-    fn create_table_frame(
-        &mut self,
-        entry: &mut PageTableEntry,
-        flags: PageTableEntryFlags,
-    ) -> Result<PageFrame, MappingError> {
-        let frame = self
-            .frame_allocator
-            .allocate_normal_frame()
-            .ok_or_else(|| MappingError::FrameAllocationFailed)?;
-
-        let flags = flags
-            & (PageTableEntryFlags::PRESENT
-                | PageTableEntryFlags::WRITABLE
-                | PageTableEntryFlags::USER_ACCESSIBLE);
-
-        // At this point we have created a new [`PageTable`] which is represented by `frame`. We
-        // now need to make sure that this page table is in a usable state. This region of memory
-        // can have some residual data that we are not sure of, so we need to zero it out.
-        let table: &mut PageTable = unsafe { &mut *(self.frame_to_pointer(frame)) };
-        table.clear_all_entries();
+/// Create a new [`PageTable`] frame and map it to the entry at `index` of `table`.
+///
+/// Makes use of the [`FrameAllocator`] of the mapper to allocate a new [`PageFrame`].
+unsafe fn create_table_frame<M: Mapper + ?Sized>(
+    mapper: &M,
+    table: *mut PageTable,
+    index: PageTableIndex,
+    flags: PageTableEntryFlags,
+) -> Result<PageFrame, MappingError> {
+    let frame = mapper
+        .frame_allocator()
+        .lock()
+        .allocate_normal_frame()
+        .ok_or(MappingError::FrameAllocationFailed)?;
+
+    let flags = flags
+        & (PageTableEntryFlags::PRESENT
+            | PageTableEntryFlags::WRITABLE
+            | PageTableEntryFlags::USER_ACCESSIBLE);
+    (&mut *table)[index].set_address(frame.start_address(), flags);
+
+    // At this point we have created a new [`PageTable`] which is represented by `frame`. We
+    // now need to make sure that this page table is in a usable state. This region of memory
+    // can have some residual data that we are not sure of, so we need to zero it out.
+    let child = mapper.child_table(table, index, frame);
+    flush_address_from_tlb(VirtualAddress::from_ptr(child));
+    (&mut *child).clear_all_entries();
+
+    Ok(frame)
+}
 
-        entry.set_address(frame.start_address(), flags);
+/// A Mapper implementation that requires that the complete physically memory is mapped at some
+/// offset in the virtual address space.
+pub struct OffsetMemoryMapper {
+    physical_memory_offset: VirtualAddress,
+    l4_table_address: PageFrame,
+    frame_allocator: &'static Locked<FrameAllocator>,
+}
 
-        Ok(frame)
+impl OffsetMemoryMapper {
+    /// Creates a new `OffsetPageTable` that uses the given offset for converting virtual
+    /// to physical addresses.
+    ///
+    /// The complete physical memory must be mapped in the virtual address space starting at
+    /// address `phys_offset`. This means that for example physical address `0x5000` can be
+    /// accessed through virtual address `phys_offset + 0x5000`. This mapping is required because
+    /// the mapper needs to access page tables, which are not mapped into the virtual address
+    /// space by default.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that the passed `phys_offset`
+    /// is correct.
+    pub unsafe fn new(
+        physical_memory_offset: VirtualAddress,
+        frame_allocator: &'static Locked<FrameAllocator>,
+    ) -> Self {
+        let (level4_table_physical_address, _) = read_control_register_3();
+        OffsetMemoryMapper {
+            physical_memory_offset,
+            l4_table_address: level4_table_physical_address,
+            frame_allocator,
+        }
     }
 
     /// Convert a given [`PageFrame`] to a pointer to a [`PageTable`].
@@ -756,11 +941,302 @@ impl OffsetMemoryMapper {
     }
 }
 
-#[derive(Debug)]
+impl Mapper for OffsetMemoryMapper {
+    fn level4_table(&self) -> *mut PageTable {
+        unsafe { self.frame_to_pointer(self.l4_table_address) }
+    }
+
+    unsafe fn child_table(
+        &self,
+        _table: *mut PageTable,
+        _index: PageTableIndex,
+        frame: PageFrame,
+    ) -> *mut PageTable {
+        self.frame_to_pointer(frame)
+    }
+
+    fn frame_allocator(&self) -> &'static Locked<FrameAllocator> {
+        self.frame_allocator
+    }
+}
+
+/// A Mapper implementation that relies on one entry of the level 4 table pointing to the level 4
+/// table itself.
+///
+/// When the CPU follows this recursive entry it ends up one level higher in the hierarchy than it
+/// expects, so the "page" it finally arrives at is a page table. Following the recursive entry
+/// `n` times in a row gives access to the tables of level `n`. For a recursive index `r`:
+///
+/// ```text
+/// | Table of  | p4 index | p3 index | p2 index | p1 index |
+/// |-----------|----------|----------|----------|----------|
+/// | Level 4   | r        | r        | r        | r        |
+/// | Level 3   | r        | r        | r        | p4       |
+/// | Level 2   | r        | r        | p4       | p3       |
+/// | Level 1   | r        | p4       | p3       | p2       |
+/// ```
+///
+/// This makes the mapper independent of the physical memory being mapped anywhere, at the cost of
+/// one level 4 entry (512GiB) of virtual address space.
+pub struct RecursiveMemoryMapper {
+    recursive_index: PageTableIndex,
+    frame_allocator: &'static Locked<FrameAllocator>,
+}
+
+impl RecursiveMemoryMapper {
+    /// Creates a new `RecursiveMemoryMapper` from the virtual address of the active level 4 table
+    /// accessed through the recursive entry.
+    ///
+    /// # Panics
+    /// If the address does not use the same index at all four levels, or if the entry at that
+    /// index does not point to the active level 4 table.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that the given address is the
+    /// recursively mapped address of the level 4 table, otherwise we read arbitrary memory.
+    pub unsafe fn new(
+        level4_table_address: VirtualAddress,
+        frame_allocator: &'static Locked<FrameAllocator>,
+    ) -> Self {
+        let recursive_index = level4_table_address.p4_index();
+        assert!(
+            level4_table_address.p3_index() == recursive_index
+                && level4_table_address.p2_index() == recursive_index
+                && level4_table_address.p1_index() == recursive_index,
+            "{:?} is not a recursive page table address",
+            level4_table_address
+        );
+
+        let level4_table: &PageTable = &*(level4_table_address.as_ptr());
+        let (level4_table_frame, _) = read_control_register_3();
+        assert_eq!(
+            level4_table[recursive_index].frame(PageTableLevel::Level4),
+            Ok(level4_table_frame),
+            "level 4 entry {:?} is not recursive",
+            recursive_index
+        );
+
+        RecursiveMemoryMapper {
+            recursive_index,
+            frame_allocator,
+        }
+    }
+}
+
+impl Mapper for RecursiveMemoryMapper {
+    fn level4_table(&self) -> *mut PageTable {
+        let index = self.recursive_index;
+        VirtualAddress::from_page_table_indices(index, index, index, index).as_mut_ptr()
+    }
+
+    /// Going down a level means shifting the indices of the table's address one level up and
+    /// putting the index of the entry at the level 1 position. The offset bits of a table address
+    /// are always zero.
+    unsafe fn child_table(
+        &self,
+        table: *mut PageTable,
+        index: PageTableIndex,
+        _frame: PageFrame,
+    ) -> *mut PageTable {
+        let table_address = table as u64;
+        let child_address =
+            (table_address << PAGE_TABLE_INDEX_BITS) | (u64::from(index) << Size4KiB::BITS);
+        VirtualAddress::new_truncate(child_address).as_mut_ptr()
+    }
+
+    fn frame_allocator(&self) -> &'static Locked<FrameAllocator> {
+        self.frame_allocator
+    }
+}
+
+/// Return every mapping in the [`PageTable`] hierarchy of the mapper as a list of
+/// [`MappedRegion`]s.
+///
+/// Adjacent pages are merged into a single region as long as they have the same size and flags
+/// and are backed by physically contiguous frames. The regions are returned in ascending order of
+/// their virtual address.
+pub fn mapped_regions<M: Mapper>(mapper: &M) -> impl Iterator<Item = MappedRegion> + '_ {
+    let mut pages = mapped_pages(mapper).peekable();
+
+    core::iter::from_fn(move || {
+        let mut region = pages.next()?;
+        while let Some(next) = pages.next_if(|next| region.is_followed_by(next)) {
+            region.page_count += next.page_count;
+        }
+        Some(region)
+    })
+}
+
+/// Walk the [`PageTable`] hierarchy and return every mapped page as a [`MappedRegion`] of a single
+/// page.
+fn mapped_pages<M: Mapper>(mapper: &M) -> impl Iterator<Item = MappedRegion> + '_ {
+    gen_iter!(move {
+        let l4_table_pointer = mapper.level4_table();
+        let l4_table: &PageTable = unsafe { &*l4_table_pointer };
+        for (p4_index, l4_entry) in l4_table.iter().enumerate() {
+            let l3_frame = match l4_entry.frame(PageTableLevel::Level4) {
+                Ok(frame) => frame,
+                Err(_) => continue,
+            };
+
+            let l3_table_pointer =
+                unsafe { mapper.child_table(l4_table_pointer, table_index(p4_index), l3_frame) };
+            let l3_table: &PageTable = unsafe { &*l3_table_pointer };
+            for (p3_index, l3_entry) in l3_table.iter().enumerate() {
+                let l2_frame = match l3_entry.frame(PageTableLevel::Level3) {
+                    Ok(frame) => frame,
+                    Err(_) => continue,
+                };
+                if l2_frame.is_huge() {
+                    let address = page_address(p4_index, p3_index, 0, 0);
+                    yield MappedRegion::single(address, l2_frame, l3_entry.flags());
+                    continue;
+                }
+
+                let l2_table_pointer =
+                    unsafe { mapper.child_table(l3_table_pointer, table_index(p3_index), l2_frame) };
+                let l2_table: &PageTable = unsafe { &*l2_table_pointer };
+                for (p2_index, l2_entry) in l2_table.iter().enumerate() {
+                    let l1_frame = match l2_entry.frame(PageTableLevel::Level2) {
+                        Ok(frame) => frame,
+                        Err(_) => continue,
+                    };
+                    if l1_frame.is_huge() {
+                        let address = page_address(p4_index, p3_index, p2_index, 0);
+                        yield MappedRegion::single(address, l1_frame, l2_entry.flags());
+                        continue;
+                    }
+
+                    let l1_table_pointer = unsafe {
+                        mapper.child_table(l2_table_pointer, table_index(p2_index), l1_frame)
+                    };
+                    let l1_table: &PageTable = unsafe { &*l1_table_pointer };
+                    for (p1_index, l1_entry) in l1_table.iter().enumerate() {
+                        if let Ok(frame) = l1_entry.frame(PageTableLevel::Level1) {
+                            let address = page_address(p4_index, p3_index, p2_index, p1_index);
+                            yield MappedRegion::single(address, frame, l1_entry.flags());
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MappingError {
     InvalidPageFrameMapping,
     PageTableEntryAlreadyUsed,
     FrameAllocationFailed,
+    PageNotMapped,
+    ParentEntryHugePage,
+}
+
+/// A range of virtual memory that is mapped to a physically contiguous range of memory using
+/// pages of the same size and the same flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MappedRegion {
+    /// The first page of the region.
+    pub start: Page,
+    /// The frame that the first page is mapped to. Its size is the page size of the region.
+    pub frame: PageFrame,
+    /// Number of pages in the region.
+    pub page_count: u64,
+    /// Flags of the page table entries mapping the region. The `ACCESSED` and `DIRTY` flags are
+    /// left out as they differ from page to page.
+    pub flags: PageTableEntryFlags,
+}
+
+impl MappedRegion {
+    fn single(address: VirtualAddress, frame: PageFrame, flags: PageTableEntryFlags) -> Self {
+        let start = match frame {
+            PageFrame::Normal(_) => Page::Normal(PageInner::containing_address(address)),
+            PageFrame::Huge(_) => Page::Huge(PageInner::containing_address(address)),
+            PageFrame::Giant(_) => Page::Giant(PageInner::containing_address(address)),
+        };
+        MappedRegion {
+            start,
+            frame,
+            page_count: 1,
+            flags: flags - (PageTableEntryFlags::ACCESSED | PageTableEntryFlags::DIRTY),
+        }
+    }
+
+    /// Size of the region in bytes.
+    pub fn size(&self) -> u64 {
+        self.frame.size() * self.page_count
+    }
+
+    /// Iterate over the pages of the region.
+    pub fn pages(&self) -> impl Iterator<Item = Page> {
+        let start = self.start;
+        let page_size = self.frame.size();
+        (0..self.page_count).map(move |index| {
+            let address = start.start_address() + index * page_size;
+            match start {
+                Page::Normal(_) => Page::Normal(PageInner::containing_address(address)),
+                Page::Huge(_) => Page::Huge(PageInner::containing_address(address)),
+                Page::Giant(_) => Page::Giant(PageInner::containing_address(address)),
+            }
+        })
+    }
+
+    /// Tells if the given region starts right where this one ends, both virtually and physically,
+    /// and can be merged with it.
+    fn is_followed_by(&self, next: &MappedRegion) -> bool {
+        let virtual_end = self
+            .start
+            .start_address()
+            .as_u64()
+            .wrapping_add(self.size());
+        let physical_end = self.frame.start_address().as_u64() + self.size();
+
+        self.frame.size() == next.frame.size()
+            && self.flags == next.flags
+            && virtual_end == next.start.start_address().as_u64()
+            && physical_end == next.frame.start_address().as_u64()
+    }
+}
+
+impl fmt::Display for MappedRegion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let page_size = match self.frame {
+            PageFrame::Normal(_) => "4KiB",
+            PageFrame::Huge(_) => "2MiB",
+            PageFrame::Giant(_) => "1GiB",
+        };
+        let start = self.start.start_address().as_u64();
+        let physical_start = self.frame.start_address().as_u64();
+        write!(
+            f,
+            "{:#018x}-{:#018x} -> {:#014x} {} x {:<6} {:?}",
+            start,
+            start.wrapping_add(self.size()),
+            physical_start,
+            page_size,
+            self.page_count,
+            self.flags
+        )
+    }
+}
+
+fn page_address(
+    p4_index: usize,
+    p3_index: usize,
+    p2_index: usize,
+    p1_index: usize,
+) -> VirtualAddress {
+    VirtualAddress::from_page_table_indices(
+        table_index(p4_index),
+        table_index(p3_index),
+        table_index(p2_index),
+        table_index(p1_index),
+    )
+}
+
+fn table_index(index: usize) -> PageTableIndex {
+    PageTableIndex::new_truncate(index as u16)
 }
 
 /// Invalidate the TLB completely by reloading the CR3 register.
@@ -776,46 +1252,6 @@ pub fn flush_address_from_tlb(address: VirtualAddress) {
     }
 }
 
-pub struct FrameAllocator {
-    memory_map: &'static MemoryMap,
-    next: usize,
-}
-
-/// A FrameAllocator that returns usable frames from the bootloader's memory map.
-impl FrameAllocator {
-    /// Create a FrameAllocator from the passed memory map.
-    ///
-    /// This function is unsafe because the caller must guarantee that the passed
-    /// memory map is valid. The main requirement is that all frames that are marked
-    /// as `USABLE` in it are really unused.
-    pub unsafe fn new(memory_map: &'static MemoryMap) -> Self {
-        FrameAllocator {
-            memory_map,
-            next: 0,
-        }
-    }
-
-    /// Returns an iterator over the usable frames specified in the memory map.
-    fn usable_frames(&self) -> impl Iterator<Item = PageFrame> {
-        let regions = self.memory_map.iter();
-        let usable_regions = regions.filter(|r| r.region_type == MemoryRegionType::Usable);
-        let addr_ranges = usable_regions.map(|r| r.range.start_addr()..r.range.end_addr());
-        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(Size4KiB::SIZE as usize));
-        frame_addresses.map(|addr| {
-            PageFrame::Normal(PageFrameInner::containing_address(PhysicalAddress::new(
-                addr,
-            )))
-        })
-    }
-
-    /// Retrun next available [`PageFrame`] of 4KiB size
-    pub fn allocate_normal_frame(&mut self) -> Option<PageFrame> {
-        let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
-        frame
-    }
-}
-
 /// A range of pages with inclusive upper bound.
 #[derive(Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
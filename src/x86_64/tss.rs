@@ -3,7 +3,7 @@ use core::arch::asm;
 use super::{address::VirtualAddress, segmentation::SegmentSelector};
 
 const NUMBER_OF_PRIVILEGE_LEVELS: usize = 3;
-const NUMBER_OF_INTERRUPT_STACKS: usize = 7;
+pub const NUMBER_OF_INTERRUPT_STACKS: usize = 7;
 
 /// In 64-bit mode the TSS holds information that is not directly related to the task-switch
 /// mechanism, but is used for finding kernel level stack if interrupts arrive while in kernel
@@ -38,7 +38,7 @@ pub struct TaskStateSegment {
 }
 
 impl TaskStateSegment {
-    pub fn new() -> TaskStateSegment {
+    pub const fn new() -> TaskStateSegment {
         TaskStateSegment {
             privilege_stack_table: [VirtualAddress::zero(); NUMBER_OF_PRIVILEGE_LEVELS],
             interrupt_stack_table: [VirtualAddress::zero(); NUMBER_OF_INTERRUPT_STACKS],
@@ -0,0 +1,97 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rosy::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rosy::{
+    acpi::{self, madt::InterruptSourceOverride, AcpiError, AcpiTables, AddressSpace, Signature},
+    x86_64::address::PhysicalAddress,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rosy::init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rosy::test_panic_handler(info)
+}
+
+fn tables() -> &'static AcpiTables {
+    acpi::tables().expect("rosy::init should have found QEMU's ACPI tables")
+}
+
+#[test_case]
+fn test_all_tables_have_valid_checksums() {
+    assert!(tables().root().has_valid_checksum());
+    assert!(tables().iter().count() > 0);
+    for table in tables().iter() {
+        assert!(table.has_valid_checksum(), "{:?}", table);
+    }
+}
+
+#[test_case]
+fn test_madt_describes_the_apics_of_qemu() {
+    let madt = tables().madt().unwrap();
+
+    assert_eq!(madt.local_apic_address(), PhysicalAddress::new(0xfee0_0000));
+    assert!(madt.has_legacy_pics());
+    assert!(madt.local_apics().any(|local_apic| local_apic.is_enabled()));
+
+    let io_apic = madt.io_apics().next().unwrap();
+    assert_eq!(io_apic.address, PhysicalAddress::new(0xfec0_0000));
+    assert_eq!(io_apic.global_system_interrupt_base, 0);
+
+    // The timer of the PIT is connected to the second pin of the I/O APIC.
+    let timer_override = madt
+        .interrupt_source_overrides()
+        .find(|source_override| source_override.source == 0);
+    assert!(matches!(
+        timer_override,
+        Some(InterruptSourceOverride {
+            bus: 0,
+            global_system_interrupt: 2,
+            ..
+        })
+    ));
+}
+
+#[test_case]
+fn test_fadt_points_to_a_valid_dsdt() {
+    let fadt = tables().fadt().unwrap();
+
+    // The PIIX4 power management function uses ISA interrupt 9.
+    assert_eq!(fadt.sci_interrupt, 9);
+    assert_ne!(fadt.pm_timer_block, 0);
+    assert_eq!(fadt.pm_timer_length, 4);
+
+    let dsdt = unsafe { tables().table_at(fadt.dsdt) }.unwrap();
+    assert_eq!(dsdt.signature(), Signature(*b"DSDT"));
+}
+
+#[test_case]
+fn test_hpet_registers_are_in_system_memory() {
+    let hpet = tables().hpet().unwrap();
+
+    assert_eq!(hpet.base_address.address_space, AddressSpace::SystemMemory);
+    assert_eq!(hpet.base_address.address, 0xfed0_0000);
+    assert!(hpet.comparator_count >= 3);
+}
+
+#[test_case]
+fn test_missing_tables_are_reported() {
+    // QEMU's default machine (i440fx) has no PCI Express, so there is no MCFG.
+    assert_eq!(
+        tables().mcfg().map(|_| ()),
+        Err(AcpiError::TableNotFound(acpi::mcfg::SIGNATURE))
+    );
+    assert_eq!(acpi::init(), Err(AcpiError::AlreadyInitialized));
+}
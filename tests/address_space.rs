@@ -0,0 +1,146 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rosy::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rosy::{
+    address_space::{AddressSpace, AddressSpaceError, USER_SPACE_START},
+    allocation::heap_start,
+    memory::{memory_mapper, FRAME_ALLOCATOR},
+    x86_64::{
+        address::VirtualAddress,
+        instructions::{read_control_register_3, write_control_register_3},
+        paging::{Mapper, Page, PageInner, PageTableEntryFlags},
+    },
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rosy::init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rosy::test_panic_handler(info)
+}
+
+fn page(address: u64) -> Page {
+    Page::Normal(PageInner::containing_address(VirtualAddress::new(address)))
+}
+
+#[test_case]
+fn test_kernel_mappings_are_shared() {
+    let address_space = AddressSpace::new().unwrap();
+    let kernel_mapper = memory_mapper().lock();
+
+    let heap = VirtualAddress::new(heap_start() as u64);
+    let vga_buffer = VirtualAddress::new(0xb8000);
+    for address in [heap, vga_buffer] {
+        assert!(kernel_mapper.translate_address(address).is_some());
+        assert_eq!(
+            address_space.translate_address(address),
+            kernel_mapper.translate_address(address)
+        );
+    }
+}
+
+#[test_case]
+fn test_user_pages_are_only_mapped_in_their_address_space() {
+    let mut address_space = AddressSpace::new().unwrap();
+    let user_page = page(USER_SPACE_START);
+    address_space
+        .map_user_page(user_page, PageTableEntryFlags::WRITABLE)
+        .unwrap();
+
+    let address = user_page.start_address();
+    assert!(address_space.translate_address(address).is_some());
+    assert_eq!(memory_mapper().lock().translate_address(address), None);
+
+    let other_address_space = AddressSpace::new().unwrap();
+    assert_eq!(other_address_space.translate_address(address), None);
+}
+
+#[test_case]
+fn test_pages_outside_of_user_space_are_rejected() {
+    let mut address_space = AddressSpace::new().unwrap();
+    assert_eq!(
+        address_space.map_user_page(page(heap_start() as u64), PageTableEntryFlags::WRITABLE),
+        Err(AddressSpaceError::OutsideUserSpace)
+    );
+}
+
+#[test_case]
+fn test_activated_address_space_can_access_its_pages() {
+    let mut address_space = AddressSpace::new().unwrap();
+    let user_page = page(USER_SPACE_START + 0x1000);
+    address_space
+        .map_user_page(user_page, PageTableEntryFlags::WRITABLE)
+        .unwrap();
+
+    let (kernel_level4_frame, flags) = read_control_register_3();
+    let pointer: *mut u64 = user_page.start_address().as_mut_ptr();
+    unsafe {
+        address_space.activate();
+        assert!(address_space.is_active());
+        // freshly mapped pages are zeroed
+        assert_eq!(pointer.read_volatile(), 0);
+        pointer.write_volatile(42);
+        assert_eq!(pointer.read_volatile(), 42);
+
+        write_control_register_3(kernel_level4_frame, flags);
+    }
+    assert!(!address_space.is_active());
+}
+
+#[test_case]
+fn test_dropping_an_address_space_frees_its_frames() {
+    let free_frames = FRAME_ALLOCATOR.lock().free_frames();
+
+    let mut address_space = AddressSpace::new().unwrap();
+    for index in 0..4 {
+        let user_page = page(USER_SPACE_START + index * 0x20_0000);
+        address_space
+            .map_user_page(user_page, PageTableEntryFlags::WRITABLE)
+            .unwrap();
+    }
+    assert!(FRAME_ALLOCATOR.lock().free_frames() < free_frames);
+
+    drop(address_space);
+    assert_eq!(FRAME_ALLOCATOR.lock().free_frames(), free_frames);
+}
+
+#[test_case]
+fn test_unmapping_kernel_pages_keeps_the_shared_tables() {
+    // Nothing else lives under this level 4 entry (index 232), so the kernel has to create the
+    // level 3 table, which the address space created afterwards shares.
+    let kernel_page = page(0x7400_0000_0000);
+    let frame = FRAME_ALLOCATOR.lock().allocate_normal_frame().unwrap();
+    let flags = PageTableEntryFlags::PRESENT | PageTableEntryFlags::WRITABLE;
+    unsafe { memory_mapper().lock().map_to(kernel_page, frame, flags) }.unwrap();
+    let address_space = AddressSpace::new().unwrap();
+
+    let unmapped_frame = unsafe { memory_mapper().lock().unmap(kernel_page) }.unwrap();
+    FRAME_ALLOCATOR.lock().deallocate_frame(unmapped_frame);
+    assert_eq!(
+        address_space.translate_address(kernel_page.start_address()),
+        None
+    );
+
+    // The level 3 table is still in use, so mapping below it shows up in the address space.
+    let frame = FRAME_ALLOCATOR.lock().allocate_normal_frame().unwrap();
+    unsafe { memory_mapper().lock().map_to(kernel_page, frame, flags) }.unwrap();
+    assert_eq!(
+        address_space.translate_address(kernel_page.start_address()),
+        Some(frame.start_address())
+    );
+
+    let unmapped_frame = unsafe { memory_mapper().lock().unmap(kernel_page) }.unwrap();
+    FRAME_ALLOCATOR.lock().deallocate_frame(unmapped_frame);
+}
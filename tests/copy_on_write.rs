@@ -0,0 +1,129 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rosy::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rosy::{
+    address_space::{AddressSpace, USER_SPACE_START},
+    memory::FRAME_ALLOCATOR,
+    x86_64::{
+        address::VirtualAddress,
+        instructions::{read_control_register_3, write_control_register_3},
+        paging::{Mapper, Page, PageInner, PageTableEntryFlags},
+    },
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rosy::init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rosy::test_panic_handler(info)
+}
+
+fn user_page() -> Page {
+    Page::Normal(PageInner::containing_address(VirtualAddress::new(
+        USER_SPACE_START,
+    )))
+}
+
+/// Run `f` with the given address space active and switch back to the kernel's afterwards.
+fn with_active<T>(address_space: &AddressSpace, f: impl FnOnce() -> T) -> T {
+    let (kernel_level4_frame, flags) = read_control_register_3();
+    unsafe { address_space.activate() };
+    let result = f();
+    unsafe { write_control_register_3(kernel_level4_frame, flags) };
+    result
+}
+
+#[test_case]
+fn test_clone_shares_frames_read_only() {
+    let mut original = AddressSpace::new().unwrap();
+    original
+        .map_user_page(user_page(), PageTableEntryFlags::WRITABLE)
+        .unwrap();
+    let clone = original.try_clone().unwrap();
+
+    let (frame, flags) = original.translate_page(user_page()).unwrap();
+    let (clone_frame, clone_flags) = clone.translate_page(user_page()).unwrap();
+    assert_eq!(frame, clone_frame);
+    assert_eq!(FRAME_ALLOCATOR.lock().reference_count(frame), 2);
+    for flags in [flags, clone_flags] {
+        assert!(!flags.contains(PageTableEntryFlags::WRITABLE));
+        assert!(flags.contains(PageTableEntryFlags::COPY_ON_WRITE));
+    }
+}
+
+#[test_case]
+fn test_write_copies_the_shared_frame() {
+    let mut original = AddressSpace::new().unwrap();
+    original
+        .map_user_page(user_page(), PageTableEntryFlags::WRITABLE)
+        .unwrap();
+    let pointer: *mut u64 = user_page().start_address().as_mut_ptr();
+    with_active(&original, || unsafe { pointer.write_volatile(1) });
+
+    let clone = original.try_clone().unwrap();
+    with_active(&clone, || unsafe {
+        assert_eq!(pointer.read_volatile(), 1);
+        pointer.write_volatile(2);
+        assert_eq!(pointer.read_volatile(), 2);
+    });
+
+    // the original still sees its own value
+    assert_eq!(
+        with_active(&original, || unsafe { pointer.read_volatile() }),
+        1
+    );
+
+    let (frame, _) = original.translate_page(user_page()).unwrap();
+    let (clone_frame, clone_flags) = clone.translate_page(user_page()).unwrap();
+    assert_ne!(frame, clone_frame);
+    assert!(clone_flags.contains(PageTableEntryFlags::WRITABLE));
+    assert_eq!(FRAME_ALLOCATOR.lock().reference_count(frame), 1);
+}
+
+#[test_case]
+fn test_last_reference_is_made_writable_without_copying() {
+    let mut original = AddressSpace::new().unwrap();
+    original
+        .map_user_page(user_page(), PageTableEntryFlags::WRITABLE)
+        .unwrap();
+    let clone = original.try_clone().unwrap();
+    let (frame, _) = original.translate_page(user_page()).unwrap();
+    drop(clone);
+
+    let pointer: *mut u64 = user_page().start_address().as_mut_ptr();
+    with_active(&original, || unsafe { pointer.write_volatile(3) });
+
+    let (frame_after_write, flags) = original.translate_page(user_page()).unwrap();
+    assert_eq!(frame, frame_after_write);
+    assert!(flags.contains(PageTableEntryFlags::WRITABLE));
+    assert!(!flags.contains(PageTableEntryFlags::COPY_ON_WRITE));
+}
+
+#[test_case]
+fn test_clones_free_all_frames_when_dropped() {
+    let free_frames = FRAME_ALLOCATOR.lock().free_frames();
+
+    let mut original = AddressSpace::new().unwrap();
+    original
+        .map_user_page(user_page(), PageTableEntryFlags::WRITABLE)
+        .unwrap();
+    let clone = original.try_clone().unwrap();
+    let pointer: *mut u64 = user_page().start_address().as_mut_ptr();
+    with_active(&clone, || unsafe { pointer.write_volatile(4) });
+
+    drop(original);
+    drop(clone);
+    assert_eq!(FRAME_ALLOCATOR.lock().free_frames(), free_frames);
+}
@@ -0,0 +1,86 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rosy::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rosy::{
+    memory::{memory_mapper, FRAME_ALLOCATOR},
+    virtual_memory::{RangeOwner, KERNEL_VIRTUAL_MEMORY},
+    vma::{self, Backing, VirtualMemoryArea},
+    x86_64::paging::{Mapper, PageSize, PageTableEntryFlags, Size4KiB},
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rosy::init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rosy::test_panic_handler(info)
+}
+
+fn lazy_area(pages: u64) -> VirtualMemoryArea {
+    vma::allocate_area(
+        pages * Size4KiB::SIZE,
+        PageTableEntryFlags::WRITABLE,
+        Backing::Lazy,
+    )
+    .unwrap()
+}
+
+#[test_case]
+fn test_lazy_area_is_mapped_on_first_access() {
+    let area = lazy_area(4);
+    assert_eq!(
+        KERNEL_VIRTUAL_MEMORY
+            .lock()
+            .find(area.start)
+            .map(|range| range.owner),
+        Some(RangeOwner::Area)
+    );
+
+    let second_page = area.start + Size4KiB::SIZE;
+    assert_eq!(memory_mapper().lock().translate_address(second_page), None);
+
+    let pointer: *mut u64 = second_page.as_mut_ptr();
+    unsafe {
+        // freshly faulted in pages are zeroed
+        assert_eq!(pointer.read_volatile(), 0);
+        pointer.write_volatile(42);
+        assert_eq!(pointer.read_volatile(), 42);
+    }
+
+    assert!(memory_mapper()
+        .lock()
+        .translate_address(second_page)
+        .is_some());
+    // only the page that was accessed got mapped
+    assert_eq!(memory_mapper().lock().translate_address(area.start), None);
+
+    unsafe { vma::release_area(area.start) };
+}
+
+#[test_case]
+fn test_releasing_an_area_frees_its_frames() {
+    let area = lazy_area(2);
+    let free_frames = FRAME_ALLOCATOR.lock().free_frames();
+
+    for page in 0..2 {
+        let pointer: *mut u8 = (area.start + page * Size4KiB::SIZE).as_mut_ptr();
+        unsafe { pointer.write_volatile(1) };
+    }
+    assert!(FRAME_ALLOCATOR.lock().free_frames() < free_frames);
+
+    assert_eq!(unsafe { vma::release_area(area.start) }, Some(area));
+    assert_eq!(FRAME_ALLOCATOR.lock().free_frames(), free_frames);
+    assert_eq!(memory_mapper().lock().translate_address(area.start), None);
+    assert_eq!(KERNEL_VIRTUAL_MEMORY.lock().find(area.start), None);
+}
@@ -0,0 +1,66 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use bootloader::{entry_point, BootInfo};
+use core::{arch::asm, panic::PanicInfo};
+use lazy_static::lazy_static;
+use rosy::{
+    exit_qemu, serial_error, serial_print, serial_println, serial_success,
+    x86_64::idt::{
+        DescriptorTable, ExceptionStackFrame, InterruptDescriptorTable, SelectorErrorCode,
+    },
+    QemuExitCode,
+};
+
+/// Selector for GDT entry 0x246, which lies way beyond the end of our GDT.
+const INVALID_SELECTOR: u16 = 0x246 << 3;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rosy::init(boot_info);
+    TEST_IDT.load();
+
+    serial_println!();
+    serial_println!("Running 1 test");
+    serial_print!("general_protection_fault::loading_an_invalid_selector_faults...\t");
+
+    unsafe { asm!("mov ds, {0:x}", in(reg) INVALID_SELECTOR, options(nostack)) };
+
+    serial_error!("[loaded an invalid selector]");
+    serial_println!();
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.set_general_protection_fault_handler(test_general_protection_fault_handler);
+        idt
+    };
+}
+
+extern "x86-interrupt" fn test_general_protection_fault_handler(
+    _stack_frame: ExceptionStackFrame,
+    error_code: SelectorErrorCode,
+) {
+    if error_code.descriptor_table() != DescriptorTable::Gdt
+        || error_code.index() != 0x246
+        || error_code.is_external()
+    {
+        panic!("unexpected error code: {:?}", error_code);
+    }
+
+    serial_success!("[ok]");
+    serial_println!();
+    serial_println!();
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rosy::test_panic_handler(info)
+}
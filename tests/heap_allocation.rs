@@ -6,10 +6,16 @@
 
 extern crate alloc;
 
-use alloc::{boxed::Box, rc::Rc, vec, vec::Vec};
+use alloc::{
+    alloc::{alloc, Layout},
+    boxed::Box,
+    rc::Rc,
+    vec,
+    vec::Vec,
+};
 use bootloader::{entry_point, BootInfo};
 use core::{mem, panic::PanicInfo};
-use rosy::allocation::HEAP_SIZE;
+use rosy::allocation::{heap_size, heap_stats, HEAP_MAX_SIZE, HEAP_SIZE};
 
 entry_point!(main);
 
@@ -71,3 +77,48 @@ fn test_allocator_frees_up_memory_even_if_there_is_a_long_lived_allocation() {
     }
     assert_eq!(*long_lived, 1);
 }
+
+#[test_case]
+fn test_small_and_large_allocations_can_be_interleaved() {
+    for i in 0..100 {
+        let small = Box::new(i);
+        let large = vec![i; 1000];
+        assert_eq!(*small, i);
+        assert_eq!(large.iter().sum::<usize>(), i * 1000);
+    }
+}
+
+#[test_case]
+fn test_heap_grows_when_allocations_exceed_initial_size() {
+    let large = vec![1u8; 2 * HEAP_SIZE];
+    assert!(heap_size() > 2 * HEAP_SIZE);
+    assert_eq!(
+        large.iter().map(|&byte| byte as usize).sum::<usize>(),
+        2 * HEAP_SIZE
+    );
+}
+
+#[test_case]
+fn test_allocation_beyond_max_heap_size_fails() {
+    let heap_size_before = heap_size();
+    let layout = Layout::from_size_align(HEAP_MAX_SIZE, 8).unwrap();
+    assert!(unsafe { alloc(layout) }.is_null());
+    assert_eq!(heap_size(), heap_size_before);
+}
+
+#[test_case]
+fn test_heap_stats_track_live_allocations() {
+    let before = heap_stats();
+    let boxed = Box::new([0u64; 4]);
+    let during = heap_stats();
+    assert_eq!(during.live_allocations, before.live_allocations + 1);
+    assert!(during.bytes_allocated >= before.bytes_allocated + mem::size_of_val(&*boxed));
+    assert!(during.peak_bytes_allocated >= during.bytes_allocated);
+
+    mem::drop(boxed);
+    let after = heap_stats();
+    assert_eq!(after.live_allocations, before.live_allocations);
+    // The bump allocator only gets its memory back once every allocation is freed.
+    #[cfg(not(feature = "alloc-bump"))]
+    assert_eq!(after.bytes_allocated, before.bytes_allocated);
+}
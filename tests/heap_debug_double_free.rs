@@ -0,0 +1,41 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rosy::{exit_qemu, serial_error, serial_print, serial_println, serial_success, QemuExitCode};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rosy::init(boot_info);
+
+    serial_println!();
+    serial_println!("Running 1 test");
+    double_free_panics();
+    serial_error!("[test did not panic]");
+    serial_println!();
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+fn double_free_panics() {
+    serial_print!("heap_debug_double_free::double_free_panics...\t");
+    let value = Box::into_raw(Box::new(41));
+    unsafe {
+        drop(Box::from_raw(value));
+        drop(Box::from_raw(value));
+    }
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    serial_success!("[ok]");
+    serial_println!();
+    serial_println!();
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
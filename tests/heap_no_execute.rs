@@ -0,0 +1,69 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use lazy_static::lazy_static;
+use rosy::{
+    exit_qemu, serial_error, serial_print, serial_println, serial_success,
+    x86_64::idt::{ExceptionStackFrame, InterruptDescriptorTable, PageFaultErrorCode},
+    QemuExitCode,
+};
+
+/// Machine code for `ret`
+const RET: u8 = 0xc3;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rosy::init(boot_info);
+    TEST_IDT.load();
+
+    serial_println!();
+    serial_println!("Running 1 test");
+    serial_print!("heap_no_execute::jumping_into_the_heap_faults...\t");
+
+    let code = Box::into_raw(Box::new([RET; 16]));
+    let function: extern "C" fn() = unsafe { core::mem::transmute(code as *const u8) };
+    function();
+
+    serial_error!("[executed code on the heap]");
+    serial_println!();
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.set_page_fault_handler(test_page_fault_handler);
+        idt
+    };
+}
+
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: ExceptionStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    if !error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+        panic!(
+            "page fault that was not caused by an instruction fetch: {:?}",
+            error_code
+        );
+    }
+
+    serial_success!("[ok]");
+    serial_println!();
+    serial_println!();
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rosy::test_panic_handler(info)
+}
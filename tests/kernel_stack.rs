@@ -0,0 +1,105 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rosy::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rosy::{
+    kernel_stack::{self, StackAllocationError},
+    memory::{memory_mapper, FRAME_ALLOCATOR},
+    virtual_memory::{RangeOwner, KERNEL_VIRTUAL_MEMORY},
+    x86_64::{
+        address::VirtualAddress,
+        paging::{Mapper, PageSize, Size4KiB},
+    },
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rosy::init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rosy::test_panic_handler(info)
+}
+
+#[test_case]
+fn test_stack_is_mapped_and_guard_page_is_not() {
+    let stack = kernel_stack::allocate_stack(3).unwrap();
+    assert_eq!(
+        stack.top().as_u64() - stack.bottom().as_u64(),
+        3 * Size4KiB::SIZE
+    );
+
+    let mapper = memory_mapper().lock();
+    assert!(mapper.translate_address(stack.bottom()).is_some());
+    let last_byte = VirtualAddress::new(stack.top().as_u64() - 1);
+    assert!(mapper.translate_address(last_byte).is_some());
+    assert_eq!(mapper.translate_address(stack.guard_page()), None);
+    drop(mapper);
+
+    // both ends of the stack are usable
+    unsafe {
+        let highest: *mut u64 = VirtualAddress::new(stack.top().as_u64() - 8).as_mut_ptr();
+        let lowest: *mut u64 = stack.bottom().as_mut_ptr();
+        highest.write_volatile(42);
+        lowest.write_volatile(43);
+        assert_eq!(highest.read_volatile(), 42);
+        assert_eq!(lowest.read_volatile(), 43);
+
+        kernel_stack::free_stack(stack);
+    }
+}
+
+#[test_case]
+fn test_freeing_a_stack_unmaps_it_and_frees_its_frames() {
+    let free_frames = FRAME_ALLOCATOR.lock().free_frames();
+    let stack = kernel_stack::allocate_stack(2).unwrap();
+    let bottom = stack.bottom();
+    assert!(FRAME_ALLOCATOR.lock().free_frames() < free_frames);
+
+    unsafe { kernel_stack::free_stack(stack) };
+    assert_eq!(memory_mapper().lock().translate_address(bottom), None);
+    assert_eq!(FRAME_ALLOCATOR.lock().free_frames(), free_frames);
+}
+
+#[test_case]
+fn test_stacks_do_not_share_guard_pages() {
+    let first = kernel_stack::allocate_stack(1).unwrap();
+    let second = kernel_stack::allocate_stack(1).unwrap();
+    assert!(second.guard_page() >= first.top());
+
+    assert_eq!(
+        kernel_stack::allocate_stack(0),
+        Err(StackAllocationError::EmptyStack)
+    );
+
+    unsafe {
+        kernel_stack::free_stack(first);
+        kernel_stack::free_stack(second);
+    }
+}
+
+#[test_case]
+fn test_stack_ranges_are_tracked_and_reused_once_freed() {
+    let stack = kernel_stack::allocate_stack(2).unwrap();
+    let guard_page = stack.guard_page();
+    let range = *KERNEL_VIRTUAL_MEMORY.lock().find(guard_page).unwrap();
+    assert_eq!(range.owner, RangeOwner::KernelStack);
+    assert_eq!(range.start, guard_page);
+    assert_eq!(range.end(), stack.top().as_u64());
+
+    unsafe { kernel_stack::free_stack(stack) };
+    assert_eq!(KERNEL_VIRTUAL_MEMORY.lock().find(guard_page), None);
+
+    let stack = kernel_stack::allocate_stack(2).unwrap();
+    assert_eq!(stack.guard_page(), guard_page);
+    unsafe { kernel_stack::free_stack(stack) };
+}
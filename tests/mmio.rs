@@ -0,0 +1,108 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rosy::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rosy::{
+    memory::memory_mapper,
+    mmio::{self, MmioError, MmioMapper},
+    vga::VGA_SEGMENT_START,
+    x86_64::{
+        address::{PhysicalAddress, VirtualAddress},
+        paging::{Mapper, PageTableEntryFlags},
+    },
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rosy::init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rosy::test_panic_handler(info)
+}
+
+/// The VGA text buffer is device memory that the bootloader identity maps, which makes it a good
+/// target to compare the MMIO mapping against.
+const VGA_BUFFER_SIZE: usize = 80 * 25 * 2;
+
+#[test_case]
+fn test_mmio_accesses_the_device_memory() {
+    let physical = PhysicalAddress::new(VGA_SEGMENT_START as u64);
+    let mut vga = unsafe { mmio::map_mmio::<u16>(physical, VGA_BUFFER_SIZE) }.unwrap();
+    assert_eq!(
+        memory_mapper()
+            .lock()
+            .translate_address(vga.virtual_address()),
+        Some(physical)
+    );
+
+    let last_cell = VGA_BUFFER_SIZE - 2;
+    let identity_mapped: *mut u16 = (VGA_SEGMENT_START + last_cell) as *mut u16;
+    vga.write_at::<u16>(last_cell, 0x0f41);
+    assert_eq!(unsafe { identity_mapped.read_volatile() }, 0x0f41);
+    assert_eq!(vga.read_at::<u16>(last_cell), 0x0f41);
+}
+
+#[test_case]
+fn test_mmio_is_mapped_uncached_and_unmapped_on_drop() {
+    // Not page aligned on purpose
+    let physical = PhysicalAddress::new(VGA_SEGMENT_START as u64 + 0x10);
+    let vga = unsafe { mmio::map_mmio::<u32>(physical, 16) }.unwrap();
+    let address = vga.virtual_address();
+    assert_eq!(address.as_u64() % 0x1000, 0x10);
+
+    let page = VirtualAddress::new(address.as_u64() & !0xfff);
+    let flags = rosy::x86_64::paging::mapped_regions(&*memory_mapper().lock())
+        .find(|region| {
+            let start = region.start.start_address().as_u64();
+            (start..start + region.size()).contains(&page.as_u64())
+        })
+        .map(|region| region.flags)
+        .unwrap();
+    assert!(flags.contains(PageTableEntryFlags::NO_CACHE | PageTableEntryFlags::WRITE_THROUGH));
+
+    drop(vga);
+    assert_eq!(memory_mapper().lock().translate_address(address), None);
+}
+
+#[test_case]
+fn test_mmio_rejects_ranges_smaller_than_the_type() {
+    let physical = PhysicalAddress::new(VGA_SEGMENT_START as u64);
+    assert_eq!(
+        unsafe { mmio::map_mmio::<u64>(physical, 4) }.err(),
+        Some(MmioError::InvalidLength)
+    );
+}
+
+#[test_case]
+fn test_mmio_rejects_ranges_that_overflow_the_address_space() {
+    let physical = PhysicalAddress::new(VGA_SEGMENT_START as u64 + 0x10);
+    assert_eq!(
+        unsafe { mmio::map_mmio::<u16>(physical, usize::MAX) }.err(),
+        Some(MmioError::InvalidLength)
+    );
+}
+
+#[test_case]
+fn test_mmio_can_be_mapped_with_a_mapper_that_is_already_locked() {
+    let physical = PhysicalAddress::new(VGA_SEGMENT_START as u64);
+    let mut mapper = memory_mapper().lock();
+    let vga = unsafe { mapper.map_mmio::<u16>(physical, VGA_BUFFER_SIZE) }.unwrap();
+    assert_eq!(
+        mapper.translate_address(vga.virtual_address()),
+        Some(physical)
+    );
+
+    // Dropping the accessor needs the kernel's memory mapper.
+    drop(mapper);
+    drop(vga);
+}
@@ -8,13 +8,15 @@ use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
 use lazy_static::lazy_static;
 use rosy::{
+    memory::FRAME_ALLOCATOR,
     screen_printing::WRITER,
     vga::{Color, ColorCode, ScreenChar, ScreenLocation},
     x86_64::{
         address::{PhysicalAddress, VirtualAddress},
         interrupts,
         paging::{
-            OffsetMemoryMapper, Page, PageFrame, PageFrameInner, PageInner, PageTableEntryFlags,
+            self, Mapper, MappingError, OffsetMemoryMapper, Page, PageFrame, PageFrameInner,
+            PageInner, PageTableEntryFlags, RecursiveMemoryMapper,
         },
     },
 };
@@ -36,6 +38,7 @@ fn black_on_white_string() -> [ScreenChar; BLACK_ON_WHITLE_STRING_SIZE] {
 struct TestFixture {
     physical_memory_offset: VirtualAddress,
     memory_mapper: OffsetMemoryMapper,
+    recursive_memory_mapper: RecursiveMemoryMapper,
 }
 
 struct FixtureWrapper {
@@ -58,18 +61,21 @@ lazy_static! {
 entry_point!(main);
 
 fn main(boot_info: &'static BootInfo) -> ! {
-    use rosy::x86_64::paging::FrameAllocator;
     rosy::init(boot_info);
 
     let physical_memory_offset = VirtualAddress::new(boot_info.physical_memory_offset);
-    let frame_allocator = unsafe { FrameAllocator::new(&boot_info.memory_map) };
-    let memory_mapper = unsafe { OffsetMemoryMapper::new(physical_memory_offset, frame_allocator) };
+    let memory_mapper =
+        unsafe { OffsetMemoryMapper::new(physical_memory_offset, &FRAME_ALLOCATOR) };
+    let level4_table_address = VirtualAddress::new(boot_info.recursive_page_table_addr);
+    let recursive_memory_mapper =
+        unsafe { RecursiveMemoryMapper::new(level4_table_address, &FRAME_ALLOCATOR) };
 
     {
         let mut test_fixture = TEST_FIXTURE.lock();
         test_fixture.fixture = Some(TestFixture {
             physical_memory_offset,
             memory_mapper,
+            recursive_memory_mapper,
         });
     }
 
@@ -182,3 +188,173 @@ fn test_verify_page_mapping_works_when_mapper_needs_to_create_new_pages() {
         }
     })
 }
+
+#[test_case]
+fn test_unmap_removes_mapping_and_frees_intermediate_page_tables() {
+    let mut test_fixture = TEST_FIXTURE.lock();
+    let memory_mapper = &mut test_fixture.fixture.as_mut().unwrap().memory_mapper;
+
+    // Nothing else lives under this level 4 entry (index 238) so the mapping needs new level 3, 2
+    // and 1 page tables. The level 2 and 1 tables are freed once we unmap it, the level 3 table
+    // stays as it might be shared with other address spaces.
+    let page = Page::Normal(PageInner::containing_address(VirtualAddress::new(
+        0x7777_7777_7000,
+    )));
+    let free_frames = FRAME_ALLOCATOR.lock().free_frames();
+    let frame = FRAME_ALLOCATOR.lock().allocate_normal_frame().unwrap();
+    let flags = PageTableEntryFlags::PRESENT | PageTableEntryFlags::WRITABLE;
+    unsafe { memory_mapper.map_to(page, frame, flags).unwrap() };
+    assert_eq!(FRAME_ALLOCATOR.lock().free_frames(), free_frames - 4);
+
+    let unmapped_frame = unsafe { memory_mapper.unmap(page).unwrap() };
+    assert_eq!(unmapped_frame, frame);
+    assert_eq!(memory_mapper.translate_address(page.start_address()), None);
+
+    FRAME_ALLOCATOR.lock().deallocate_frame(unmapped_frame);
+    assert_eq!(FRAME_ALLOCATOR.lock().free_frames(), free_frames - 1);
+}
+
+#[test_case]
+fn test_unmap_and_update_flags_fail_for_unmapped_page() {
+    let mut test_fixture = TEST_FIXTURE.lock();
+    let memory_mapper = &mut test_fixture.fixture.as_mut().unwrap().memory_mapper;
+
+    let page = Page::Normal(PageInner::containing_address(VirtualAddress::new(
+        0x5555_0000_0000,
+    )));
+    let flags = PageTableEntryFlags::PRESENT;
+    assert_eq!(
+        unsafe { memory_mapper.unmap(page) },
+        Err(MappingError::PageNotMapped)
+    );
+    assert_eq!(
+        unsafe { memory_mapper.update_flags(page, flags) },
+        Err(MappingError::PageNotMapped)
+    );
+}
+
+#[test_case]
+fn test_update_flags_keeps_the_page_mapped_to_the_same_frame() {
+    let mut test_fixture = TEST_FIXTURE.lock();
+    let memory_mapper = &mut test_fixture.fixture.as_mut().unwrap().memory_mapper;
+
+    let page = Page::Normal(PageInner::containing_address(VirtualAddress::new(
+        0x5555_5555_6000,
+    )));
+    let frame = FRAME_ALLOCATOR.lock().allocate_normal_frame().unwrap();
+    let flags = PageTableEntryFlags::PRESENT | PageTableEntryFlags::WRITABLE;
+    unsafe { memory_mapper.map_to(page, frame, flags).unwrap() };
+
+    let value: *mut u64 = page.start_address().as_mut_ptr();
+    unsafe { value.write_volatile(42) };
+
+    let flags = PageTableEntryFlags::PRESENT | PageTableEntryFlags::NO_CACHE;
+    unsafe { memory_mapper.update_flags(page, flags).unwrap() };
+    assert_eq!(
+        memory_mapper.translate_address(page.start_address()),
+        Some(frame.start_address())
+    );
+    assert_eq!(unsafe { value.read_volatile() }, 42);
+
+    let frame = unsafe { memory_mapper.unmap(page).unwrap() };
+    FRAME_ALLOCATOR.lock().deallocate_frame(frame);
+}
+
+#[test_case]
+fn test_mapped_regions_cover_the_heap() {
+    use rosy::allocation::{heap_size, heap_start};
+
+    let test_fixture = TEST_FIXTURE.lock();
+    let memory_mapper = &test_fixture.fixture.as_ref().unwrap().memory_mapper;
+
+    let heap_start = heap_start() as u64;
+    let heap_end = heap_start + heap_size() as u64;
+    let heap_regions = paging::mapped_regions(memory_mapper).filter(|region| {
+        let start = region.start.start_address().as_u64();
+        heap_start <= start && start < heap_end
+    });
+
+    let mut mapped_size = 0;
+    for region in heap_regions {
+        assert!(matches!(region.frame, PageFrame::Normal(_)));
+        assert!(region.flags.contains(PageTableEntryFlags::WRITABLE));
+        mapped_size += region.size();
+    }
+    assert_eq!(mapped_size, heap_size() as u64);
+}
+
+#[test_case]
+fn test_recursive_mapper_translates_like_offset_mapper() {
+    use rosy::allocation::heap_start;
+
+    let test_fixture = TEST_FIXTURE.lock();
+    let test_fixture = test_fixture.fixture.as_ref().unwrap();
+
+    let stack_variable = 0u64;
+    let addresses = [
+        VGA_BUFFER_START_LOCATION,
+        heap_start() as u64,
+        black_on_white_string as *const () as u64,
+        &stack_variable as *const u64 as u64,
+        test_fixture.physical_memory_offset.as_u64(),
+        // not mapped at all
+        0x5555_0000_0000,
+    ];
+
+    for address in addresses {
+        let virt = VirtualAddress::new(address);
+        assert_eq!(
+            test_fixture.recursive_memory_mapper.translate_address(virt),
+            test_fixture.memory_mapper.translate_address(virt)
+        );
+    }
+}
+
+#[test_case]
+fn test_map_and_unmap_with_offset_mapper() {
+    let mut test_fixture = TEST_FIXTURE.lock();
+    let memory_mapper = &mut test_fixture.fixture.as_mut().unwrap().memory_mapper;
+
+    assert_map_and_unmap_round_trip(memory_mapper, VirtualAddress::new(0x5555_6666_7000));
+}
+
+#[test_case]
+fn test_map_and_unmap_with_recursive_mapper() {
+    let mut test_fixture = TEST_FIXTURE.lock();
+    let memory_mapper = &mut test_fixture
+        .fixture
+        .as_mut()
+        .unwrap()
+        .recursive_memory_mapper;
+
+    assert_map_and_unmap_round_trip(memory_mapper, VirtualAddress::new(0x5555_6666_7000));
+}
+
+/// Map a fresh frame at the (unmapped) address, write through the mapping, and unmap it again.
+/// The level 2 and 1 page tables need to be created and should be freed afterwards.
+fn assert_map_and_unmap_round_trip(memory_mapper: &mut impl Mapper, address: VirtualAddress) {
+    let page = Page::Normal(PageInner::containing_address(address));
+    let free_frames = FRAME_ALLOCATOR.lock().free_frames();
+    let frame = FRAME_ALLOCATOR.lock().allocate_normal_frame().unwrap();
+    let flags = PageTableEntryFlags::PRESENT | PageTableEntryFlags::WRITABLE;
+
+    unsafe { memory_mapper.map_to(page, frame, flags).unwrap() };
+    assert_eq!(
+        memory_mapper.translate_address(address),
+        Some(frame.start_address())
+    );
+
+    let page_ptr: *mut u64 = address.as_mut_ptr();
+    unsafe {
+        page_ptr.write_volatile(0xdead_beef);
+        assert_eq!(page_ptr.read_volatile(), 0xdead_beef);
+    }
+
+    let unmapped_frame = unsafe { memory_mapper.unmap(page).unwrap() };
+    assert_eq!(unmapped_frame, frame);
+    assert_eq!(memory_mapper.translate_address(address), None);
+
+    FRAME_ALLOCATOR.lock().deallocate_frame(unmapped_frame);
+    // Only the level 3 table stays around (if it had to be created).
+    assert!(free_frames - FRAME_ALLOCATOR.lock().free_frames() <= 1);
+}
@@ -2,6 +2,7 @@
 #![no_main]
 #![feature(abi_x86_interrupt)]
 
+use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
 use lazy_static::lazy_static;
 use rosy::{
@@ -10,13 +11,17 @@ use rosy::{
     QemuExitCode,
 };
 
-#[no_mangle]
-pub extern "C" fn _start() -> ! {
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
     serial_println!();
     serial_print!("stack_overflow::stack_overflow...\t");
 
     rosy::gdt::init();
     init_test_idt();
+    // the interrupt stacks of the TSS are allocated using the memory system
+    rosy::memory::init(boot_info);
+    unsafe { rosy::gdt::init_interrupt_stacks() };
 
     // trigger a stack overflow
     stack_overflow();